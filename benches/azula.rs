@@ -1,8 +1,8 @@
 use async_std::task::block_on;
-use criterion::{black_box, criterion_group, criterion_main, Criterion};
 use azula::input::{PortRange, ScanOrder};
 use azula::port_strategy::PortStrategy;
 use azula::scanner::Scanner;
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
 use std::net::IpAddr;
 use std::time::Duration;
 
@@ -36,7 +36,7 @@ fn criterion_benchmark(c: &mut Criterion) {
     let strategy_udp = PortStrategy::pick(&Some(range.clone()), None, ScanOrder::Serial);
 
     let scanner_tcp = Scanner::new(
-        &addrs,
+        addrs.iter().copied().collect(),
         10,
         Duration::from_millis(10),
         1,
@@ -52,7 +52,7 @@ fn criterion_benchmark(c: &mut Criterion) {
     });
 
     let scanner_udp = Scanner::new(
-        &addrs,
+        addrs.iter().copied().collect(),
         10,
         Duration::from_millis(10),
         1,
@@ -77,4 +77,4 @@ fn criterion_benchmark(c: &mut Criterion) {
 }
 
 criterion_group!(benches, criterion_benchmark);
-criterion_main!(benches);
\ No newline at end of file
+criterion_main!(benches);
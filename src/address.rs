@@ -1,4 +1,4 @@
-use std::collections::BTreeSet;
+use std::collections::HashMap;
 use std::fs::{self, File};
 use std::io::{prelude::*, BufReader};
 use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
@@ -8,11 +8,13 @@ use cidr_utils::cidr::IpCidr;
 use hickory_resolver::{config::{NameServerConfig, Protocol, ResolverConfig, ResolverOpts}, Resolver, };
 use log::debug;
 use crate::input::Opts;
+use crate::targets::{IpRange, TargetSet};
 use crate::warning;
 
-/// Parses the string(s) into IP addresses.
+/// Parses the string(s) into the set of IP addresses to scan.
 ///
-/// Goes through all possible IP inputs (files or via argparsing).
+/// Goes through all possible IP inputs (files or via argparsing). CIDRs and
+/// ranges are kept as ranges, they are never expanded to every address.
 ///
 /// ```rust
 /// # use azula::input::Opts;
@@ -24,15 +26,24 @@ use crate::warning;
 /// ```
 ///
 /// Finally, any duplicates are removed to avoid excessive scans.
-pub fn parse_addresses(input: &Opts) -> Vec<IpAddr> {
-    let mut ips: Vec<IpAddr> = Vec::new();
+pub fn parse_addresses(input: &Opts) -> TargetSet {
+    parse_addresses_with_hostnames(input).0
+}
+
+/// Same as `parse_addresses`, but also hands back the hostname each IP was
+/// resolved from, for the targets that were given as hostnames. When
+/// several hostnames resolve to the same IP the first one wins.
+pub fn parse_addresses_with_hostnames(input: &Opts) -> (TargetSet, HashMap<IpAddr, String>) {
+    let mut ranges: Vec<IpRange> = Vec::new();
+    let mut hostnames: HashMap<IpAddr, String> = HashMap::new();
     let mut unresolved_addresses: Vec<&str> = Vec::new();
     let backup_resolver = get_resolver(&input.resolver);
 
     for address in &input.addresses {
-        let parsed_ips = parse_address(address, &backup_resolver);
-        if !parsed_ips.is_empty() {
-            ips.extend(parsed_ips);
+        let parsed_ranges = parse_address(address, &backup_resolver);
+        if !parsed_ranges.is_empty() {
+            record_hostname(address, &parsed_ranges, &mut hostnames);
+            ranges.extend(parsed_ranges);
         } else {
             unresolved_addresses.push(address);
         }
@@ -52,8 +63,8 @@ pub fn parse_addresses(input: &Opts) -> Vec<IpAddr> {
             continue;
         }
 
-        if let Ok(x) = read_ips_from_file(file_path, &backup_resolver) {
-            ips.extend(x);
+        if let Ok(x) = read_ips_from_file(file_path, &backup_resolver, &mut hostnames) {
+            ranges.extend(x);
         } else {
             warning!(
                 format!("Host {file_path:?} could not be resolved."),
@@ -63,13 +74,25 @@ pub fn parse_addresses(input: &Opts) -> Vec<IpAddr> {
         }
     }
 
-    ips.into_iter()
-        .collect::<BTreeSet<_>>()
-        .into_iter()
-        .collect()
+    (ranges.into_iter().collect(), hostnames)
 }
 
-/// Given a string, parse it as a host, IP address, or CIDR.
+/// Remembers `address` as the hostname of `ranges` unless it is an IP, a
+/// CIDR or a range itself.
+fn record_hostname(address: &str, ranges: &[IpRange], hostnames: &mut HashMap<IpAddr, String>) {
+    let address = address.trim();
+    if IpCidr::from_str(address).is_ok() || parse_range(address).is_some() {
+        return;
+    }
+    for range in ranges {
+        hostnames
+            .entry(range.start())
+            .or_insert_with(|| address.to_owned());
+    }
+}
+
+/// Given a string, parse it as a host, IP address, CIDR, or range of IP
+/// addresses like `10.0.0.1-10.0.0.50`.
 ///
 /// This allows us to pass files as hosts or cidr or IPs easily
 /// Call this every time you have a possible IP-or-host.
@@ -80,19 +103,35 @@ pub fn parse_addresses(input: &Opts) -> Vec<IpAddr> {
 /// ```rust
 /// # use azula::address::parse_address;
 /// # use hickory_resolver::Resolver;
-/// let ips = parse_address("127.0.0.1", &Resolver::default().unwrap());
+/// let ranges = parse_address("127.0.0.1", &Resolver::default().unwrap());
 /// ```
-pub fn parse_address(address: &str, resolver: &Resolver) -> Vec<IpAddr> {
+pub fn parse_address(address: &str, resolver: &Resolver) -> Vec<IpRange> {
     IpCidr::from_str(address)
-        .map(|cidr| cidr.iter().map(|c| c.address()).collect())
         .ok()
+        .and_then(|cidr| IpRange::new(cidr.first_address(), cidr.last_address()))
+        .or_else(|| parse_range(address))
+        .map(|range| vec![range])
         .or_else(|| {
             format!("{}:80", &address)
                 .to_socket_addrs()
                 .ok()
-                .map(|mut iter| vec![iter.next().unwrap().ip()])
+                .map(|mut iter| vec![iter.next().unwrap().ip().into()])
+        })
+        .unwrap_or_else(|| {
+            resolve_ips_from_host(address, resolver)
+                .into_iter()
+                .map(IpRange::from)
+                .collect()
         })
-        .unwrap_or_else(|| resolve_ips_from_host(address, resolver))
+}
+
+/// Parses `start-end`, where both ends are IP addresses of the same family.
+fn parse_range(address: &str) -> Option<IpRange> {
+    let (start, end) = address.split_once('-')?;
+    IpRange::new(
+        IpAddr::from_str(start.trim()).ok()?,
+        IpAddr::from_str(end.trim()).ok()?,
+    )
 }
 
 /// Uses DNS to get the IPS associated with host
@@ -163,27 +202,30 @@ fn read_resolver_from_file(path: &str) -> Result<Vec<IpAddr>, std::io::Error> {
 fn read_ips_from_file(
     ips: &std::path::Path,
     backup_resolver: &Resolver,
-) -> Result<Vec<IpAddr>, std::io::Error> {
+    hostnames: &mut HashMap<IpAddr, String>,
+) -> Result<Vec<IpRange>, std::io::Error> {
     let file = File::open(ips)?;
     let reader = BufReader::new(file);
 
-    let mut ips: Vec<IpAddr> = Vec::new();
+    let mut ranges: Vec<IpRange> = Vec::new();
 
     for address_line in reader.lines() {
         if let Ok(address) = address_line {
-            ips.extend(parse_address(&address, backup_resolver));
+            let parsed_ranges = parse_address(&address, backup_resolver);
+            record_hostname(&address, &parsed_ranges, hostnames);
+            ranges.extend(parsed_ranges);
         } else {
             debug!("Line in file is not valid");
         }
     }
 
-    Ok(ips)
+    Ok(ranges)
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{get_resolver, parse_addresses, Opts};
-    use std::net::Ipv4Addr;
+    use super::{get_resolver, parse_addresses, parse_addresses_with_hostnames, Opts};
+    use std::net::{IpAddr, Ipv4Addr};
 
     #[test]
     fn parse_correct_addresses() {
@@ -192,7 +234,7 @@ mod tests {
             ..Default::default()
         };
 
-        let ips = parse_addresses(&opts);
+        let ips: Vec<IpAddr> = parse_addresses(&opts).iter().collect();
 
         assert_eq!(
             ips,
@@ -225,11 +267,26 @@ mod tests {
             ..Default::default()
         };
 
-        let ips = parse_addresses(&opts);
+        let ips: Vec<IpAddr> = parse_addresses(&opts).iter().collect();
 
         assert_eq!(ips, [Ipv4Addr::new(127, 0, 0, 1),]);
     }
 
+    #[test]
+    fn parse_addresses_keeps_hostnames() {
+        let opts = Opts {
+            addresses: vec!["localhost".to_owned(), "10.0.0.1".to_owned()],
+            ..Default::default()
+        };
+
+        let (ips, hostnames) = parse_addresses_with_hostnames(&opts);
+
+        assert_eq!(ips.len(), 2);
+        assert_eq!(hostnames.len(), 1);
+        assert!(hostnames.values().all(|hostname| hostname == "localhost"));
+        assert!(!hostnames.contains_key(&Ipv4Addr::new(10, 0, 0, 1).into()));
+    }
+
     #[test]
     fn parse_incorrect_addresses() {
         let opts = Opts {
@@ -292,6 +349,25 @@ mod tests {
         assert_eq!(ips.len(), 2_048);
     }
 
+    #[test]
+    fn parse_ranges_without_expanding_them() {
+        let opts = Opts {
+            addresses: vec![
+                "10.0.0.0/8".to_owned(),
+                "10.255.255.250-11.0.0.9".to_owned(),
+                "2001:db8::/32".to_owned(),
+                "10.0.0.9-10.0.0.1".to_owned(),
+            ],
+            ..Default::default()
+        };
+
+        let (ips, hostnames) = parse_addresses_with_hostnames(&opts);
+
+        assert_eq!(ips.ranges().len(), 2);
+        assert_eq!(ips.len(), (1 << 24) + 10 + (1 << 96));
+        assert!(hostnames.is_empty());
+    }
+
     #[test]
     fn resolver_default_cloudflare() {
         let opts = Opts::default();
@@ -317,17 +393,3 @@ mod tests {
         assert!(lookup.iter().next().is_some());
     }
 }
-
-
-
-
-
-
-
-
-
-
-
-
-
-
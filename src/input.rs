@@ -1,7 +1,11 @@
-use clap::{Parser, ValueEnum};
+use crate::scanner::{PortState, Protocol, Proxy};
+use clap::parser::ValueSource;
+use clap::{CommandFactory, FromArgMatches, Parser, ValueEnum};
 use serde_derive::Deserialize;
 use std::collections::HashMap;
+use std::ffi::OsString;
 use std::fs;
+use std::net::IpAddr;
 use std::path::PathBuf;
 
 const LOWEST_PORT_NUMBER: u16 = 1;
@@ -27,6 +31,102 @@ pub enum ScriptsRequired {
     Custom,
 }
 
+/// Represents the format used to report the scan results.
+///   - human prints colored progress lines and `ip -> [ports]` summaries.
+///   - json prints a single JSON document with every target once the run is over.
+///   - xml prints an nmap compatible XML report, like `nmap -oX` does.
+#[derive(Deserialize, Debug, ValueEnum, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Human,
+    Json,
+    Xml,
+}
+
+/// Represents the format used to stream events while the scan is running.
+///   - ndjson writes one JSON object per line for every event.
+#[derive(Deserialize, Debug, ValueEnum, Clone, Copy, PartialEq, Eq)]
+pub enum StreamFormat {
+    Ndjson,
+}
+
+/// Represents the nmap style timing templates, from the slowest and
+/// stealthiest to the fastest.
+///   - paranoid waits 5 minutes between probes, one at a time.
+///   - sneaky waits 15 seconds between probes, one at a time.
+///   - polite sends at most 10 probes per second.
+///   - normal uses the regular defaults.
+///   - aggressive uses shorter timeouts, bigger batches and a retry.
+///   - insane uses very short timeouts and the biggest batches.
+#[derive(Deserialize, Debug, ValueEnum, Clone, Copy, PartialEq, Eq)]
+pub enum TimingTemplate {
+    Paranoid,
+    Sneaky,
+    Polite,
+    Normal,
+    Aggressive,
+    Insane,
+}
+
+/// The values a timing template sets, each one named after the `Opts`
+/// field it ends up in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimingValues {
+    pub timeout: u32,
+    pub tries: u8,
+    pub batch_size: u16,
+    pub scan_delay: u32,
+    pub max_rate: Option<u32>,
+}
+
+impl TimingTemplate {
+    pub fn values(self) -> TimingValues {
+        match self {
+            TimingTemplate::Paranoid => TimingValues {
+                timeout: 5_000,
+                tries: 1,
+                batch_size: 1,
+                scan_delay: 300_000,
+                max_rate: None,
+            },
+            TimingTemplate::Sneaky => TimingValues {
+                timeout: 5_000,
+                tries: 1,
+                batch_size: 1,
+                scan_delay: 15_000,
+                max_rate: None,
+            },
+            TimingTemplate::Polite => TimingValues {
+                timeout: 3_000,
+                tries: 1,
+                batch_size: 100,
+                scan_delay: 0,
+                max_rate: Some(10),
+            },
+            TimingTemplate::Normal => TimingValues {
+                timeout: 1_500,
+                tries: 1,
+                batch_size: 4_500,
+                scan_delay: 0,
+                max_rate: None,
+            },
+            TimingTemplate::Aggressive => TimingValues {
+                timeout: 1_000,
+                tries: 2,
+                batch_size: 10_000,
+                scan_delay: 0,
+                max_rate: None,
+            },
+            TimingTemplate::Insane => TimingValues {
+                timeout: 500,
+                tries: 1,
+                batch_size: 65_535,
+                scan_delay: 0,
+                max_rate: None,
+            },
+        }
+    }
+}
+
 /// Represents the range of ports to be scanned.
 #[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct PortRange {
@@ -58,6 +158,41 @@ fn parse_range(input: &str) -> Result<PortRange, String> {
     }
 }
 
+/// A port given with `--ports`. Like with nmap, a `T:` or `U:` prefix picks
+/// the protocol of that port and the ones after it, so `T:22,80,U:53` scans
+/// 22 and 80 over TCP and 53 over UDP. Ports without one are scanned over
+/// every protocol.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PortSpec {
+    pub protocol: Option<Protocol>,
+    pub port: u16,
+}
+
+impl From<u16> for PortSpec {
+    fn from(port: u16) -> Self {
+        Self {
+            protocol: None,
+            port,
+        }
+    }
+}
+
+#[cfg(not(tarpaulin_include))]
+fn parse_port_spec(input: &str) -> Result<PortSpec, String> {
+    let (protocol, port) = match input.split_once(':') {
+        Some((prefix, port)) if prefix.eq_ignore_ascii_case("t") => (Some(Protocol::Tcp), port),
+        Some((prefix, port)) if prefix.eq_ignore_ascii_case("u") => (Some(Protocol::Udp), port),
+        Some(_) => {
+            return Err(String::from(
+                "the protocol prefix must be 'T:' or 'U:'. Example: T:22,80,U:53.",
+            ))
+        }
+        None => (None, input),
+    };
+    let port = port.parse().map_err(|e| format!("{e}"))?;
+    Ok(PortSpec { protocol, port })
+}
+
 #[derive(Parser, Debug, Clone)]
 #[command(
     name = "rustscan",
@@ -69,8 +204,8 @@ fn parse_range(input: &str) -> Result<PortRange, String> {
 pub struct Opts {
     #[arg(short, long, value_delimiter = ',')]
     pub addresses: Vec<String>,
-    #[arg(short, long, value_delimiter = ',')]
-    pub ports: Option<Vec<u16>>,
+    #[arg(short, long, value_delimiter = ',', value_parser = parse_port_spec)]
+    pub ports: Option<Vec<PortSpec>>,
     #[arg(short, long, conflicts_with = "ports", value_parser = parse_range)]
     pub range: Option<PortRange>,
     #[arg(short, long)]
@@ -87,8 +222,24 @@ pub struct Opts {
     pub batch_size: u16,
     #[arg(short, long, default_value = "1500")]
     pub timeout: u32,
+    #[arg(long, default_value = "100")]
+    pub min_rtt_timeout: u32,
+    #[arg(long, default_value = "10000")]
+    pub max_rtt_timeout: u32,
     #[arg(long, default_value = "1")]
     pub tries: u8,
+    #[arg(long)]
+    pub max_rate: Option<u32>,
+    #[arg(long)]
+    pub min_rate: Option<u32>,
+    #[arg(long, default_value = "0")]
+    pub scan_delay: u32,
+    #[arg(long, value_enum, ignore_case = true)]
+    pub timing: Option<TimingTemplate>,
+    #[arg(long)]
+    pub skip_discovery: bool,
+    #[arg(long, value_delimiter = ',', default_value = "80,443,22")]
+    pub discovery_ports: Vec<u16>,
     #[arg(short, long)]
     pub ulimit: Option<u64>,
     #[arg(long, value_enum, ignore_case = true, default_value = "serial")]
@@ -103,12 +254,98 @@ pub struct Opts {
     pub exclude_ports: Option<Vec<u16>>,
     #[arg(long)]
     pub udp: bool,
+    #[arg(long, conflicts_with = "udp")]
+    pub syn: bool,
+    #[arg(
+        long,
+        value_enum,
+        ignore_case = true,
+        value_delimiter = ',',
+        conflicts_with = "udp"
+    )]
+    pub protocols: Vec<Protocol>,
+    #[arg(long)]
+    pub banners: bool,
+    #[arg(long, default_value = "1000")]
+    pub banner_timeout: u32,
+    #[arg(long)]
+    pub service_detection: bool,
+    #[arg(long, default_value = "7", value_parser = clap::value_parser!(u8).range(0..=9))]
+    pub version_intensity: u8,
+    #[arg(long, default_value = "5000")]
+    pub service_timeout: u32,
+    #[arg(long, value_parser)]
+    pub service_probes: Option<PathBuf>,
+    /// Shake hands with TLS services and record their certificates. Full
+    /// handshakes need TLS 1.2 or 1.3, services stuck on older versions or
+    /// cipher suites are only sent hellos, telling which versions they take.
+    #[arg(long)]
+    pub tls: bool,
+    #[arg(long, default_value = "3000")]
+    pub tls_timeout: u32,
+    #[arg(long)]
+    pub http_probe: bool,
+    #[arg(long, default_value = "3000")]
+    pub http_timeout: u32,
+    #[arg(long)]
+    pub source_ip: Option<IpAddr>,
+    #[arg(long)]
+    pub interface: Option<String>,
+    #[arg(long)]
+    pub source_port: Option<u16>,
+    #[arg(long, value_delimiter = ',', conflicts_with_all = ["udp", "syn"])]
+    pub proxy: Vec<Proxy>,
+    #[arg(long, value_enum, ignore_case = true, default_value = "human")]
+    pub output: OutputFormat,
+    #[arg(long, value_parser)]
+    pub output_file: Option<PathBuf>,
+    #[arg(long, value_enum, ignore_case = true)]
+    pub stream: Option<StreamFormat>,
+    #[arg(long, value_enum, ignore_case = true, value_delimiter = ',')]
+    pub report_states: Vec<PortState>,
+    #[arg(long, value_parser)]
+    pub resume_file: Option<PathBuf>,
+    #[arg(long)]
+    pub scripts_on_interrupt: bool,
+    #[arg(long)]
+    pub max_scan_time: Option<u32>,
+    #[arg(long)]
+    pub host_timeout: Option<u32>,
+    /// Ids of the arguments given on the command line, as opposed to the
+    /// ones left to their default value.
+    #[arg(skip)]
+    pub explicit_args: Vec<String>,
 }
 
 #[cfg(not(tarpaulin_include))]
 impl Opts {
     pub fn read() -> Self {
-        let mut opts = Opts::parse();
+        Self::read_from(std::env::args_os())
+    }
+
+    /// Same as `read` but takes the command line arguments instead of
+    /// reading them from the environment.
+    pub fn read_from<I, T>(args: I) -> Self
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString> + Clone,
+    {
+        let matches = Opts::command().get_matches_from(args);
+        let mut opts = Opts::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+        opts.explicit_args = matches
+            .ids()
+            .filter(|id| matches.value_source(id.as_str()) == Some(ValueSource::CommandLine))
+            .map(|id| id.as_str().to_owned())
+            .collect();
+
+        // A protocol prefix carries over to the ports after it.
+        let mut protocol = None;
+        for spec in opts.ports.iter_mut().flatten() {
+            match spec.protocol {
+                Some(_) => protocol = spec.protocol,
+                None => spec.protocol = protocol,
+            }
+        }
 
         if opts.ports.is_none() && opts.range.is_none() {
             opts.range = Some(PortRange {
@@ -120,6 +357,42 @@ impl Opts {
         opts
     }
 
+    /// The protocols to scan, the ones given with `--protocols`, or else the
+    /// ones the ports were prefixed with, or else UDP with `--udp` and TCP
+    /// otherwise.
+    pub fn scan_protocols(&self) -> Vec<Protocol> {
+        if !self.protocols.is_empty() {
+            return self.protocols.clone();
+        }
+        let mut prefixed: Vec<Protocol> = self
+            .ports
+            .iter()
+            .flatten()
+            .filter_map(|spec| spec.protocol)
+            .collect();
+        prefixed.sort_unstable();
+        prefixed.dedup();
+        if !prefixed.is_empty() {
+            prefixed
+        } else if self.udp {
+            vec![Protocol::Udp]
+        } else {
+            vec![Protocol::Tcp]
+        }
+    }
+
+    /// The ports given with `--ports` that are scanned over `protocol`,
+    /// `None` when none were given and the range is scanned instead.
+    pub fn ports_for(&self, protocol: Protocol) -> Option<Vec<u16>> {
+        self.ports.as_ref().map(|specs| {
+            specs
+                .iter()
+                .filter(|spec| spec.protocol.is_none_or(|prefix| prefix == protocol))
+                .map(|spec| spec.port)
+                .collect()
+        })
+    }
+
     /// Reads the command line arguments into an Opts struct and merge
     /// values found within the user configuration file.
     pub fn merge(&mut self, config: &Config) {
@@ -127,6 +400,30 @@ impl Opts {
             self.merge_required(config);
             self.merge_optional(config);
         }
+        self.apply_timing(config);
+    }
+
+    /// Fills in the values of the selected timing template, leaving alone
+    /// the ones given on the command line or in the configuration file.
+    fn apply_timing(&mut self, config: &Config) {
+        let Some(timing) = self.timing else {
+            return;
+        };
+        let values = timing.values();
+
+        macro_rules! apply_timing {
+            ($($field: ident),+) => {
+                $(
+                    let explicit = self.explicit_args.iter().any(|id| id == stringify!($field));
+                    let configured = !self.no_config && config.$field.is_some();
+                    if !explicit && !configured {
+                        self.$field = values.$field;
+                    }
+                )+
+            }
+        }
+
+        apply_timing!(timeout, tries, batch_size, scan_delay, max_rate);
     }
 
     fn merge_required(&mut self, config: &Config) {
@@ -141,8 +438,36 @@ impl Opts {
         }
 
         merge_required!(
-            addresses, greppable, accessible, batch_size, timeout, tries, scan_order, scripts,
-            command, udp
+            addresses,
+            greppable,
+            accessible,
+            batch_size,
+            timeout,
+            scan_delay,
+            skip_discovery,
+            discovery_ports,
+            min_rtt_timeout,
+            max_rtt_timeout,
+            tries,
+            scan_order,
+            scripts,
+            command,
+            udp,
+            syn,
+            protocols,
+            banners,
+            banner_timeout,
+            service_detection,
+            version_intensity,
+            service_timeout,
+            tls,
+            tls_timeout,
+            http_probe,
+            http_timeout,
+            proxy,
+            scripts_on_interrupt,
+            output,
+            report_states
         );
     }
 
@@ -163,10 +488,26 @@ impl Opts {
             for entry in config.ports.as_ref().unwrap().keys() {
                 ports.push(entry.parse().unwrap());
             }
-            self.ports = Some(ports);
+            self.ports = Some(ports.into_iter().map(PortSpec::from).collect());
         }
 
-        merge_optional!(range, resolver, ulimit, exclude_ports);
+        merge_optional!(
+            range,
+            resolver,
+            ulimit,
+            exclude_ports,
+            output_file,
+            stream,
+            max_rate,
+            min_rate,
+            timing,
+            service_probes,
+            source_ip,
+            interface,
+            source_port,
+            max_scan_time,
+            host_timeout
+        );
     }
 }
 
@@ -179,7 +520,15 @@ impl Default for Opts {
             greppable: true,
             batch_size: 0,
             timeout: 0,
+            min_rtt_timeout: 0,
+            max_rtt_timeout: 0,
             tries: 0,
+            max_rate: None,
+            min_rate: None,
+            scan_delay: 0,
+            timing: None,
+            skip_discovery: false,
+            discovery_ports: vec![],
             ulimit: None,
             command: vec![],
             accessible: false,
@@ -191,6 +540,31 @@ impl Default for Opts {
             config_path: None,
             exclude_ports: None,
             udp: false,
+            syn: false,
+            protocols: vec![],
+            banners: false,
+            banner_timeout: 0,
+            service_detection: false,
+            version_intensity: 0,
+            service_timeout: 0,
+            service_probes: None,
+            tls: false,
+            tls_timeout: 0,
+            http_probe: false,
+            http_timeout: 0,
+            source_ip: None,
+            interface: None,
+            source_port: None,
+            proxy: vec![],
+            output: OutputFormat::Human,
+            output_file: None,
+            stream: None,
+            report_states: vec![],
+            resume_file: None,
+            scripts_on_interrupt: false,
+            max_scan_time: None,
+            host_timeout: None,
+            explicit_args: vec![],
         }
     }
 }
@@ -208,7 +582,15 @@ pub struct Config {
     accessible: Option<bool>,
     batch_size: Option<u16>,
     timeout: Option<u32>,
+    min_rtt_timeout: Option<u32>,
+    max_rtt_timeout: Option<u32>,
     tries: Option<u8>,
+    max_rate: Option<u32>,
+    min_rate: Option<u32>,
+    scan_delay: Option<u32>,
+    timing: Option<TimingTemplate>,
+    skip_discovery: Option<bool>,
+    discovery_ports: Option<Vec<u16>>,
     ulimit: Option<u64>,
     resolver: Option<String>,
     scan_order: Option<ScanOrder>,
@@ -216,6 +598,29 @@ pub struct Config {
     scripts: Option<ScriptsRequired>,
     exclude_ports: Option<Vec<u16>>,
     udp: Option<bool>,
+    syn: Option<bool>,
+    protocols: Option<Vec<Protocol>>,
+    banners: Option<bool>,
+    banner_timeout: Option<u32>,
+    service_detection: Option<bool>,
+    version_intensity: Option<u8>,
+    service_timeout: Option<u32>,
+    service_probes: Option<PathBuf>,
+    tls: Option<bool>,
+    tls_timeout: Option<u32>,
+    http_probe: Option<bool>,
+    http_timeout: Option<u32>,
+    source_ip: Option<IpAddr>,
+    interface: Option<String>,
+    source_port: Option<u16>,
+    proxy: Option<Vec<Proxy>>,
+    scripts_on_interrupt: Option<bool>,
+    max_scan_time: Option<u32>,
+    host_timeout: Option<u32>,
+    output: Option<OutputFormat>,
+    output_file: Option<PathBuf>,
+    stream: Option<StreamFormat>,
+    report_states: Option<Vec<PortState>>,
 }
 
 #[cfg(not(tarpaulin_include))]
@@ -233,6 +638,7 @@ impl Config {
     /// scan_order = "Serial"
     /// exclude_ports = [8080, 9090, 80]
     /// udp = false
+    /// output = "Json"
     ///
     pub fn read(custom_config_path: Option<PathBuf>) -> Self {
         let mut content = String::new();
@@ -270,7 +676,10 @@ mod tests {
     use clap::{CommandFactory, Parser};
     use parameterized::parameterized;
 
-    use super::{Config, Opts, PortRange, ScanOrder, ScriptsRequired};
+    use super::{
+        Config, Opts, OutputFormat, PortRange, PortState, Protocol, ScanOrder, ScriptsRequired,
+        StreamFormat, TimingTemplate,
+    };
 
     impl Config {
         fn default() -> Self {
@@ -281,7 +690,15 @@ mod tests {
                 greppable: Some(true),
                 batch_size: Some(25_000),
                 timeout: Some(1_000),
+                min_rtt_timeout: Some(50),
+                max_rtt_timeout: Some(2_000),
                 tries: Some(1),
+                max_rate: None,
+                min_rate: None,
+                scan_delay: None,
+                timing: None,
+                skip_discovery: Some(true),
+                discovery_ports: None,
                 ulimit: None,
                 command: Some(vec!["-A".to_owned()]),
                 accessible: Some(true),
@@ -290,6 +707,29 @@ mod tests {
                 scripts: None,
                 exclude_ports: None,
                 udp: Some(false),
+                syn: Some(false),
+                protocols: Some(vec![Protocol::Tcp, Protocol::Udp]),
+                banners: Some(true),
+                banner_timeout: None,
+                service_detection: Some(true),
+                version_intensity: Some(2),
+                service_timeout: None,
+                service_probes: None,
+                tls: Some(true),
+                tls_timeout: None,
+                http_probe: Some(true),
+                http_timeout: Some(1500),
+                source_ip: None,
+                interface: None,
+                source_port: None,
+                proxy: Some(vec!["socks5://127.0.0.1:1080".parse().unwrap()]),
+                scripts_on_interrupt: Some(true),
+                max_scan_time: None,
+                host_timeout: None,
+                output: Some(OutputFormat::Json),
+                output_file: None,
+                stream: None,
+                report_states: Some(vec![PortState::Closed]),
             }
         }
     }
@@ -344,10 +784,24 @@ mod tests {
         assert_eq!(opts.addresses, config.addresses.unwrap());
         assert_eq!(opts.greppable, config.greppable.unwrap());
         assert_eq!(opts.timeout, config.timeout.unwrap());
+        assert_eq!(opts.min_rtt_timeout, config.min_rtt_timeout.unwrap());
+        assert_eq!(opts.max_rtt_timeout, config.max_rtt_timeout.unwrap());
         assert_eq!(opts.command, config.command.unwrap());
         assert_eq!(opts.accessible, config.accessible.unwrap());
         assert_eq!(opts.scan_order, config.scan_order.unwrap());
         assert_eq!(opts.scripts, ScriptsRequired::Default);
+        assert_eq!(opts.output, OutputFormat::Json);
+        assert_eq!(opts.report_states, vec![PortState::Closed]);
+        assert!(opts.skip_discovery);
+        assert!(opts.banners);
+        assert!(opts.service_detection);
+        assert_eq!(opts.version_intensity, 2);
+        assert!(opts.tls);
+        assert!(opts.http_probe);
+        assert_eq!(opts.http_timeout, 1500);
+        assert_eq!(opts.protocols, [Protocol::Tcp, Protocol::Udp]);
+        assert_eq!(opts.proxy, config.proxy.unwrap());
+        assert!(opts.scripts_on_interrupt);
     }
 
     #[test]
@@ -360,11 +814,104 @@ mod tests {
         });
         config.ulimit = Some(1_000);
         config.resolver = Some("1.1.1.1".to_owned());
+        config.output_file = Some("report.xml".into());
+        config.stream = Some(StreamFormat::Ndjson);
+        config.max_rate = Some(500);
+        config.service_probes = Some("/usr/share/nmap/nmap-service-probes".into());
+        config.source_ip = Some("10.8.0.2".parse().unwrap());
+        config.interface = Some("tun0".to_owned());
+        config.max_scan_time = Some(1_800_000);
 
         opts.merge_optional(&config);
 
         assert_eq!(opts.range, config.range);
         assert_eq!(opts.ulimit, config.ulimit);
         assert_eq!(opts.resolver, config.resolver);
+        assert_eq!(opts.output_file, config.output_file);
+        assert_eq!(opts.stream, config.stream);
+        assert_eq!(opts.max_rate, config.max_rate);
+        assert_eq!(opts.min_rate, None);
+        assert_eq!(opts.service_probes, config.service_probes);
+        assert_eq!(opts.source_ip, config.source_ip);
+        assert_eq!(opts.interface, config.interface);
+        assert_eq!(opts.source_port, None);
+        assert_eq!(opts.max_scan_time, config.max_scan_time);
+        assert_eq!(opts.host_timeout, None);
+    }
+
+    #[test]
+    fn parses_protocol_prefixed_ports() {
+        let opts = Opts::read_from(["azula", "-a", "127.0.0.1", "-p", "T:22,80,U:53,u:161"]);
+
+        assert_eq!(opts.scan_protocols(), [Protocol::Tcp, Protocol::Udp]);
+        assert_eq!(opts.ports_for(Protocol::Tcp), Some(vec![22, 80]));
+        assert_eq!(opts.ports_for(Protocol::Udp), Some(vec![53, 161]));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn unprefixed_ports_go_over_every_protocol() {
+        let opts = Opts::read_from([
+            "azula",
+            "-a",
+            "127.0.0.1",
+            "-p",
+            "53,443",
+            "--protocols",
+            "tcp,udp",
+        ]);
+
+        assert_eq!(opts.scan_protocols(), [Protocol::Tcp, Protocol::Udp]);
+        assert_eq!(opts.ports_for(Protocol::Tcp), Some(vec![53, 443]));
+        assert_eq!(opts.ports_for(Protocol::Udp), Some(vec![53, 443]));
+        assert_eq!(Opts::default().scan_protocols(), [Protocol::Tcp]);
+    }
+
+    #[test]
+    fn timing_template_sets_defaults() {
+        let mut opts = Opts::read_from([
+            "azula",
+            "-a",
+            "127.0.0.1",
+            "--no-config",
+            "--timing",
+            "polite",
+        ]);
+
+        opts.merge(&Config::default());
+
+        assert_eq!(opts.timing, Some(TimingTemplate::Polite));
+        assert_eq!(opts.timeout, 3_000);
+        assert_eq!(opts.batch_size, 100);
+        assert_eq!(opts.max_rate, Some(10));
+    }
+
+    #[test]
+    fn timing_template_leaves_explicit_flags_alone() {
+        let mut opts = Opts::read_from([
+            "azula",
+            "-a",
+            "127.0.0.1",
+            "--no-config",
+            "--timing",
+            "insane",
+            "-t",
+            "2000",
+        ]);
+
+        opts.merge(&Config::default());
+
+        assert_eq!(opts.timeout, 2_000);
+        assert_eq!(opts.batch_size, 65_535);
+    }
+
+    #[test]
+    fn timing_template_leaves_config_values_alone() {
+        let mut opts = Opts::read_from(["azula", "-a", "127.0.0.1", "--timing", "paranoid"]);
+
+        opts.merge(&Config::default());
+
+        assert_eq!(opts.timeout, 1_000);
+        assert_eq!(opts.batch_size, 25_000);
+        assert_eq!(opts.scan_delay, 300_000);
+    }
+}
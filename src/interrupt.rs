@@ -0,0 +1,51 @@
+//! Stops scans gracefully on SIGINT and SIGTERM.
+//!
+//! The first signal only raises a flag, the scanner stops sending new
+//! probes once it sees it and hands back what it found so far. A second
+//! signal ends the process right away, for when finishing the probes in
+//! flight takes too long.
+
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+
+/// Exit status of a process ended by SIGINT, which is also used once a
+/// scan interrupted by a signal is done.
+pub const INTERRUPTED_EXIT_CODE: i32 = 130;
+
+static STOP: OnceLock<Arc<AtomicBool>> = OnceLock::new();
+
+/// Returns the flag raised by the first SIGINT or SIGTERM, see
+/// `Scanner::with_stop_signal`.
+///
+/// Signals are only handled on Unix, elsewhere the flag is never raised
+/// and Ctrl-C ends the process straight away.
+pub fn stop_on_signals() -> io::Result<Arc<AtomicBool>> {
+    let stop = Arc::clone(STOP.get_or_init(Arc::default));
+    #[cfg(unix)]
+    for signal in [libc::SIGINT, libc::SIGTERM] {
+        // SAFETY: the handler only touches an atomic and calls `_exit`,
+        // which are both fine to do from a signal handler.
+        unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = handle as *const () as libc::sighandler_t;
+            libc::sigemptyset(&mut action.sa_mask);
+            action.sa_flags = libc::SA_RESTART;
+            if libc::sigaction(signal, &action, std::ptr::null_mut()) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+    }
+    Ok(stop)
+}
+
+#[cfg(unix)]
+extern "C" fn handle(_signal: libc::c_int) {
+    if let Some(stop) = STOP.get() {
+        if stop.swap(true, Ordering::SeqCst) {
+            // SAFETY: `_exit` skips the atexit handlers and destructors,
+            // which is what makes it safe to call here.
+            unsafe { libc::_exit(INTERRUPTED_EXIT_CODE) };
+        }
+    }
+}
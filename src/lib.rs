@@ -14,4 +14,10 @@ pub mod scripts;
 
 pub mod address;
 
-pub mod generate;
\ No newline at end of file
+pub mod generate;
+
+pub mod output;
+
+pub mod interrupt;
+
+pub mod targets;
\ No newline at end of file
@@ -3,20 +3,27 @@
 #![allow(clippy::doc_markdown, clippy::if_not_else, clippy::non_ascii_literal)]
 
 use azula::benchmark::{Benchmark, NamedTimer};
-use azula::input::{self, Config, Opts, ScriptsRequired};
+use azula::input::{self, Config, Opts, OutputFormat, ScanOrder, ScriptsRequired, StreamFormat};
+use azula::interrupt::{self, INTERRUPTED_EXIT_CODE};
+use azula::output::{json, ndjson::NdjsonWriter, xml};
 use azula::port::PortStrategy;
-use azula::scanner::Scanner;
+#[cfg(target_os = "linux")]
+use azula::scanner::SynScanner;
+use azula::scanner::{
+    Discovery, EventSink, HttpProbe, Protocol, ResumeFile, ScanEvent, ScanReport, Scanner,
+    ScriptOutput, ServiceDetector, ServiceProbes, Source, TlsProbe,
+};
 use azula::scripts::{init_scripts, Script, ScriptFile};
-use azula::{detail, funny_opening, output, warning};
+use azula::{detail, fatal, funny_opening, output, warning};
 
 use colorful::{Color, Colorful};
 use futures::executor::block_on;
-use std::collections::HashMap;
-use std::net::IpAddr;
 use std::string::ToString;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::time::Duration;
 
-use azula::address::parse_addresses;
+use azula::address::parse_addresses_with_hostnames;
 
 extern crate colorful;
 extern crate dirs;
@@ -46,16 +53,38 @@ fn main() {
     let config = Config::read(opts.config_path.clone());
     opts.merge(&config);
 
+    if opts.stream.is_some() && opts.output != OutputFormat::Human && opts.output_file.is_none() {
+        fatal!(
+            "Streaming events and writing a report both need stdout, use --output-file for the report.",
+            opts.accessible
+        );
+        std::process::exit(1);
+    }
+
+    if let (Some(min_rate), Some(max_rate)) = (opts.min_rate, opts.max_rate) {
+        if min_rate > max_rate {
+            fatal!(
+                "--min-rate can't be higher than --max-rate.",
+                opts.accessible
+            );
+            std::process::exit(1);
+        }
+    }
+
+    // Structured formats written to stdout own it, so silence all the human oriented chatter.
+    let greppable = opts.greppable;
+    let structured_stdout =
+        opts.stream.is_some() || (opts.output != OutputFormat::Human && opts.output_file.is_none());
+    if structured_stdout {
+        opts.greppable = true;
+    }
+
     debug!("Main() `opts` arguments are {:?}", opts);
 
     let scripts_to_run: Vec<ScriptFile> = match init_scripts(&opts.scripts) {
         Ok(scripts_to_run) => scripts_to_run,
         Err(e) => {
-            warning!(
-                format!("Initiating scripts failed!\n{e}"),
-                opts.greppable,
-                opts.accessible
-            );
+            fatal!(format!("Initiating scripts failed!\n{e}"), opts.accessible);
             std::process::exit(1);
         }
     };
@@ -66,14 +95,10 @@ fn main() {
         print_opening(&opts);
     }
 
-    let ips: Vec<IpAddr> = parse_addresses(&opts);
+    let (ips, hostnames) = parse_addresses_with_hostnames(&opts);
 
     if ips.is_empty() {
-        warning!(
-            "No IPs could be resolved, aborting scan.",
-            opts.greppable,
-            opts.accessible
-        );
+        fatal!("No IPs could be resolved, aborting scan.", opts.accessible);
         std::process::exit(1);
     }
 
@@ -83,112 +108,413 @@ fn main() {
     #[cfg(not(unix))]
     let batch_size: u16 = AVERAGE_BATCH_SIZE;
 
-    let scanner = Scanner::new(
-        &ips,
+    let event_sink: Option<Arc<dyn EventSink>> = opts.stream.map(|format| match format {
+        StreamFormat::Ndjson => {
+            Arc::new(NdjsonWriter::new(std::io::stdout())) as Arc<dyn EventSink>
+        }
+    });
+    let emit = |event: ScanEvent| {
+        if let Some(sink) = &event_sink {
+            sink.emit(&event);
+        }
+    };
+
+    let resume_file = opts.resume_file.clone().map(|path| {
+        ResumeFile::open(path).unwrap_or_else(|e| {
+            fatal!(
+                format!("Failed to read the resume file: {e}"),
+                opts.accessible
+            );
+            std::process::exit(1);
+        })
+    });
+    // A resumed scan has to go through the ports in the same order as the
+    // interrupted one.
+    let seed = resume_file
+        .as_ref()
+        .map_or_else(rand::random, ResumeFile::seed);
+
+    // Every protocol gets its own port strategy, leaving out the ones whose
+    // ports all went to other protocols.
+    let mut targets = opts.scan_protocols().into_iter().filter_map(|protocol| {
+        let ports = opts.ports_for(protocol);
+        if ports.as_ref().is_some_and(Vec::is_empty) {
+            return None;
+        }
+        Some((
+            protocol,
+            PortStrategy::pick_seeded(&opts.range, ports, opts.scan_order, seed),
+        ))
+    });
+    let Some((protocol, port_strategy)) = targets.next() else {
+        fatal!(
+            "None of the ports are scanned over the selected protocols, aborting scan.",
+            opts.accessible
+        );
+        std::process::exit(1);
+    };
+
+    if !opts.proxy.is_empty() && opts.scan_protocols().contains(&Protocol::Udp) {
+        fatal!(
+            "UDP can't be scanned through proxies, aborting scan.",
+            opts.accessible
+        );
+        std::process::exit(1);
+    }
+    let source = Source::new(opts.source_ip, opts.interface.clone(), opts.source_port)
+        .with_proxies(opts.proxy.clone());
+    // Only the scan probes leave from the fixed source port. Everything
+    // else connects to the ports the scan just connected to, which are
+    // still in TIME_WAIT from that port.
+    let follow_up_source = source.without_port();
+
+    let mut scanner = Scanner::new(
+        ips,
         batch_size,
         Duration::from_millis(opts.timeout.into()),
         opts.tries,
         opts.greppable,
-        PortStrategy::pick(&opts.range, opts.ports, opts.scan_order),
+        port_strategy,
         opts.accessible,
-        opts.exclude_ports.unwrap_or_default(),
-        opts.udp,
-    );
+        opts.exclude_ports.clone().unwrap_or_default(),
+        protocol == Protocol::Udp,
+    )
+    .with_reported_states(&opts.report_states)
+    .with_hostnames(hostnames)
+    .with_rtt_timeout_bounds(
+        Duration::from_millis(opts.min_rtt_timeout.into()),
+        Duration::from_millis(opts.max_rtt_timeout.into()),
+    )
+    .with_rate_limits(opts.min_rate, opts.max_rate)
+    .with_scan_delay(Duration::from_millis(opts.scan_delay.into()))
+    .with_source(source.clone());
+    for (protocol, port_strategy) in targets {
+        scanner = scanner.with_protocol_ports(protocol, port_strategy);
+    }
+    if opts.scan_order == ScanOrder::Random {
+        // Spreads the probes over every target rather than only shuffling
+        // the ports each of them is probed on.
+        scanner = scanner.with_permuted_sockets(seed);
+    }
+    if let Some(sink) = &event_sink {
+        scanner = scanner.with_event_sink(Arc::clone(sink));
+    }
+    if let Some(resume_file) = resume_file {
+        if resume_file.is_resuming() {
+            detail!(
+                format!(
+                    "Resuming the scan saved in {}",
+                    resume_file.path().display()
+                ),
+                opts.greppable,
+                opts.accessible
+            );
+        }
+        scanner = scanner.with_resume_file(resume_file);
+    }
+    #[cfg(target_os = "linux")]
+    if opts.syn {
+        match SynScanner::new(source.clone()) {
+            Ok(syn) => scanner = scanner.with_syn_scan(Arc::new(syn)),
+            Err(e) => {
+                fatal!(
+                    format!("SYN scans need raw sockets, run as root or grant CAP_NET_RAW. {e}"),
+                    opts.accessible
+                );
+                std::process::exit(1);
+            }
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    if opts.syn {
+        fatal!("SYN scans are only supported on Linux.", opts.accessible);
+        std::process::exit(1);
+    }
+    if opts.banners {
+        scanner = scanner.with_banners(Duration::from_millis(opts.banner_timeout.into()));
+    }
+    if opts.service_detection {
+        let probes = match &opts.service_probes {
+            Some(path) => ServiceProbes::load(path),
+            None => Ok(ServiceProbes::bundled()),
+        };
+        match probes {
+            Ok(probes) => {
+                scanner = scanner.with_service_detection(
+                    ServiceDetector::new(
+                        probes,
+                        opts.version_intensity,
+                        Duration::from_millis(opts.service_timeout.into()),
+                    )
+                    .with_source(follow_up_source.clone()),
+                );
+            }
+            Err(e) => {
+                fatal!(
+                    format!("Failed to read the service probes: {e}"),
+                    opts.accessible
+                );
+                std::process::exit(1);
+            }
+        }
+    }
+    if opts.tls {
+        scanner = scanner.with_tls_probe(
+            TlsProbe::new(Duration::from_millis(opts.tls_timeout.into()))
+                .with_source(follow_up_source.clone()),
+        );
+    }
+    if opts.http_probe {
+        scanner = scanner.with_http_probe(
+            HttpProbe::new(Duration::from_millis(opts.http_timeout.into()))
+                .with_source(follow_up_source.clone()),
+        );
+    }
+    if !opts.skip_discovery {
+        scanner = scanner.with_discovery(
+            Discovery::new(
+                opts.discovery_ports.clone(),
+                Duration::from_millis(opts.timeout.into()),
+            )
+            .with_source(follow_up_source),
+        );
+    }
+    let stop = interrupt::stop_on_signals().unwrap_or_else(|e| {
+        debug!("Failed to handle interruptions: {}", e);
+        Arc::new(AtomicBool::new(false))
+    });
+    scanner = scanner.with_stop_signal(Arc::clone(&stop));
+    if let Some(limit) = opts.max_scan_time {
+        scanner = scanner.with_max_scan_time(Duration::from_millis(limit.into()));
+    }
+    if let Some(limit) = opts.host_timeout {
+        scanner = scanner.with_host_timeout(Duration::from_millis(limit.into()));
+    }
     debug!("Scanner finished building: {:?}", scanner);
 
     let mut portscan_bench = NamedTimer::start("Portscan");
-    let scan_result = block_on(scanner.run());
+    let mut scan_result = block_on(scanner.run());
     portscan_bench.end();
     benchmarks.push(portscan_bench);
 
-    let mut ports_per_ip = HashMap::new();
-
-    for socket in scan_result {
-        ports_per_ip
-            .entry(socket.ip())
-            .or_insert_with(Vec::new)
-            .push(socket.port());
+    if scan_result.interrupted {
+        warning!(
+            "The scan was interrupted, the results are partial.",
+            opts.greppable,
+            opts.accessible
+        );
     }
-
-    for ip in ips {
-        if ports_per_ip.contains_key(&ip) {
-            continue;
+    if scan_result.hosts_down > 0 {
+        warning!(
+            format!("{} of the {} targets didn't answer host discovery, so their ports weren't scanned. Use --skip-discovery to scan them anyway.", scan_result.hosts_down, scan_result.targets),
+            opts.greppable,
+            opts.accessible
+        );
+    }
+    if scan_result.hosts_unscanned > 0 {
+        warning!(
+            format!(
+                "Ran out of time before scanning {} of the {} targets, the results are partial.",
+                scan_result.hosts_unscanned, scan_result.targets
+            ),
+            opts.greppable,
+            opts.accessible
+        );
+    }
+    for host in &scan_result.hosts {
+        if host.incomplete {
+            warning!(
+                format!(
+                    "Gave up on {} before all of its ports were scanned, the results are partial.",
+                    host.ip
+                ),
+                opts.greppable,
+                opts.accessible
+            );
         }
+    }
+    if scan_result.open_sockets().is_empty()
+        && !scan_result.interrupted
+        && scan_result.hosts_down < scan_result.targets
+    {
+        // If we got here it means the scan couldn't find any open ports on any of the IPs.
 
-        // If we got here it means the IP was not found within the HashMap, this
-        // means the scan couldn't find any open ports for it.
-
-        let x = format!("Looks like I didn't find any open ports for {:?}. This is usually caused by a high batch size.
+        let x = format!("Looks like I didn't find any open ports. This is usually caused by a high batch size.
         \n*I used {} batch size, consider lowering it with {} or a comfortable number for your system.
         \n Alternatively, increase the timeout if your ping is high. Rustscan -t 2000 for 2000 milliseconds (2s) timeout.\n",
-                        ip,
                         opts.batch_size,
                         "'rustscan -b <batch_size> -a <ip address>'");
         warning!(x, opts.greppable, opts.accessible);
     }
 
+    // Scripts only run on the results of an interrupted scan when asked to,
+    // an interruption while they run stops the ones left.
+    let scripts_stopped =
+        || stop.load(Ordering::SeqCst) && !(scan_result.interrupted && opts.scripts_on_interrupt);
     let mut script_bench = NamedTimer::start("Scripts");
-    for (ip, ports) in &ports_per_ip {
-        let vec_str_ports: Vec<String> = ports.iter().map(ToString::to_string).collect();
-
-        // nmap port style is 80,443. Comma separated with no spaces.
-        let ports_str = vec_str_ports.join(",");
+    for host in &mut scan_result.hosts {
+        let ip = host.ip;
+        let open_ports: Vec<(Protocol, Vec<u16>)> = [Protocol::Tcp, Protocol::Udp]
+            .into_iter()
+            .map(|protocol| (protocol, host.open_ports_over(protocol)))
+            .filter(|(_, ports)| !ports.is_empty())
+            .collect();
+        if open_ports.is_empty() {
+            continue;
+        }
 
         // if option scripts is none, no script will be spawned
-        if opts.greppable || opts.scripts == ScriptsRequired::None {
-            println!("{} -> [{}]", &ip, ports_str);
+        if greppable || opts.scripts == ScriptsRequired::None || scripts_stopped() {
+            if !structured_stdout {
+                println!("{} -> [{}]", &ip, nmap_ports(&open_ports));
+            }
             continue;
         }
         detail!("Starting Script(s)", opts.greppable, opts.accessible);
 
-        // Run all the scripts we found and parsed based on the script config file tags field.
-        for mut script_f in scripts_to_run.clone() {
-            // This part allows us to add commandline arguments to the Script call_format, appending them to the end of the command.
-            if !opts.command.is_empty() {
-                let user_extra_args = &opts.command.join(" ");
-                debug!("Extra args vec {:?}", user_extra_args);
-                if script_f.call_format.is_some() {
-                    let mut call_f = script_f.call_format.unwrap();
-                    call_f.push(' ');
-                    call_f.push_str(user_extra_args);
-                    output!(
-                        format!("Running script {:?} on ip {}\nDepending on the complexity of the script, results may take some time to appear.", call_f, &ip),
-                        opts.greppable,
-                        opts.accessible
-                    );
-                    debug!("Call format {}", call_f);
-                    script_f.call_format = Some(call_f);
+        // Scripts run once per protocol, against the ports open over it.
+        for (protocol, ports) in open_ports {
+            // Run all the scripts we found and parsed based on the script config file tags field.
+            for mut script_f in scripts_to_run.clone() {
+                if scripts_stopped() {
+                    break;
                 }
-            }
-
-            // Building the script with the arguments from the ScriptFile, and ip-ports.
-            let script = Script::build(
-                script_f.path,
-                *ip,
-                ports.clone(),
-                script_f.port,
-                script_f.ports_separator,
-                script_f.tags,
-                script_f.call_format,
-            );
-            match script.run() {
-                Ok(script_result) => {
-                    detail!(script_result.to_string(), opts.greppable, opts.accessible);
-                }
-                Err(e) => {
-                    warning!(&format!("Error {e}"), opts.greppable, opts.accessible);
+                // This part allows us to add commandline arguments to the Script call_format, appending them to the end of the command.
+                if !opts.command.is_empty() {
+                    let user_extra_args = &opts.command.join(" ");
+                    debug!("Extra args vec {:?}", user_extra_args);
+                    if script_f.call_format.is_some() {
+                        let mut call_f = script_f.call_format.unwrap();
+                        call_f.push(' ');
+                        call_f.push_str(user_extra_args);
+                        output!(
+                            format!("Running script {:?} on ip {}\nDepending on the complexity of the script, results may take some time to appear.", call_f, &ip),
+                            opts.greppable,
+                            opts.accessible
+                        );
+                        debug!("Call format {}", call_f);
+                        script_f.call_format = Some(call_f);
+                    }
                 }
+
+                let script_name = script_name(&script_f);
+
+                // Building the script with the arguments from the ScriptFile, and ip-ports.
+                let script = Script::build(
+                    script_f.path,
+                    ip,
+                    ports.clone(),
+                    script_f.port,
+                    script_f.ports_separator,
+                    script_f.tags,
+                    script_f.call_format,
+                )
+                .with_protocol(protocol);
+                let script_output = match script.run() {
+                    Ok(script_result) => {
+                        detail!(&script_result, opts.greppable, opts.accessible);
+                        ScriptOutput {
+                            script: script_name,
+                            protocol,
+                            output: Some(script_result),
+                            error: None,
+                        }
+                    }
+                    Err(e) => {
+                        warning!(&format!("Error {e}"), opts.greppable, opts.accessible);
+                        ScriptOutput {
+                            script: script_name,
+                            protocol,
+                            output: None,
+                            error: Some(e.to_string()),
+                        }
+                    }
+                };
+                emit(ScanEvent::ScriptFinished {
+                    ip,
+                    script: script_output.clone(),
+                });
+                host.scripts.push(script_output);
             }
         }
     }
 
-    // To use the runtime benchmark, run the process as: RUST_LOG=info ./rustscan
     script_bench.end();
     benchmarks.push(script_bench);
+
+    emit(ScanEvent::ScanFinished {
+        hosts: scan_result.targets,
+        open_ports: scan_result.open_sockets().len(),
+        elapsed: scan_result.elapsed,
+        interrupted: stop.load(Ordering::SeqCst),
+    });
+
+    if let Err(e) = write_report(&opts, &scan_result) {
+        fatal!(
+            format!("Failed to write the scan report: {e}"),
+            opts.accessible
+        );
+        std::process::exit(1);
+    }
+
+    // To use the runtime benchmark, run the process as: RUST_LOG=info ./rustscan
     rustscan_bench.end();
     benchmarks.push(rustscan_bench);
     debug!("Benchmarks raw {:?}", benchmarks);
     info!("{}", benchmarks.summary());
+
+    if stop.load(Ordering::SeqCst) {
+        std::process::exit(INTERRUPTED_EXIT_CODE);
+    }
+}
+
+/// Renders the scan result in the structured format picked with `--output`
+/// and writes it to `--output-file`, or to stdout when no file was given.
+fn write_report(opts: &Opts, scan_result: &ScanReport) -> std::io::Result<()> {
+    let document = match opts.output {
+        OutputFormat::Human => return Ok(()),
+        OutputFormat::Json => json::render(scan_result)?,
+        OutputFormat::Xml => {
+            let args: Vec<String> = std::env::args().collect();
+            xml::render(scan_result, &args.join(" "))
+        }
+    };
+
+    if let Some(path) = &opts.output_file {
+        std::fs::write(path, document)
+    } else {
+        println!("{document}");
+        Ok(())
+    }
+}
+
+/// Writes the open ports nmap style, comma separated with no spaces like
+/// `80,443`. When ports are open over both protocols each group gets its
+/// prefix, like `T:80,443,U:53`.
+fn nmap_ports(open_ports: &[(Protocol, Vec<u16>)]) -> String {
+    let prefixed = open_ports.len() > 1;
+    open_ports
+        .iter()
+        .map(|(protocol, ports)| {
+            let ports: Vec<String> = ports.iter().map(ToString::to_string).collect();
+            match (prefixed, protocol) {
+                (false, _) => ports.join(","),
+                (true, Protocol::Tcp) => format!("T:{}", ports.join(",")),
+                (true, Protocol::Udp) => format!("U:{}", ports.join(",")),
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+/// Name used to identify a script in the reports, the path of the script
+/// file when there is one or its call format otherwise.
+fn script_name(script_f: &ScriptFile) -> String {
+    match (&script_f.path, &script_f.call_format) {
+        (Some(path), _) => path.display().to_string(),
+        (None, Some(call_format)) => call_format.clone(),
+        (None, None) => String::from("unknown"),
+    }
 }
 
 /// Prints the opening title of RustScan
@@ -297,7 +623,18 @@ fn infer_batch_size(opts: &Opts, ulimit: u64) -> u16 {
 mod tests {
     #[cfg(unix)]
     use super::{adjust_ulimit_size, infer_batch_size};
-    use super::{print_opening, Opts};
+    use super::{nmap_ports, print_opening, Opts};
+    use azula::scanner::Protocol;
+
+    #[test]
+    fn prefixes_ports_of_both_protocols() {
+        let tcp = (Protocol::Tcp, vec![22, 80]);
+        let udp = (Protocol::Udp, vec![53]);
+
+        assert_eq!(nmap_ports(std::slice::from_ref(&tcp)), "22,80");
+        assert_eq!(nmap_ports(std::slice::from_ref(&udp)), "53");
+        assert_eq!(nmap_ports(&[tcp, udp]), "T:22,80,U:53");
+    }
 
     #[test]
     #[cfg(unix)]
@@ -0,0 +1,88 @@
+use crate::scanner::ScanReport;
+
+/// Renders the whole scan result as a single pretty printed JSON document.
+///
+/// `hosts` holds every target, with a `status` of `down` for the ones that
+/// didn't answer host discovery, unless there were more than
+/// `MAX_LISTED_TARGETS` of them. Then it only holds the ones something was
+/// found about, and `hosts_down` and `hosts_unscanned` count the others,
+/// see `ScanReport`.
+///
+/// ```rust
+/// # use azula::output::json;
+/// # use azula::scanner::ScanReport;
+/// let document = json::render(&ScanReport::default()).unwrap();
+/// assert!(document.contains("\"hosts\": []"));
+/// ```
+pub fn render(result: &ScanReport) -> serde_json::Result<String> {
+    serde_json::to_string_pretty(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::render;
+    use crate::scanner::{
+        HostResult, HostStatus, PortResult, PortState, Protocol, ScanReport, ScriptOutput,
+    };
+    use std::time::Duration;
+
+    #[test]
+    fn renders_hosts_ports_and_scripts() {
+        let mut host = HostResult::new("127.0.0.1".parse().unwrap());
+        host.ports.push(PortResult::new(
+            53,
+            Protocol::Udp,
+            PortState::Open,
+            Duration::from_micros(1_500),
+        ));
+        host.scripts.push(ScriptOutput {
+            script: "nmap".to_owned(),
+            protocol: Protocol::Udp,
+            output: Some("done".to_owned()),
+            error: None,
+        });
+        let mut down = HostResult::new("::2".parse().unwrap());
+        down.status = HostStatus::Down;
+        let result = ScanReport {
+            hosts: vec![host, HostResult::new("::1".parse().unwrap()), down],
+            targets: 3,
+            hosts_down: 1,
+            started_at: 1_700_000_000,
+            elapsed: Duration::from_secs(2),
+            ..ScanReport::default()
+        };
+
+        let document: serde_json::Value = serde_json::from_str(&render(&result).unwrap()).unwrap();
+
+        assert_eq!(document["started_at"], 1_700_000_000);
+        assert_eq!(document["elapsed_ms"], 2_000.0);
+        assert!(document.get("interrupted").is_none());
+        assert_eq!(document["targets"], 3);
+        assert_eq!(document["hosts_down"], 1);
+        assert_eq!(document["hosts"][0]["ip"], "127.0.0.1");
+        assert_eq!(document["hosts"][0]["ports"][0]["port"], 53);
+        assert_eq!(document["hosts"][0]["ports"][0]["protocol"], "udp");
+        assert_eq!(document["hosts"][0]["ports"][0]["state"], "open");
+        assert_eq!(document["hosts"][0]["ports"][0]["rtt_ms"], 1.5);
+        assert_eq!(document["hosts"][0]["scripts"][0]["protocol"], "udp");
+        assert_eq!(document["hosts"][0]["scripts"][0]["output"], "done");
+        assert!(document["hosts"][0]["scripts"][0].get("error").is_none());
+        assert!(document["hosts"][0].get("errors").is_none());
+        assert_eq!(document["hosts"][1]["ip"], "::1");
+        assert_eq!(document["hosts"][1]["ports"], serde_json::json!([]));
+        assert_eq!(document["hosts"][1]["status"], "up");
+        assert_eq!(document["hosts"][2]["ip"], "::2");
+        assert_eq!(document["hosts"][2]["status"], "down");
+
+        let sparse = ScanReport {
+            hosts: Vec::new(),
+            targets: 1 << 64,
+            hosts_unscanned: 1 << 63,
+            ..result
+        };
+        let document: serde_json::Value = serde_json::from_str(&render(&sparse).unwrap()).unwrap();
+        assert_eq!(document["hosts"], serde_json::json!([]));
+        assert_eq!(document["targets"], 18_446_744_073_709_551_616.0);
+        assert_eq!(document["hosts_unscanned"], 9_223_372_036_854_775_808.0);
+    }
+}
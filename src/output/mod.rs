@@ -0,0 +1,5 @@
+//! Writers that turn a `ScanReport`, or the events of a running scan, into
+//! machine readable reports.
+pub mod json;
+pub mod ndjson;
+pub mod xml;
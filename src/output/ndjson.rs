@@ -0,0 +1,114 @@
+use crate::scanner::{EventSink, ScanEvent};
+use log::debug;
+use std::fmt;
+use std::io::Write;
+use std::sync::Mutex;
+
+/// Streams scan events as newline delimited JSON, one event per line,
+/// flushing after every event so consumers see them as they happen.
+///
+/// ```rust
+/// # use azula::output::ndjson::NdjsonWriter;
+/// # use azula::scanner::{EventSink, ScanEvent};
+/// let writer = NdjsonWriter::new(std::io::stdout());
+/// writer.emit(&ScanEvent::HostStarted { ip: "127.0.0.1".parse().unwrap() });
+/// ```
+pub struct NdjsonWriter<W> {
+    writer: Mutex<W>,
+}
+
+impl<W: Write + Send> NdjsonWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer: Mutex::new(writer),
+        }
+    }
+
+    /// Gives back the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.writer
+            .into_inner()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+}
+
+impl<W> fmt::Debug for NdjsonWriter<W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NdjsonWriter").finish_non_exhaustive()
+    }
+}
+
+impl<W: Write + Send> EventSink for NdjsonWriter<W> {
+    fn emit(&self, event: &ScanEvent) {
+        let mut writer = self
+            .writer
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        let written = serde_json::to_writer(&mut *writer, event)
+            .map_err(std::io::Error::from)
+            .and_then(|()| writer.write_all(b"\n"))
+            .and_then(|()| writer.flush());
+
+        if let Err(e) = written {
+            debug!("Failed to write event {:?}: {}", event, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::NdjsonWriter;
+    use crate::scanner::{EventSink, PortResult, PortState, Protocol, ScanEvent};
+    use std::time::Duration;
+
+    #[test]
+    fn writes_one_event_per_line() {
+        let ip = "10.0.0.1".parse().unwrap();
+        let writer = NdjsonWriter::new(Vec::new());
+        writer.emit(&ScanEvent::HostStarted { ip });
+        writer.emit(&ScanEvent::PortOpen {
+            ip,
+            port: PortResult::new(
+                443,
+                Protocol::Tcp,
+                PortState::Open,
+                Duration::from_millis(2),
+            ),
+        });
+        writer.emit(&ScanEvent::PortClosed {
+            ip,
+            port: PortResult::new(
+                444,
+                Protocol::Tcp,
+                PortState::Closed,
+                Duration::from_millis(1),
+            ),
+        });
+        writer.emit(&ScanEvent::ScanFinished {
+            hosts: 1,
+            open_ports: 1,
+            elapsed: Duration::from_secs(1),
+            interrupted: false,
+        });
+
+        let output = String::from_utf8(writer.into_inner()).unwrap();
+        let lines: Vec<serde_json::Value> = output
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0]["event"], "host_started");
+        assert_eq!(lines[0]["ip"], "10.0.0.1");
+        assert_eq!(lines[1]["event"], "port_open");
+        assert_eq!(lines[1]["port"], 443);
+        assert_eq!(lines[1]["protocol"], "tcp");
+        assert_eq!(lines[1]["state"], "open");
+        assert_eq!(lines[1]["rtt_ms"], 2.0);
+        assert_eq!(lines[2]["event"], "port_closed");
+        assert_eq!(lines[2]["port"], 444);
+        assert_eq!(lines[2]["state"], "closed");
+        assert_eq!(lines[3]["event"], "scan_finished");
+        assert_eq!(lines[3]["elapsed_ms"], 1_000.0);
+    }
+}
@@ -0,0 +1,451 @@
+use crate::scanner::{
+    HostResult, HostStatus, HttpInfo, PortResult, PortState, Protocol, ScanReport, ScanType,
+    Service, TlsInfo,
+};
+use std::fmt::Write;
+use std::net::IpAddr;
+
+/// Version of the nmap XML format the report follows.
+const XML_OUTPUT_VERSION: &str = "1.05";
+
+/// Renders the scan result as an nmap compatible `<nmaprun>` XML document,
+/// the same format `nmap -oX` writes, so it can be fed to the tools that
+/// consume nmap reports.
+///
+/// `args` ends up in the `args` attribute of `<nmaprun>` and is meant to
+/// hold the command line the scan was started with.
+///
+/// There is a `<host>` for every host of the report that is up, which is
+/// every target that didn't turn out down unless there were more than
+/// `MAX_LISTED_TARGETS` of them, see `ScanReport`. The run stats count
+/// every target either way.
+///
+/// ```rust
+/// # use azula::output::xml;
+/// # use azula::scanner::ScanReport;
+/// let document = xml::render(&ScanReport::default(), "azula -a 127.0.0.1");
+/// assert!(document.contains("<nmaprun scanner=\"azula\""));
+/// ```
+pub fn render(result: &ScanReport, args: &str) -> String {
+    let finished_at = result.started_at + result.elapsed.as_secs();
+    let hosts_up = result.targets.saturating_sub(result.hosts_down);
+    let mut xml = String::new();
+
+    xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str("<!DOCTYPE nmaprun>\n");
+    let _ = writeln!(
+        xml,
+        "<nmaprun scanner=\"azula\" args=\"{}\" start=\"{}\" version=\"{}\" xmloutputversion=\"{}\">",
+        escape(args),
+        result.started_at,
+        env!("CARGO_PKG_VERSION"),
+        XML_OUTPUT_VERSION
+    );
+    for scan in &result.scans {
+        let _ = writeln!(
+            xml,
+            "<scaninfo type=\"{}\" protocol=\"{}\" numservices=\"{}\" services=\"{}\"/>",
+            scan_type_name(scan.scan_type),
+            protocol_name(scan.protocol),
+            scan.ports,
+            scan.services
+        );
+    }
+
+    // Like nmap, hosts found down are only counted in the run stats.
+    for host in result
+        .hosts
+        .iter()
+        .filter(|host| host.status == HostStatus::Up)
+    {
+        render_host(&mut xml, result, host, finished_at);
+    }
+
+    xml.push_str("<runstats>\n");
+    let _ = writeln!(
+        xml,
+        "<finished time=\"{}\" elapsed=\"{:.2}\" summary=\"Azula done: {} IP address{} ({} host{} up) scanned in {:.2} seconds\" {}/>",
+        finished_at,
+        result.elapsed.as_secs_f64(),
+        result.targets,
+        if result.targets == 1 { "" } else { "es" },
+        hosts_up,
+        if hosts_up == 1 { "" } else { "s" },
+        result.elapsed.as_secs_f64(),
+        if result.interrupted {
+            "exit=\"error\" errormsg=\"Interrupted, the results are partial\""
+        } else {
+            "exit=\"success\""
+        }
+    );
+    let _ = writeln!(
+        xml,
+        "<hosts up=\"{}\" down=\"{}\" total=\"{}\"/>",
+        hosts_up, result.hosts_down, result.targets
+    );
+    xml.push_str("</runstats>\n");
+    xml.push_str("</nmaprun>\n");
+
+    xml
+}
+
+fn render_host(xml: &mut String, result: &ScanReport, host: &HostResult, finished_at: u64) {
+    let _ = writeln!(
+        xml,
+        "<host starttime=\"{}\" endtime=\"{finished_at}\"{}>",
+        result.started_at,
+        if host.incomplete {
+            " timedout=\"true\""
+        } else {
+            ""
+        }
+    );
+    // Discovery doesn't keep track of which ping got an answer, so report
+    // the host the way nmap does when run with -Pn.
+    xml.push_str("<status state=\"up\" reason=\"user-set\" reason_ttl=\"0\"/>\n");
+    let _ = writeln!(
+        xml,
+        "<address addr=\"{}\" addrtype=\"{}\"/>",
+        host.ip,
+        match host.ip {
+            IpAddr::V4(_) => "ipv4",
+            IpAddr::V6(_) => "ipv6",
+        }
+    );
+    xml.push_str("<hostnames>\n");
+    if let Some(hostname) = &host.hostname {
+        let _ = writeln!(
+            xml,
+            "<hostname name=\"{}\" type=\"user\"/>",
+            escape(hostname)
+        );
+    }
+    xml.push_str("</hostnames>\n");
+
+    xml.push_str("<ports>");
+    for port in &host.ports {
+        render_port(xml, port, scan_type(result, port.protocol));
+    }
+    xml.push_str("</ports>\n");
+
+    if !host.scripts.is_empty() {
+        xml.push_str("<hostscript>");
+        for script in &host.scripts {
+            let output = script
+                .output
+                .as_deref()
+                .or(script.error.as_deref())
+                .unwrap_or_default();
+            let _ = write!(
+                xml,
+                "<script id=\"{}\" output=\"{}\"/>",
+                escape(&script.script),
+                escape(output)
+            );
+        }
+        xml.push_str("</hostscript>\n");
+    }
+
+    xml.push_str("</host>\n");
+}
+
+fn render_port(xml: &mut String, port: &PortResult, scan_type: ScanType) {
+    let _ = write!(
+        xml,
+        "<port protocol=\"{}\" portid=\"{}\"><state state=\"{}\" reason=\"{}\" reason_ttl=\"0\"/>",
+        protocol_name(port.protocol),
+        port.port,
+        port.state.as_str(),
+        reason(port, scan_type)
+    );
+    if let Some(service) = &port.service {
+        render_service(xml, service);
+    }
+    // nmap reports banners through its banner script, so do the same.
+    if let Some(banner) = &port.banner {
+        let _ = write!(xml, "<script id=\"banner\" output=\"{}\"/>", escape(banner));
+    }
+    // Same for certificates and the ssl-cert script.
+    if let Some(tls) = &port.tls {
+        render_tls(xml, tls);
+    }
+    // And http-title with http-server-header.
+    if let Some(http) = &port.http {
+        render_http(xml, http);
+    }
+    xml.push_str("</port>");
+}
+
+fn render_service(xml: &mut String, service: &Service) {
+    let _ = write!(xml, "<service name=\"{}\"", escape(&service.name));
+    let attributes = [
+        ("product", &service.product),
+        ("version", &service.version),
+        ("extrainfo", &service.extra_info),
+        ("hostname", &service.hostname),
+        ("ostype", &service.os_type),
+        ("devicetype", &service.device_type),
+    ];
+    for (attribute, value) in attributes {
+        if let Some(value) = value {
+            let _ = write!(xml, " {attribute}=\"{}\"", escape(value));
+        }
+    }
+    xml.push_str(" method=\"probed\" conf=\"10\">");
+    for cpe in &service.cpe {
+        let _ = write!(xml, "<cpe>{}</cpe>", escape(cpe));
+    }
+    xml.push_str("</service>");
+}
+
+fn render_tls(xml: &mut String, tls: &TlsInfo) {
+    let mut output = format!("Subject: {}\n", tls.subject);
+    if !tls.sans.is_empty() {
+        let _ = writeln!(output, "Subject Alternative Name: {}", tls.sans.join(", "));
+    }
+    let _ = write!(
+        output,
+        "Issuer: {}\nNot valid before: {}\nNot valid after:  {}",
+        tls.issuer, tls.not_before, tls.not_after
+    );
+    let _ = write!(
+        xml,
+        "<script id=\"ssl-cert\" output=\"{}\"/>",
+        escape(&output)
+    );
+}
+
+fn render_http(xml: &mut String, http: &HttpInfo) {
+    let title = match (&http.location, &http.title) {
+        (Some(location), _) => format!("Did not follow redirect to {location}"),
+        (None, Some(title)) => title.clone(),
+        (None, None) => "Site doesn't have a title.".to_owned(),
+    };
+    let _ = write!(
+        xml,
+        "<script id=\"http-title\" output=\"{}\"/>",
+        escape(&title)
+    );
+    if let Some(server) = &http.server {
+        let _ = write!(
+            xml,
+            "<script id=\"http-server-header\" output=\"{}\"/>",
+            escape(server)
+        );
+    }
+}
+
+/// How the ports of `protocol` were scanned, taken to be with full
+/// connections or UDP probes when the report doesn't say.
+fn scan_type(result: &ScanReport, protocol: Protocol) -> ScanType {
+    result
+        .scans
+        .iter()
+        .find(|scan| scan.protocol == protocol)
+        .map_or(
+            match protocol {
+                Protocol::Tcp => ScanType::Connect,
+                Protocol::Udp => ScanType::Udp,
+            },
+            |scan| scan.scan_type,
+        )
+}
+
+fn scan_type_name(scan_type: ScanType) -> &'static str {
+    match scan_type {
+        ScanType::Connect => "connect",
+        ScanType::Syn => "syn",
+        ScanType::Udp => "udp",
+    }
+}
+
+fn protocol_name(protocol: Protocol) -> &'static str {
+    match protocol {
+        Protocol::Tcp => "tcp",
+        Protocol::Udp => "udp",
+    }
+}
+
+/// The nmap reason string explaining why a port ended up in its state,
+/// which for TCP depends on whether the connection was made in full or
+/// half-open.
+fn reason(port: &PortResult, scan_type: ScanType) -> &'static str {
+    match (port.protocol, port.state) {
+        (Protocol::Tcp, PortState::Open) => "syn-ack",
+        (Protocol::Tcp, PortState::Closed) if scan_type == ScanType::Syn => "reset",
+        (Protocol::Tcp, PortState::Closed) => "conn-refused",
+        (Protocol::Udp, PortState::Open) => "udp-response",
+        (Protocol::Udp, PortState::Closed) => "port-unreach",
+        (Protocol::Tcp, PortState::Filtered) | (_, PortState::OpenFiltered) => "no-response",
+        (Protocol::Udp, PortState::Filtered) => "host-unreach",
+    }
+}
+
+/// Escapes a value so it can be placed inside an XML attribute. Control
+/// characters that XML 1.0 does not allow are dropped.
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '\n' => escaped.push_str("&#xa;"),
+            '\r' => escaped.push_str("&#xd;"),
+            '\t' => escaped.push_str("&#x9;"),
+            c if c.is_control() => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{escape, render};
+    use crate::scanner::{
+        HostResult, HostStatus, HttpInfo, PortResult, PortState, Protocol, ScanInfo, ScanReport,
+        ScanType, ScriptOutput, Service, TlsInfo,
+    };
+    use std::time::Duration;
+
+    #[test]
+    fn renders_nmaprun_document() {
+        let mut host = HostResult::new("192.168.0.1".parse().unwrap());
+        host.hostname = Some("router.lan".to_owned());
+        let mut ssh = PortResult::new(22, Protocol::Tcp, PortState::Open, Duration::from_millis(1));
+        ssh.banner = Some("SSH-2.0-OpenSSH_9.6".to_owned());
+        ssh.service = Some(Service {
+            name: "ssh".to_owned(),
+            product: Some("OpenSSH".to_owned()),
+            version: Some("9.6".to_owned()),
+            extra_info: Some("protocol 2.0".to_owned()),
+            cpe: vec!["cpe:/a:openbsd:openssh:9.6".to_owned()],
+            ..Service::default()
+        });
+        host.ports.push(ssh);
+        let mut https = PortResult::new(
+            443,
+            Protocol::Tcp,
+            PortState::Open,
+            Duration::from_millis(1),
+        );
+        https.tls = Some(TlsInfo {
+            version: "TLSv1.3".to_owned(),
+            cipher: "TLS13_AES_256_GCM_SHA384".to_owned(),
+            subject: "CN=router.lan".to_owned(),
+            issuer: "CN=Router CA".to_owned(),
+            sans: vec!["DNS:router.lan".to_owned(), "IP:192.168.0.1".to_owned()],
+            not_before: "2025-01-01T00:00:00Z".to_owned(),
+            not_after: "2026-01-01T00:00:00Z".to_owned(),
+            handshake_error: None,
+            versions: Vec::new(),
+        });
+        host.ports.push(https);
+        let mut http =
+            PortResult::new(80, Protocol::Tcp, PortState::Open, Duration::from_millis(1));
+        http.http = Some(HttpInfo {
+            tls: false,
+            status: 301,
+            server: Some("nginx".to_owned()),
+            title: Some("301 Moved Permanently".to_owned()),
+            location: Some("https://router.lan/".to_owned()),
+            content_length: 162,
+        });
+        host.ports.push(http);
+        host.ports.push(PortResult::new(
+            23,
+            Protocol::Tcp,
+            PortState::Closed,
+            Duration::from_millis(1),
+        ));
+        host.ports.push(PortResult::new(
+            161,
+            Protocol::Udp,
+            PortState::Open,
+            Duration::from_millis(1),
+        ));
+        host.ports.push(PortResult::new(
+            162,
+            Protocol::Udp,
+            PortState::OpenFiltered,
+            Duration::from_millis(1),
+        ));
+        host.scripts.push(ScriptOutput {
+            script: "nmap".to_owned(),
+            protocol: Protocol::Tcp,
+            output: Some("22/tcp open ssh\n".to_owned()),
+            error: None,
+        });
+        let mut down = HostResult::new("192.168.0.2".parse().unwrap());
+        down.status = HostStatus::Down;
+        let mut incomplete = HostResult::new("::1".parse().unwrap());
+        incomplete.incomplete = true;
+        let result = ScanReport {
+            scans: vec![
+                ScanInfo::new(ScanType::Connect, Protocol::Tcp, &[443, 80, 22, 23, 21]),
+                ScanInfo::new(ScanType::Udp, Protocol::Udp, &[161, 162]),
+            ],
+            hosts: vec![host, down, incomplete],
+            targets: 3,
+            hosts_down: 1,
+            started_at: 1_700_000_000,
+            elapsed: Duration::from_secs(3),
+            ..ScanReport::default()
+        };
+
+        let document = render(&result, "azula -a 192.168.0.1 --output xml");
+
+        assert!(document
+            .starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<!DOCTYPE nmaprun>\n"));
+        assert!(
+            document.contains("args=\"azula -a 192.168.0.1 --output xml\" start=\"1700000000\"")
+        );
+        assert!(document.contains("<scaninfo type=\"connect\" protocol=\"tcp\" numservices=\"5\" services=\"21-23,80,443\"/>\n<scaninfo type=\"udp\" protocol=\"udp\" numservices=\"2\" services=\"161-162\"/>"));
+        assert!(document.contains("<address addr=\"192.168.0.1\" addrtype=\"ipv4\"/>"));
+        assert!(document.contains("<address addr=\"::1\" addrtype=\"ipv6\"/>"));
+        assert!(!document.contains("192.168.0.2"));
+        assert_eq!(document.matches(" timedout=\"true\">").count(), 1);
+        assert!(document.contains("<port protocol=\"tcp\" portid=\"22\"><state state=\"open\" reason=\"syn-ack\" reason_ttl=\"0\"/><service name=\"ssh\" product=\"OpenSSH\" version=\"9.6\" extrainfo=\"protocol 2.0\" method=\"probed\" conf=\"10\"><cpe>cpe:/a:openbsd:openssh:9.6</cpe></service><script id=\"banner\" output=\"SSH-2.0-OpenSSH_9.6\"/></port>"));
+        assert!(document
+            .contains("<hostnames>\n<hostname name=\"router.lan\" type=\"user\"/>\n</hostnames>"));
+        assert!(document.contains("<port protocol=\"tcp\" portid=\"443\"><state state=\"open\" reason=\"syn-ack\" reason_ttl=\"0\"/><script id=\"ssl-cert\" output=\"Subject: CN=router.lan&#xa;Subject Alternative Name: DNS:router.lan, IP:192.168.0.1&#xa;Issuer: CN=Router CA&#xa;Not valid before: 2025-01-01T00:00:00Z&#xa;Not valid after:  2026-01-01T00:00:00Z\"/></port>"));
+        assert!(document.contains("<port protocol=\"tcp\" portid=\"80\"><state state=\"open\" reason=\"syn-ack\" reason_ttl=\"0\"/><script id=\"http-title\" output=\"Did not follow redirect to https://router.lan/\"/><script id=\"http-server-header\" output=\"nginx\"/></port>"));
+        assert!(document.contains("<port protocol=\"tcp\" portid=\"23\"><state state=\"closed\" reason=\"conn-refused\" reason_ttl=\"0\"/></port>"));
+        assert!(document.contains("<port protocol=\"udp\" portid=\"161\"><state state=\"open\" reason=\"udp-response\" reason_ttl=\"0\"/></port>"));
+        assert!(document.contains("<port protocol=\"udp\" portid=\"162\"><state state=\"open|filtered\" reason=\"no-response\" reason_ttl=\"0\"/></port>"));
+        assert!(document.contains("<script id=\"nmap\" output=\"22/tcp open ssh&#xa;\"/>"));
+        assert!(document.contains("<finished time=\"1700000003\" elapsed=\"3.00\""));
+        assert!(document.contains("<hosts up=\"2\" down=\"1\" total=\"3\"/>"));
+        assert!(document.contains("exit=\"success\""));
+        assert!(document.ends_with("</nmaprun>\n"));
+
+        let syn = ScanReport {
+            scans: vec![ScanInfo::new(ScanType::Syn, Protocol::Tcp, &[22, 23])],
+            ..result.clone()
+        };
+        let document = render(&syn, "azula --syn");
+        assert!(document.contains(
+            "<scaninfo type=\"syn\" protocol=\"tcp\" numservices=\"2\" services=\"22-23\"/>"
+        ));
+        assert!(document.contains("<port protocol=\"tcp\" portid=\"22\"><state state=\"open\" reason=\"syn-ack\" reason_ttl=\"0\"/>"));
+        assert!(document.contains("<port protocol=\"tcp\" portid=\"23\"><state state=\"closed\" reason=\"reset\" reason_ttl=\"0\"/></port>"));
+
+        let interrupted = ScanReport {
+            interrupted: true,
+            ..result
+        };
+        assert!(render(&interrupted, "azula")
+            .contains("exit=\"error\" errormsg=\"Interrupted, the results are partial\""));
+    }
+
+    #[test]
+    fn escapes_attribute_values() {
+        assert_eq!(
+            escape("<a href=\"x\">Tom & 'Jerry'</a>\u{1b}"),
+            "&lt;a href=&quot;x&quot;&gt;Tom &amp; &apos;Jerry&apos;&lt;/a&gt;"
+        );
+    }
+}
@@ -1,7 +1,9 @@
 mod range;
 use crate::input::{PortRange, ScanOrder};
+use rand::rngs::StdRng;
 use rand::seq::SliceRandom;
-use rand::thread_rng;
+use rand::{thread_rng, Rng, SeedableRng};
+pub(crate) use range::Permutation;
 use range::RangeIterator;
 
 /// Represents options of port scanning.
@@ -17,6 +19,17 @@ pub enum PortStrategy {
 
 impl PortStrategy {
     pub fn pick(range: &Option<PortRange>, ports: Option<Vec<u16>>, order: ScanOrder) -> Self {
+        Self::pick_seeded(range, ports, order, thread_rng().gen())
+    }
+
+    /// Same as `pick`, but random orders are drawn from `seed` so the same
+    /// seed always gives the same order.
+    pub fn pick_seeded(
+        range: &Option<PortRange>,
+        ports: Option<Vec<u16>>,
+        order: ScanOrder,
+        seed: u64,
+    ) -> Self {
         match order {
             ScanOrder::Serial if ports.is_none() => {
                 let range = range.as_ref().unwrap();
@@ -30,11 +43,12 @@ impl PortStrategy {
                 PortStrategy::Random(RandomRange {
                     start: range.start,
                     end: range.end,
+                    seed,
                 })
             }
             ScanOrder::Serial => PortStrategy::Manual(ports.unwrap()),
             ScanOrder::Random => {
-                let mut rng = thread_rng();
+                let mut rng = StdRng::seed_from_u64(seed);
                 let mut ports = ports.unwrap();
                 ports.shuffle(&mut rng);
                 PortStrategy::Manual(ports)
@@ -77,6 +91,7 @@ impl RangeOrder for SerialRange {
 pub struct RandomRange {
     start: u16,
     end: u16,
+    seed: u64,
 }
 
 impl RangeOrder for RandomRange {
@@ -90,7 +105,8 @@ impl RangeOrder for RandomRange {
     // port numbers close to each other are pretty slim due to the way the
     // algorithm works.
     fn generate(&self) -> Vec<u16> {
-        RangeIterator::new(self.start.into(), self.end.into()).collect()
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        RangeIterator::new(self.start.into(), self.end.into(), &mut rng).collect()
     }
 }
 
@@ -136,4 +152,18 @@ mod tests {
         result.sort_unstable();
         assert_eq!(expected_range, result);
     }
+
+    #[test]
+    fn random_strategy_follows_its_seed() {
+        let range = Some(PortRange { start: 1, end: 100 });
+        let pick = |seed| PortStrategy::pick_seeded(&range, None, ScanOrder::Random, seed).order();
+        let shuffle = |seed| {
+            PortStrategy::pick_seeded(&None, Some((1..10).collect()), ScanOrder::Random, seed)
+                .order()
+        };
+
+        assert_eq!(pick(7), pick(7));
+        assert_ne!(pick(7), pick(8));
+        assert_eq!(shuffle(7), shuffle(7));
+    }
 }
\ No newline at end of file
@@ -3,12 +3,8 @@ use rand::Rng;
 use std::convert::TryInto;
 
 pub struct RangeIterator {
-    active: bool,
-    normalized_end: u32,
-    normalized_first_pick: u32,
-    normalized_pick: u32,
     actual_start: u32,
-    step: u32,
+    picks: Permutation,
 }
 
 /// An iterator that follows the `Linear Congruential Generator` algorithm.
@@ -21,28 +17,85 @@ impl RangeIterator {
     ///
     /// For example, the range `1000-2500` will be normalized to `0-1500`
     /// before going through the algorithm.
-    pub fn new(start: u32, end: u32) -> Self {
-        let normalized_end = end - start + 1;
-        let step = pick_random_coprime(normalized_end);
+    ///
+    /// The step and the first pick are drawn from `rng`.
+    pub fn new<R: Rng>(start: u32, end: u32, rng: &mut R) -> Self {
+        Self {
+            actual_start: start,
+            picks: Permutation::new(u128::from(end - start + 1), rng),
+        }
+    }
+}
+
+impl Iterator for RangeIterator {
+    type Item = u16;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.picks.next().map(|pick| {
+            (u128::from(self.actual_start) + pick)
+                .try_into()
+                .expect("Could not convert u128 to u16")
+        })
+    }
+}
+
+/// Goes through every number of `0..size` once, in a random order, without
+/// storing them. This is the algorithm behind `RangeIterator`, working on
+/// numbers big enough to index every IP and port pair of a scan.
+pub struct Permutation {
+    active: bool,
+    normalized_end: u128,
+    normalized_first_pick: u128,
+    normalized_pick: u128,
+    step: u128,
+}
+
+impl Permutation {
+    /// The step and the first pick are drawn from `rng`.
+    pub fn new<R: Rng>(size: u128, rng: &mut R) -> Self {
+        if size == 0 {
+            return Self {
+                active: false,
+                normalized_end: 0,
+                normalized_first_pick: 0,
+                normalized_pick: 0,
+                step: 0,
+            };
+        }
+        let step = pick_random_coprime(size, rng);
 
         // Randomly choose a number within the range to be the first
         // and assign it as a pick.
-        let mut rng = rand::thread_rng();
-        let normalized_first_pick = rng.gen_range(0..normalized_end);
+        let normalized_first_pick = rng.gen_range(0..size);
 
         Self {
             active: true,
-            normalized_end,
+            normalized_end: size,
             step,
             normalized_first_pick,
             normalized_pick: normalized_first_pick,
-            actual_start: start,
         }
     }
+
+    /// Where the picks that leave `remainder` when divided by `period` come
+    /// up, as the position of the pick counted modulo `period`. The step
+    /// being coprime with the size, it is with `period` too when `period`
+    /// divides the size, and every `period` picks in a row go through each
+    /// remainder once, always at the same position.
+    pub fn position_of(&self, remainder: u128, period: u128) -> u128 {
+        let first = self.normalized_first_pick % period;
+        let remainder = remainder % period;
+        let offset = if remainder >= first {
+            remainder - first
+        } else {
+            period - (first - remainder)
+        };
+        mul_mod(offset, inverse_mod(self.step % period, period), period)
+    }
 }
 
-impl Iterator for RangeIterator {
-    type Item = u16;
+impl Iterator for Permutation {
+    type Item = u128;
 
     // The next step is always bound by the formula: N+1 = (N + STEP) % TOP_OF_THE_RANGE
     // It will only stop once we generate a number equal to the first generated number.
@@ -51,8 +104,14 @@ impl Iterator for RangeIterator {
             return None;
         }
 
+        // Worked out without adding first, which could overflow near the
+        // top of the range.
         let current_pick = self.normalized_pick;
-        let next_pick = (current_pick + self.step) % self.normalized_end;
+        let next_pick = if current_pick >= self.normalized_end - self.step {
+            current_pick - (self.normalized_end - self.step)
+        } else {
+            current_pick + self.step
+        };
 
         // If the next pick is equal to the first pick this means that
         // we have iterated through the entire range.
@@ -61,11 +120,7 @@ impl Iterator for RangeIterator {
         }
 
         self.normalized_pick = next_pick;
-        Some(
-            (self.actual_start + current_pick)
-                .try_into()
-                .expect("Could not convert u32 to u16"),
-        )
+        Some(current_pick)
     }
 }
 
@@ -79,11 +134,10 @@ impl Iterator for RangeIterator {
 /// the boundaries, which in these case are the "start" and "end" arguments
 /// would also provide non-ideal randomization as discussed on the paragraph
 /// above.
-fn pick_random_coprime(end: u32) -> u32 {
+fn pick_random_coprime<R: Rng>(end: u128, rng: &mut R) -> u128 {
     let range_boundary = end / 4;
     let lower_range = range_boundary;
     let upper_range = end - range_boundary;
-    let mut rng = rand::thread_rng();
     let mut candidate = rng.gen_range(lower_range..upper_range);
 
     for _ in 0..10 {
@@ -96,9 +150,51 @@ fn pick_random_coprime(end: u32) -> u32 {
     end - 1
 }
 
+/// `a + b` modulo `m`, both being below `m`, without overflowing.
+fn add_mod(a: u128, b: u128, m: u128) -> u128 {
+    if a >= m - b {
+        a - (m - b)
+    } else {
+        a + b
+    }
+}
+
+/// `a * b` modulo `m`, both being below `m`, adding up the doublings of
+/// `a` when the product doesn't fit.
+fn mul_mod(a: u128, b: u128, m: u128) -> u128 {
+    if let Some(product) = a.checked_mul(b) {
+        return product % m;
+    }
+    let (mut a, mut b, mut product) = (a, b, 0);
+    while b > 0 {
+        if b & 1 == 1 {
+            product = add_mod(product, a, m);
+        }
+        a = add_mod(a, a, m);
+        b >>= 1;
+    }
+    product
+}
+
+/// The inverse of `a` modulo `m`, the two being coprime, worked out with
+/// the extended Euclidean algorithm keeping the coefficients modulo `m`.
+fn inverse_mod(a: u128, m: u128) -> u128 {
+    let (mut r0, mut r1) = (m, a);
+    let (mut t0, mut t1) = (0, 1 % m);
+    while r1 != 0 {
+        let quotient = r0 / r1;
+        (r0, r1) = (r1, r0 - quotient * r1);
+        let t = add_mod(t0, m - mul_mod(quotient % m, t1, m), m);
+        (t0, t1) = (t1, t);
+    }
+    t0
+}
+
 #[cfg(test)]
 mod tests {
-    use super::RangeIterator;
+    use super::{Permutation, RangeIterator};
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
 
     #[test]
     fn range_iterator_iterates_through_the_entire_range() {
@@ -119,11 +215,54 @@ mod tests {
         assert_eq!(expected_range, result);
     }
 
+    #[test]
+    fn permutation_goes_through_every_index_once() {
+        for size in [0, 1, 2, 12, 4_096 * 3] {
+            let mut result = Permutation::new(size, &mut rand::thread_rng()).collect::<Vec<u128>>();
+            result.sort_unstable();
+            assert_eq!((0..size).collect::<Vec<u128>>(), result);
+        }
+    }
+
+    #[test]
+    fn permutation_does_not_overflow_near_the_top() {
+        let size = u128::MAX - 2;
+        let picks: Vec<u128> = Permutation::new(size, &mut rand::thread_rng())
+            .take(1_000)
+            .collect();
+
+        assert!(picks.iter().all(|&pick| pick < size));
+    }
+
+    #[test]
+    fn permutation_positions_repeat_every_period() {
+        for (period, rounds) in [(1, 5), (7, 3), (12, 4), (4_096, 3)] {
+            let order =
+                || Permutation::new(period * rounds, &mut StdRng::seed_from_u64(period as u64));
+            let positions: Vec<u128> = (0..period)
+                .map(|remainder| order().position_of(remainder, period))
+                .collect();
+            for (index, pick) in order().enumerate() {
+                assert_eq!(positions[(pick % period) as usize], index as u128 % period);
+            }
+        }
+    }
+
+    #[test]
+    fn permutation_positions_do_not_overflow() {
+        let period = (1 << 126) + 15;
+        let order = || Permutation::new(period * 3, &mut StdRng::seed_from_u64(7));
+        let positions = order();
+        for (index, pick) in order().take(1_000).enumerate() {
+            assert_eq!(positions.position_of(pick, period), index as u128 % period);
+        }
+    }
+
     fn generate_sorted_range(start: u32, end: u32) -> Vec<u16> {
-        let range = RangeIterator::new(start, end);
+        let range = RangeIterator::new(start, end, &mut rand::thread_rng());
         let mut result = range.into_iter().collect::<Vec<u16>>();
         result.sort_unstable();
 
         result
     }
-}
\ No newline at end of file
+}
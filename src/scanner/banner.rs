@@ -0,0 +1,118 @@
+use async_std::io::{self, Read, ReadExt};
+use async_std::net::TcpStream;
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+
+/// Most bytes of a banner that are kept.
+const MAX_BANNER_BYTES: usize = 1024;
+/// Once the service started talking, how long to wait for the rest of its
+/// banner before calling it done.
+const MORE_DATA_GRACE: Duration = Duration::from_millis(100);
+
+/// Reads what the service on the other end of `stream` sends on its own,
+/// for up to `wait` and `MAX_BANNER_BYTES`. Returns `None` when it stayed
+/// quiet, like HTTP servers do until they get a request.
+pub async fn read_banner(stream: &mut TcpStream, wait: Duration) -> Option<String> {
+    let banner = sanitize(&read_response(stream, wait, MAX_BANNER_BYTES).await);
+    (!banner.is_empty()).then_some(banner)
+}
+
+/// Reads up to `limit` bytes from `stream` for up to `wait`, stopping
+/// early once the other end went quiet after it started talking.
+pub async fn read_response<S: Read + Unpin>(
+    stream: &mut S,
+    wait: Duration,
+    limit: usize,
+) -> Vec<u8> {
+    let deadline = Instant::now() + wait;
+    let mut buf = vec![0u8; limit];
+    let mut read = 0;
+
+    while read < buf.len() {
+        let left = deadline.saturating_duration_since(Instant::now());
+        let left = if read == 0 {
+            left
+        } else {
+            left.min(MORE_DATA_GRACE)
+        };
+        if left.is_zero() {
+            break;
+        }
+        match io::timeout(left, stream.read(&mut buf[read..])).await {
+            Ok(0) | Err(_) => break,
+            Ok(size) => read += size,
+        }
+    }
+
+    buf.truncate(read);
+    buf
+}
+
+/// Trims the banner and escapes every byte that isn't printable ASCII, so
+/// it can safely end up in a terminal or a report.
+pub fn sanitize(bytes: &[u8]) -> String {
+    let mut banner = String::with_capacity(bytes.len());
+    for &byte in bytes.trim_ascii() {
+        match byte {
+            b'\r' => banner.push_str("\\r"),
+            b'\n' => banner.push_str("\\n"),
+            b'\t' => banner.push_str("\\t"),
+            b'\\' => banner.push_str("\\\\"),
+            0x20..=0x7e => banner.push(char::from(byte)),
+            _ => {
+                let _ = write!(banner, "\\x{byte:02x}");
+            }
+        }
+    }
+    banner
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_banner, sanitize};
+    use async_std::net::TcpStream;
+    use async_std::task::block_on;
+    use std::io::Write;
+    use std::time::Duration;
+
+    #[test]
+    fn sanitizes_banner() {
+        assert_eq!(sanitize(b"SSH-2.0-OpenSSH_9.6\r\n"), "SSH-2.0-OpenSSH_9.6");
+        assert_eq!(
+            sanitize(b"220 mail\r\n220 ready\x1b[31m\xff"),
+            "220 mail\\r\\n220 ready\\x1b[31m\\xff"
+        );
+        assert_eq!(sanitize(b" \r\n"), "");
+    }
+
+    #[test]
+    fn reads_what_the_service_sends_first() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(b"220 azula ESMTP\r\n").unwrap();
+            std::thread::sleep(Duration::from_millis(500));
+        });
+
+        let banner = block_on(async {
+            let mut stream = TcpStream::connect(address).await.unwrap();
+            read_banner(&mut stream, Duration::from_secs(1)).await
+        });
+
+        assert_eq!(banner.as_deref(), Some("220 azula ESMTP"));
+    }
+
+    #[test]
+    fn quiet_services_have_no_banner() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+
+        let banner = block_on(async {
+            let mut stream = TcpStream::connect(address).await.unwrap();
+            read_banner(&mut stream, Duration::from_millis(100)).await
+        });
+
+        assert_eq!(banner, None);
+    }
+}
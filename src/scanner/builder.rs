@@ -0,0 +1,379 @@
+use super::{PortState, Protocol, Scanner};
+use crate::input::{PortRange, ScanOrder, TimingTemplate};
+use crate::port::PortStrategy;
+use crate::targets::{IpRange, TargetSet};
+use std::fmt;
+use std::time::Duration;
+
+/// Builds a `Scanner` from named options, for running scans from other
+/// programs rather than from the command line.
+///
+/// Only the targets have to be given, everything else defaults to what
+/// the command line does: every TCP port in ascending order with the
+/// timeout, tries and batch size of the normal timing template. Nothing
+/// is printed unless asked for, the results are read from the
+/// `ScanReport` handed back by `Scanner::run`.
+///
+/// ```rust
+/// # use azula::scanner::{Protocol, ScannerBuilder};
+/// # use azula::port::PortStrategy;
+/// # use std::time::Duration;
+/// let scanner = ScannerBuilder::new()
+///     .target("127.0.0.1".parse::<std::net::IpAddr>().unwrap())
+///     .ports(Protocol::Tcp, PortStrategy::Manual(vec![22, 80, 443]))
+///     .timeout(Duration::from_millis(500))
+///     .build()
+///     .unwrap();
+/// ```
+///
+/// Options the builder doesn't cover, like service detection or host
+/// discovery, are set with the `with_*` methods of the built `Scanner`.
+#[derive(Debug)]
+pub struct ScannerBuilder {
+    targets: Vec<IpRange>,
+    ports: Vec<(Protocol, PortStrategy)>,
+    exclude_ports: Vec<u16>,
+    batch_size: u16,
+    timeout: Duration,
+    tries: u8,
+    scan_delay: Duration,
+    rtt_timeout_bounds: Option<(Duration, Duration)>,
+    min_rate: Option<u32>,
+    max_rate: Option<u32>,
+    reported_states: Vec<PortState>,
+    quiet: bool,
+    greppable: bool,
+    accessible: bool,
+}
+
+impl Default for ScannerBuilder {
+    fn default() -> Self {
+        let timing = TimingTemplate::Normal.values();
+        Self {
+            targets: Vec::new(),
+            ports: Vec::new(),
+            exclude_ports: Vec::new(),
+            batch_size: timing.batch_size,
+            timeout: Duration::from_millis(timing.timeout.into()),
+            tries: timing.tries,
+            scan_delay: Duration::from_millis(timing.scan_delay.into()),
+            rtt_timeout_bounds: None,
+            min_rate: None,
+            max_rate: None,
+            reported_states: Vec::new(),
+            quiet: true,
+            greppable: false,
+            accessible: false,
+        }
+    }
+}
+
+impl ScannerBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an IP or a range of IPs to the targets.
+    #[must_use]
+    pub fn target(mut self, target: impl Into<IpRange>) -> Self {
+        self.targets.push(target.into());
+        self
+    }
+
+    /// Adds every IP or range of IPs of `targets` to the targets, see
+    /// `address::parse_addresses` to get them from strings.
+    #[must_use]
+    pub fn targets<T: Into<IpRange>>(mut self, targets: impl IntoIterator<Item = T>) -> Self {
+        self.targets.extend(targets.into_iter().map(Into::into));
+        self
+    }
+
+    /// Scans the ports of `port_strategy` over `protocol`. Protocols are
+    /// scanned one after the other, in the order they were added, and
+    /// replace the default of every TCP port.
+    #[must_use]
+    pub fn ports(mut self, protocol: Protocol, port_strategy: PortStrategy) -> Self {
+        self.ports.push((protocol, port_strategy));
+        self
+    }
+
+    /// Leaves `ports` out of every protocol.
+    #[must_use]
+    pub fn exclude_ports(mut self, ports: Vec<u16>) -> Self {
+        self.exclude_ports = ports;
+        self
+    }
+
+    /// The most probes in flight at once.
+    #[must_use]
+    pub fn batch_size(mut self, batch_size: u16) -> Self {
+        self.batch_size = batch_size;
+        self
+    }
+
+    /// How long to wait for an answer before trying again or giving up on
+    /// a port, until the round trip times of the host are known.
+    #[must_use]
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// How many probes a port gets before it is given up on.
+    #[must_use]
+    pub fn tries(mut self, tries: u8) -> Self {
+        self.tries = tries;
+        self
+    }
+
+    /// Sets the timeout, tries and batch size, along with the delay and
+    /// rate limit the scanner gets, from `template`.
+    #[must_use]
+    pub fn timing(mut self, template: TimingTemplate) -> Self {
+        let timing = template.values();
+        self.timeout = Duration::from_millis(timing.timeout.into());
+        self.tries = timing.tries;
+        self.batch_size = timing.batch_size;
+        self.scan_delay = Duration::from_millis(timing.scan_delay.into());
+        self.max_rate = timing.max_rate;
+        self
+    }
+
+    /// See `Scanner::with_scan_delay`.
+    #[must_use]
+    pub fn scan_delay(mut self, delay: Duration) -> Self {
+        self.scan_delay = delay;
+        self
+    }
+
+    /// See `Scanner::with_rtt_timeout_bounds`.
+    #[must_use]
+    pub fn rtt_timeout_bounds(mut self, min: Duration, max: Duration) -> Self {
+        self.rtt_timeout_bounds = Some((min, max));
+        self
+    }
+
+    /// See `Scanner::with_rate_limits`.
+    #[must_use]
+    pub fn rate_limits(mut self, min: Option<u32>, max: Option<u32>) -> Self {
+        self.min_rate = min;
+        self.max_rate = max;
+        self
+    }
+
+    /// See `Scanner::with_reported_states`.
+    #[must_use]
+    pub fn reported_states(mut self, states: &[PortState]) -> Self {
+        self.reported_states = states.to_vec();
+        self
+    }
+
+    /// Whether the scanner keeps from printing anything, which is the
+    /// default. Otherwise it prints open ports and warnings like the
+    /// command line does, `greppable` and `accessible` telling how.
+    #[must_use]
+    pub fn quiet(mut self, quiet: bool) -> Self {
+        self.quiet = quiet;
+        self
+    }
+
+    #[must_use]
+    pub fn greppable(mut self, greppable: bool) -> Self {
+        self.greppable = greppable;
+        self
+    }
+
+    #[must_use]
+    pub fn accessible(mut self, accessible: bool) -> Self {
+        self.accessible = accessible;
+        self
+    }
+
+    /// Checks the options go together and builds the scanner.
+    pub fn build(self) -> Result<Scanner, BuildError> {
+        if self.targets.is_empty() {
+            return Err(BuildError::NoTargets);
+        }
+        if self.batch_size == 0 {
+            return Err(BuildError::ZeroBatchSize);
+        }
+        if self.timeout.is_zero() {
+            return Err(BuildError::ZeroTimeout);
+        }
+        if self.tries == 0 {
+            return Err(BuildError::ZeroTries);
+        }
+        if let Some((min, max)) = self.rtt_timeout_bounds {
+            if min > max {
+                return Err(BuildError::RttTimeoutBounds { min, max });
+            }
+        }
+        if let (Some(min), Some(max)) = (self.min_rate, self.max_rate) {
+            if min > max {
+                return Err(BuildError::RateLimits { min, max });
+            }
+        }
+        let mut ports = self.ports;
+        if ports.is_empty() {
+            ports.push((
+                Protocol::Tcp,
+                PortStrategy::pick(
+                    &Some(PortRange {
+                        start: 1,
+                        end: u16::MAX,
+                    }),
+                    None,
+                    ScanOrder::Serial,
+                ),
+            ));
+        }
+        if ports.iter().all(|(_, port_strategy)| {
+            port_strategy
+                .order()
+                .iter()
+                .all(|port| self.exclude_ports.contains(port))
+        }) {
+            return Err(BuildError::NoPorts);
+        }
+
+        let mut ports = ports.into_iter();
+        let (protocol, port_strategy) = ports.next().expect("there is at least one protocol");
+        let mut scanner = Scanner::new(
+            self.targets.into_iter().collect::<TargetSet>(),
+            self.batch_size,
+            self.timeout,
+            self.tries,
+            self.greppable,
+            port_strategy,
+            self.accessible,
+            self.exclude_ports,
+            protocol == Protocol::Udp,
+        )
+        .with_reported_states(&self.reported_states)
+        .with_rate_limits(self.min_rate, self.max_rate)
+        .with_scan_delay(self.scan_delay);
+        for (protocol, port_strategy) in ports {
+            scanner = scanner.with_protocol_ports(protocol, port_strategy);
+        }
+        if let Some((min, max)) = self.rtt_timeout_bounds {
+            scanner = scanner.with_rtt_timeout_bounds(min, max);
+        }
+        scanner.quiet = self.quiet;
+        Ok(scanner)
+    }
+}
+
+/// Why a `ScannerBuilder` couldn't build a scanner.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BuildError {
+    NoTargets,
+    /// Every port to scan is excluded.
+    NoPorts,
+    ZeroBatchSize,
+    ZeroTimeout,
+    ZeroTries,
+    RttTimeoutBounds {
+        min: Duration,
+        max: Duration,
+    },
+    RateLimits {
+        min: u32,
+        max: u32,
+    },
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BuildError::NoTargets => f.write_str("no targets to scan"),
+            BuildError::NoPorts => f.write_str("every port to scan is excluded"),
+            BuildError::ZeroBatchSize => f.write_str("the batch size must be at least 1"),
+            BuildError::ZeroTimeout => f.write_str("the timeout must be longer than zero"),
+            BuildError::ZeroTries => f.write_str("ports must be tried at least once"),
+            BuildError::RttTimeoutBounds { min, max } => write!(
+                f,
+                "the minimum RTT timeout ({min:?}) is above the maximum ({max:?})"
+            ),
+            BuildError::RateLimits { min, max } => write!(
+                f,
+                "the minimum rate ({min}/s) is above the maximum rate ({max}/s)"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for BuildError {}
+
+#[cfg(test)]
+mod tests {
+    use super::{BuildError, ScannerBuilder};
+    use crate::port::PortStrategy;
+    use crate::scanner::{PortState, Protocol};
+    use async_std::task::block_on;
+    use std::net::IpAddr;
+    use std::time::Duration;
+
+    #[test]
+    fn builds_a_quiet_scanner_reporting_open_ports() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let open = listener.local_addr().unwrap().port();
+        let ip: IpAddr = "127.0.0.1".parse().unwrap();
+
+        let scanner = ScannerBuilder::new()
+            .target(ip)
+            .ports(Protocol::Tcp, PortStrategy::Manual(vec![open, 1]))
+            .timeout(Duration::from_millis(500))
+            .reported_states(&[PortState::Closed])
+            .build()
+            .unwrap();
+        assert!(scanner.silent());
+        let report = block_on(scanner.run());
+
+        assert_eq!(report.targets, 1);
+        assert_eq!(report.hosts[0].ip, ip);
+        assert_eq!(report.hosts[0].open_ports(), vec![open]);
+        assert_eq!(report.hosts[0].ports.len(), 2);
+        assert!(report.hosts[0].errors.is_empty());
+        drop(listener);
+    }
+
+    #[test]
+    fn refuses_options_that_do_not_go_together() {
+        let ip: IpAddr = "127.0.0.1".parse().unwrap();
+
+        assert_eq!(
+            ScannerBuilder::new().build().unwrap_err(),
+            BuildError::NoTargets
+        );
+        assert_eq!(
+            ScannerBuilder::new()
+                .target(ip)
+                .batch_size(0)
+                .build()
+                .unwrap_err(),
+            BuildError::ZeroBatchSize
+        );
+        assert_eq!(
+            ScannerBuilder::new()
+                .target(ip)
+                .ports(Protocol::Udp, PortStrategy::Manual(vec![53]))
+                .exclude_ports(vec![53])
+                .build()
+                .unwrap_err(),
+            BuildError::NoPorts
+        );
+        assert_eq!(
+            ScannerBuilder::new()
+                .target(ip)
+                .rate_limits(Some(100), Some(10))
+                .build()
+                .unwrap_err(),
+            BuildError::RateLimits { min: 100, max: 10 }
+        );
+        assert!(ScannerBuilder::new()
+            .target(ip)
+            .quiet(false)
+            .build()
+            .is_ok_and(|scanner| !scanner.silent()));
+    }
+}
@@ -0,0 +1,265 @@
+use super::result::{deserialize_millis, serialize_millis, HostResult, Protocol};
+use crate::targets::{IpRange, TargetSet};
+use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// How often the progress of a scan is saved to its resume file.
+pub(super) const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// A file the progress of a scan is saved to while it runs, so a scan that
+/// crashed or was stopped can carry on where it was when it is run again
+/// with the same file and the same options.
+///
+/// The checkpoint keeps the seed the random port orders were drawn from,
+/// how far the scan got through its sockets and the results found so far.
+/// The file is removed once the scan is done.
+#[derive(Debug)]
+pub struct ResumeFile {
+    path: PathBuf,
+    seed: u64,
+    checkpoint: Option<Checkpoint>,
+}
+
+impl ResumeFile {
+    /// Reads the checkpoint saved at `path`, a missing file starts a new
+    /// scan with a random seed.
+    pub fn open(path: PathBuf) -> io::Result<Self> {
+        let checkpoint: Option<Checkpoint> = match fs::read(&path) {
+            Ok(content) => Some(serde_json::from_slice(&content)?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e),
+        };
+        Ok(Self {
+            path,
+            seed: checkpoint
+                .as_ref()
+                .map_or_else(rand::random, |checkpoint| checkpoint.seed),
+            checkpoint,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The seed random port orders have to be drawn from for the scan to
+    /// go through its sockets in the same order as the interrupted one.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Whether there is a checkpoint to carry on from.
+    pub fn is_resuming(&self) -> bool {
+        self.checkpoint.is_some()
+    }
+
+    pub(super) fn checkpoint(&self) -> Option<&Checkpoint> {
+        self.checkpoint.as_ref()
+    }
+
+    /// Replaces the saved checkpoint with `checkpoint`. The new one is
+    /// written next to the old one first, so a crash halfway leaves the
+    /// old one whole.
+    pub(super) fn save(&self, checkpoint: &Checkpoint) -> io::Result<()> {
+        let mut partial = self.path.clone().into_os_string();
+        partial.push(".partial");
+        fs::write(&partial, serde_json::to_vec(checkpoint)?)?;
+        fs::rename(&partial, &self.path)
+    }
+
+    pub(super) fn remove(&self) -> io::Result<()> {
+        match fs::remove_file(&self.path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Everything needed to carry on with a scan.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub(super) struct Checkpoint {
+    pub seed: u64,
+    /// Tells checkpoints of other scans apart, see `fingerprint`.
+    pub fingerprint: u64,
+    pub started_at: u64,
+    /// Time spent scanning so far, over every run.
+    #[serde(
+        rename = "elapsed_ms",
+        serialize_with = "serialize_millis",
+        deserialize_with = "deserialize_millis"
+    )]
+    pub elapsed: Duration,
+    /// The hosts that answered host discovery, whose sockets are the ones
+    /// being gone through.
+    pub live: TargetSet,
+    pub progress: Progress,
+    /// The hosts something was found about so far.
+    pub hosts: Vec<HostResult>,
+}
+
+/// Which sockets the scan is done with, by their index in the order they
+/// are scanned in. Probes finish out of order, so on top of every socket
+/// before `position` a few past it can be done already.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub(super) struct Progress {
+    position: usize,
+    finished: BTreeSet<usize>,
+}
+
+impl Progress {
+    pub fn is_finished(&self, index: usize) -> bool {
+        index < self.position || self.finished.contains(&index)
+    }
+
+    pub fn finish(&mut self, index: usize) {
+        self.finished.insert(index);
+        while self.finished.remove(&self.position) {
+            self.position += 1;
+        }
+    }
+}
+
+/// Sums up the targets and ports of a scan in the order they are scanned
+/// in, along with the seed sockets are permuted with if they are,
+/// checkpoints are only picked up by a scan with the same fingerprint.
+/// It is FNV-1a, which unlike the hasher of the standard library gives the
+/// same value from one build to the next.
+pub(super) fn fingerprint(
+    ips: &[IpRange],
+    targets: &[(Protocol, Vec<u16>)],
+    permutation: Option<u64>,
+) -> u64 {
+    let mut bytes = Vec::new();
+    for ip in ips.iter().flat_map(|range| [range.start(), range.end()]) {
+        match ip {
+            IpAddr::V4(ip) => bytes.extend_from_slice(&ip.octets()),
+            IpAddr::V6(ip) => bytes.extend_from_slice(&ip.octets()),
+        }
+    }
+    for (protocol, ports) in targets {
+        bytes.push(*protocol as u8);
+        for port in ports {
+            bytes.extend_from_slice(&port.to_be_bytes());
+        }
+    }
+    if let Some(seed) = permutation {
+        bytes.extend_from_slice(&seed.to_be_bytes());
+    }
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{fingerprint, Checkpoint, Progress, ResumeFile};
+    use crate::scanner::{HostResult, PortResult, PortState, Protocol};
+    use crate::targets::{IpRange, TargetSet};
+    use std::net::IpAddr;
+    use std::time::Duration;
+
+    #[test]
+    fn progress_moves_past_finished_sockets() {
+        let mut progress = Progress::default();
+
+        progress.finish(1);
+        progress.finish(3);
+        assert_eq!(progress.position, 0);
+        assert!(progress.is_finished(3));
+        assert!(!progress.is_finished(2));
+
+        progress.finish(0);
+        assert_eq!(progress.position, 2);
+        progress.finish(2);
+        assert_eq!(progress.position, 4);
+        assert_eq!(progress.finished.len(), 0);
+    }
+
+    #[test]
+    fn saves_and_reads_checkpoints() {
+        let path = std::env::temp_dir().join(format!("azula-resume-{}.json", std::process::id()));
+        let ip: IpAddr = "192.0.2.1".parse().unwrap();
+        let targets = [(Protocol::Tcp, vec![22, 80])];
+        let mut host = HostResult::new(ip);
+        host.ports.push(PortResult::new(
+            22,
+            Protocol::Tcp,
+            PortState::Open,
+            Duration::from_micros(1_500),
+        ));
+        let mut progress = Progress::default();
+        progress.finish(0);
+        let checkpoint = Checkpoint {
+            seed: 42,
+            fingerprint: fingerprint(&[ip.into()], &targets, None),
+            started_at: 1_700_000_000,
+            elapsed: Duration::from_secs(3),
+            live: TargetSet::from(ip),
+            progress,
+            hosts: vec![host],
+        };
+
+        let fresh = ResumeFile::open(path.clone()).unwrap();
+        assert!(!fresh.is_resuming());
+        fresh.save(&checkpoint).unwrap();
+
+        let resumed = ResumeFile::open(path.clone()).unwrap();
+        assert_eq!(resumed.seed(), 42);
+        assert_eq!(resumed.checkpoint(), Some(&checkpoint));
+        assert_ne!(
+            checkpoint.fingerprint,
+            fingerprint(&[ip.into()], &[(Protocol::Tcp, vec![80, 22])], None)
+        );
+        assert_ne!(
+            checkpoint.fingerprint,
+            fingerprint(&[ip.into()], &targets, Some(42))
+        );
+        assert_ne!(
+            checkpoint.fingerprint,
+            fingerprint(
+                &[IpRange::new(ip, "192.0.2.2".parse().unwrap()).unwrap()],
+                &targets,
+                None
+            )
+        );
+
+        resumed.remove().unwrap();
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn refuses_checkpoints_with_invalid_ranges() {
+        let path =
+            std::env::temp_dir().join(format!("azula-resume-invalid-{}.json", std::process::id()));
+        let range =
+            IpRange::new("192.0.2.1".parse().unwrap(), "192.0.2.9".parse().unwrap()).unwrap();
+        let checkpoint = Checkpoint {
+            seed: 42,
+            fingerprint: fingerprint(&[range], &[(Protocol::Tcp, vec![22])], None),
+            started_at: 1_700_000_000,
+            elapsed: Duration::from_secs(3),
+            live: TargetSet::from(vec![range]),
+            progress: Progress::default(),
+            hosts: vec![],
+        };
+        ResumeFile::open(path.clone())
+            .unwrap()
+            .save(&checkpoint)
+            .unwrap();
+        let saved = std::fs::read_to_string(&path).unwrap();
+        let edited = saved.replace(r#""end":"192.0.2.9""#, r#""end":"192.0.2.0""#);
+        assert_ne!(saved, edited);
+        std::fs::write(&path, edited).unwrap();
+
+        let error = ResumeFile::open(path.clone()).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+        assert!(error.to_string().contains("invalid IP range"));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
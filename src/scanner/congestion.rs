@@ -0,0 +1,298 @@
+use log::debug;
+use std::io;
+use std::time::{Duration, Instant};
+
+/// Number of probes in flight when a scan starts, unless the batch size
+/// is smaller.
+const INITIAL_WINDOW: usize = 256;
+/// The window never shrinks below this many probes in flight.
+const MIN_WINDOW: usize = 8;
+/// How many probes the window grows by after a healthy round once it is
+/// past the slow start threshold.
+const ADDITIVE_INCREASE: usize = 32;
+/// How much the drop rate of a round may exceed the usual drop rate before
+/// we take it as a sign of congestion. Hosts that drop most probes because
+/// of a firewall have a high usual drop rate, so they don't slow us down.
+const DROP_SPIKE: f64 = 0.15;
+/// How long no new probe is sent after running out of local resources,
+/// giving the sockets in flight time to close.
+const EXHAUSTION_PAUSE: Duration = Duration::from_millis(500);
+/// How many rounds in a row have to go by without running out of local
+/// resources before the most the window may grow to goes up again.
+const RECOVERY_ROUNDS: usize = 8;
+/// How many times a probe that ran out of local resources is sent again
+/// before its socket is given up on.
+pub const EXHAUSTED_TRIES: u8 = 10;
+
+/// What became of a single probe, as far as congestion is concerned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProbeOutcome {
+    /// The target answered, be it with an accepted or a refused connection.
+    Answered,
+    /// Nothing came back before the timeout.
+    Dropped,
+    /// The local host ran out of sockets, file descriptors or buffers.
+    ResourceExhausted,
+}
+
+impl ProbeOutcome {
+    /// Tells whether the error came from running out of local resources,
+    /// as opposed to something the target did.
+    pub fn is_resource_exhaustion(error: &io::Error) -> bool {
+        #[cfg(unix)]
+        {
+            matches!(
+                error.raw_os_error(),
+                Some(libc::EMFILE | libc::ENFILE | libc::ENOBUFS | libc::EADDRNOTAVAIL)
+            )
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = error;
+            false
+        }
+    }
+}
+
+/// Decides how many probes `Scanner::run` keeps in flight, AIMD style.
+///
+/// Outcomes are looked at in rounds of one window worth of probes. After a
+/// healthy round the window doubles until it reaches the slow start
+/// threshold and grows linearly after that, never past the batch size.
+/// A round whose drop rate spikes above the usual one halves the window.
+/// Running out of local resources halves it too, makes that the most it
+/// can grow back to and pauses new probes for a bit. That limit goes back
+/// up linearly, towards the batch size, after every few rounds without
+/// running out again, as the sockets holding the resources get closed.
+#[derive(Debug)]
+pub struct CongestionWindow {
+    window: usize,
+    max: usize,
+    batch_size: usize,
+    /// Rounds in a row that didn't run out of local resources.
+    clean_rounds: usize,
+    paused_until: Option<Instant>,
+    slow_start_threshold: usize,
+    answered: usize,
+    dropped: usize,
+    exhausted: usize,
+    usual_drop_rate: Option<f64>,
+}
+
+impl CongestionWindow {
+    pub fn new(max: usize) -> Self {
+        let max = max.max(1);
+        Self {
+            window: INITIAL_WINDOW.min(max),
+            max,
+            batch_size: max,
+            clean_rounds: 0,
+            paused_until: None,
+            slow_start_threshold: max,
+            answered: 0,
+            dropped: 0,
+            exhausted: 0,
+            usual_drop_rate: None,
+        }
+    }
+
+    /// How many probes can be in flight right now.
+    pub fn window(&self) -> usize {
+        self.window
+    }
+
+    /// The most probes the window may grow to, the batch size unless local
+    /// resources ran out lately.
+    pub fn max(&self) -> usize {
+        self.max
+    }
+
+    /// How long to hold off new probes for, after running out of local
+    /// resources.
+    pub fn pause(&self) -> Option<Duration> {
+        self.paused_until
+            .map(|until| until.saturating_duration_since(Instant::now()))
+            .filter(|left| !left.is_zero())
+    }
+
+    pub fn record(&mut self, outcome: ProbeOutcome) {
+        match outcome {
+            ProbeOutcome::Answered => self.answered += 1,
+            ProbeOutcome::Dropped => self.dropped += 1,
+            ProbeOutcome::ResourceExhausted => {
+                self.exhausted += 1;
+                self.paused_until = Some(Instant::now() + EXHAUSTION_PAUSE);
+                // Back off straight away, but only once per round as the
+                // probes already in flight are likely to fail the same way.
+                if self.exhausted == 1 {
+                    debug!("Local resources exhausted, backing off");
+                    self.max = (self.window / 2).max(1);
+                    self.window = self.max;
+                    self.slow_start_threshold = self.max;
+                    debug!("Congestion window is now at most {}", self.max);
+                }
+            }
+        }
+
+        if self.answered + self.dropped + self.exhausted >= self.window {
+            self.end_round();
+        }
+    }
+
+    fn end_round(&mut self) {
+        if self.exhausted > 0 {
+            self.clean_rounds = 0;
+        } else if self.max < self.batch_size {
+            self.clean_rounds += 1;
+            if self.clean_rounds == RECOVERY_ROUNDS {
+                self.clean_rounds = 0;
+                self.max = (self.max + ADDITIVE_INCREASE).min(self.batch_size);
+                debug!("Congestion window may grow to {} again", self.max);
+            }
+        }
+
+        let replies = self.answered + self.dropped;
+        if self.exhausted == 0 && replies > 0 {
+            #[allow(clippy::cast_precision_loss)]
+            let drop_rate = self.dropped as f64 / replies as f64;
+            let spiked = self
+                .usual_drop_rate
+                .is_some_and(|usual| drop_rate > usual + DROP_SPIKE);
+            self.usual_drop_rate = Some(
+                self.usual_drop_rate
+                    .map_or(drop_rate, |usual| usual * 0.75 + drop_rate * 0.25),
+            );
+
+            if spiked {
+                debug!("Drop rate spiked to {:.2}, backing off", drop_rate);
+                self.back_off();
+            } else {
+                self.grow();
+            }
+        }
+
+        self.answered = 0;
+        self.dropped = 0;
+        self.exhausted = 0;
+    }
+
+    fn grow(&mut self) {
+        self.window = if self.window < self.slow_start_threshold {
+            (self.window * 2).min(self.slow_start_threshold)
+        } else {
+            self.window + ADDITIVE_INCREASE
+        }
+        .min(self.max);
+    }
+
+    fn back_off(&mut self) {
+        self.slow_start_threshold = (self.window / 2).max(MIN_WINDOW).min(self.max);
+        self.window = self.slow_start_threshold;
+        debug!("Congestion window is now {}", self.window);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CongestionWindow, ProbeOutcome, ADDITIVE_INCREASE, RECOVERY_ROUNDS};
+
+    fn round(congestion: &mut CongestionWindow, dropped: usize) {
+        let window = congestion.window();
+        for n in 0..window {
+            congestion.record(if n < dropped {
+                ProbeOutcome::Dropped
+            } else {
+                ProbeOutcome::Answered
+            });
+        }
+    }
+
+    #[test]
+    fn grows_up_to_the_batch_size() {
+        let mut congestion = CongestionWindow::new(1_000);
+        assert_eq!(congestion.window(), 256);
+
+        round(&mut congestion, 0);
+        assert_eq!(congestion.window(), 512);
+        for _ in 0..10 {
+            round(&mut congestion, 0);
+        }
+        assert_eq!(congestion.window(), 1_000);
+    }
+
+    #[test]
+    fn backs_off_when_drops_spike() {
+        let mut congestion = CongestionWindow::new(1_000);
+        round(&mut congestion, 0);
+        assert_eq!(congestion.window(), 512);
+
+        round(&mut congestion, 256);
+        assert_eq!(congestion.window(), 256);
+
+        // Past the threshold the window only grows linearly.
+        round(&mut congestion, 0);
+        assert_eq!(congestion.window(), 288);
+    }
+
+    #[test]
+    fn steady_drops_do_not_shrink_the_window() {
+        let mut congestion = CongestionWindow::new(1_000);
+        for _ in 0..3 {
+            let window = congestion.window();
+            round(&mut congestion, window * 9 / 10);
+        }
+        assert_eq!(congestion.window(), 1_000);
+    }
+
+    #[test]
+    fn backs_off_once_per_round_on_resource_errors() {
+        let mut congestion = CongestionWindow::new(1_000);
+        for _ in 0..10 {
+            congestion.record(ProbeOutcome::ResourceExhausted);
+        }
+        assert_eq!(congestion.window(), 128);
+        assert!(congestion.pause().is_some());
+    }
+
+    #[test]
+    fn does_not_grow_back_past_exhausted_resources_right_away() {
+        let mut congestion = CongestionWindow::new(1_000);
+        congestion.record(ProbeOutcome::ResourceExhausted);
+        for _ in 0..RECOVERY_ROUNDS - 1 {
+            round(&mut congestion, 0);
+        }
+        assert_eq!(congestion.window(), 128);
+        assert_eq!(congestion.max(), 128);
+
+        // Running out again with a window this small can go below the
+        // usual minimum, down to a single probe.
+        let mut congestion = CongestionWindow::new(2);
+        congestion.record(ProbeOutcome::ResourceExhausted);
+        assert_eq!(congestion.window(), 1);
+    }
+
+    #[test]
+    fn recovers_from_exhausted_resources_while_they_last() {
+        let mut congestion = CongestionWindow::new(1_000);
+        congestion.record(ProbeOutcome::ResourceExhausted);
+        // The rest of the round in which resources ran out isn't clean.
+        for _ in 0..127 {
+            congestion.record(ProbeOutcome::Answered);
+        }
+        for _ in 0..RECOVERY_ROUNDS {
+            round(&mut congestion, 0);
+        }
+        assert_eq!(congestion.max(), 128 + ADDITIVE_INCREASE);
+        round(&mut congestion, 0);
+        assert_eq!(congestion.window(), 128 + ADDITIVE_INCREASE);
+
+        // Running out again halves the window and starts over.
+        congestion.record(ProbeOutcome::ResourceExhausted);
+        assert_eq!(congestion.max(), (128 + ADDITIVE_INCREASE) / 2);
+        for _ in 0..RECOVERY_ROUNDS * 1_000 {
+            round(&mut congestion, 0);
+        }
+        assert_eq!(congestion.max(), 1_000);
+        assert_eq!(congestion.window(), 1_000);
+    }
+}
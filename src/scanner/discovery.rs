@@ -0,0 +1,149 @@
+use super::Source;
+use async_std::io;
+use futures::future::{self, BoxFuture, FutureExt};
+use log::debug;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+/// Finds out which targets are alive before spending a full port scan on
+/// them.
+///
+/// A host counts as alive as soon as one of its pings gets an answer: a TCP
+/// connect that is accepted or refused on any of the ping ports, or an ICMP
+/// echo reply. ICMP echo goes through the unprivileged datagram ICMP sockets
+/// of Linux, so it is only tried there and only works when
+/// `net.ipv4.ping_group_range` lets us in. Everywhere else the TCP pings are
+/// all we have.
+#[derive(Debug, Clone)]
+pub struct Discovery {
+    ports: Vec<u16>,
+    timeout: Duration,
+    source: Source,
+}
+
+impl Discovery {
+    pub fn new(ports: Vec<u16>, timeout: Duration) -> Self {
+        Self {
+            ports,
+            timeout,
+            source: Source::default(),
+        }
+    }
+
+    /// Sends the pings from `source`.
+    #[must_use]
+    pub fn with_source(mut self, source: Source) -> Self {
+        self.source = source;
+        self
+    }
+
+    /// How many sockets pinging a single host takes.
+    pub fn sockets_per_host(&self) -> usize {
+        self.ports.len() + 1
+    }
+
+    /// Tells whether `ip` answered any of the pings.
+    pub async fn is_alive(&self, ip: IpAddr) -> bool {
+        let mut pings: Vec<BoxFuture<'_, io::Result<()>>> = self
+            .ports
+            .iter()
+            .map(|&port| self.tcp_ping(SocketAddr::new(ip, port)).boxed())
+            .collect();
+        // Echo requests can't go through proxies, they would leave from
+        // here and tell nothing about what the proxies can reach.
+        if self.source.proxies().is_empty() {
+            pings.push(icmp_ping(ip, &self.source, self.timeout).boxed());
+        }
+
+        match future::select_ok(pings).await {
+            Ok(_) => true,
+            Err(e) => {
+                debug!("{} did not answer discovery: {}", ip, e);
+                false
+            }
+        }
+    }
+
+    async fn tcp_ping(&self, socket: SocketAddr) -> io::Result<()> {
+        match self.source.connect(socket, self.timeout).await {
+            // A refused connection still means someone is home.
+            Err(e) if e.kind() != io::ErrorKind::ConnectionRefused => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+async fn icmp_ping(ip: IpAddr, source: &Source, timeout: Duration) -> io::Result<()> {
+    use super::checksum::checksum;
+    use async_std::net::UdpSocket;
+    use socket2::{Protocol, Type};
+
+    const ECHO_REQUEST_V4: u8 = 8;
+    const ECHO_REPLY_V4: u8 = 0;
+    const ECHO_REQUEST_V6: u8 = 128;
+    const ECHO_REPLY_V6: u8 = 129;
+
+    let (protocol, request, reply) = match ip {
+        IpAddr::V4(_) => (Protocol::ICMPV4, ECHO_REQUEST_V4, ECHO_REPLY_V4),
+        IpAddr::V6(_) => (Protocol::ICMPV6, ECHO_REQUEST_V6, ECHO_REPLY_V6),
+    };
+
+    // ICMP has no ports, the socket only takes the address and interface.
+    let socket = source
+        .without_port()
+        .socket(SocketAddr::new(ip, 0), Type::DGRAM, protocol)?;
+    socket.set_nonblocking(true)?;
+    let socket = UdpSocket::from(std::net::UdpSocket::from(socket));
+    socket.connect(SocketAddr::new(ip, 0)).await?;
+
+    // The kernel fills in the identifier, and the checksum for ICMPv6.
+    let mut packet = vec![request, 0, 0, 0, 0, 0, 0, 1];
+    packet.extend_from_slice(b"azula");
+    let checksum = checksum(&packet);
+    packet[2..4].copy_from_slice(&checksum.to_be_bytes());
+    socket.send(&packet).await?;
+
+    let mut buf = [0u8; 1500];
+    io::timeout(timeout, async {
+        loop {
+            let size = socket.recv(&mut buf).await?;
+            if size >= 8 && buf[0] == reply {
+                return Ok(());
+            }
+        }
+    })
+    .await
+}
+
+#[cfg(not(target_os = "linux"))]
+async fn icmp_ping(_ip: IpAddr, _source: &Source, _timeout: Duration) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "ICMP echo needs Linux datagram ICMP sockets",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Discovery;
+    use async_std::task::block_on;
+    use std::time::Duration;
+
+    #[test]
+    fn listening_host_is_alive() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let discovery = Discovery::new(vec![port], Duration::from_millis(500));
+
+        assert!(block_on(discovery.is_alive("127.0.0.1".parse().unwrap())));
+    }
+
+    #[test]
+    fn unroutable_host_is_down() {
+        // 100::/64 is a discard only prefix, nothing there ever answers.
+        let discovery = Discovery::new(vec![80], Duration::from_millis(200));
+
+        assert!(!block_on(discovery.is_alive("100::1".parse().unwrap())));
+    }
+}
@@ -0,0 +1,67 @@
+use super::result::serialize_millis;
+use super::{PortResult, ScriptOutput};
+use serde_derive::Serialize;
+use std::fmt::Debug;
+use std::net::IpAddr;
+use std::time::Duration;
+
+/// Something that happened while a scan was running.
+///
+/// The scanner emits the start, host and port events as they happen, for
+/// every port state no matter which states end up in the `ScanReport`,
+/// script and end of run events are emitted by whoever drives the scan
+/// once the scripts are done.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ScanEvent {
+    ScanStarted {
+        targets: u128,
+        ports: usize,
+        started_at: u64,
+    },
+    HostStarted {
+        ip: IpAddr,
+    },
+    HostDown {
+        ip: IpAddr,
+    },
+    HostIncomplete {
+        ip: IpAddr,
+    },
+    PortOpen {
+        ip: IpAddr,
+        #[serde(flatten)]
+        port: PortResult,
+    },
+    PortClosed {
+        ip: IpAddr,
+        #[serde(flatten)]
+        port: PortResult,
+    },
+    PortFiltered {
+        ip: IpAddr,
+        #[serde(flatten)]
+        port: PortResult,
+    },
+    ScriptFinished {
+        ip: IpAddr,
+        #[serde(flatten)]
+        script: ScriptOutput,
+    },
+    ScanFinished {
+        hosts: u128,
+        open_ports: usize,
+        #[serde(rename = "elapsed_ms", serialize_with = "serialize_millis")]
+        elapsed: Duration,
+        #[serde(skip_serializing_if = "std::ops::Not::not")]
+        interrupted: bool,
+    },
+}
+
+/// Receives the events of a running scan, see `Scanner::with_event_sink`.
+///
+/// Events are emitted from the scanning loop, so implementations should
+/// hand them off quickly instead of doing heavy work.
+pub trait EventSink: Send + Sync + Debug {
+    fn emit(&self, event: &ScanEvent);
+}
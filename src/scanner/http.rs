@@ -0,0 +1,265 @@
+use super::banner;
+use super::result::HttpInfo;
+use super::tls::TlsProbe;
+use super::Source;
+use async_std::io::{self, Read, Write, WriteExt};
+use log::debug;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+/// Most bytes of an answer that are read, enough for the head of the page
+/// where its title is.
+const MAX_RESPONSE_BYTES: usize = 64 * 1024;
+
+/// Asks web servers for `GET /` to see what they serve.
+#[derive(Debug, Clone)]
+pub struct HttpProbe {
+    tls: TlsProbe,
+    wait: Duration,
+    source: Source,
+}
+
+impl HttpProbe {
+    /// Gives up on answers and TLS handshakes that take longer than `wait`.
+    pub fn new(wait: Duration) -> Self {
+        Self {
+            tls: TlsProbe::new(wait),
+            wait,
+            source: Source::default(),
+        }
+    }
+
+    /// Connects from `source`, over plain HTTP and TLS alike.
+    #[must_use]
+    pub fn with_source(mut self, source: Source) -> Self {
+        self.tls = self.tls.with_source(source.clone());
+        self.source = source;
+        self
+    }
+
+    /// Sends `GET /` to `socket` with `hostname` as the `Host` header, the
+    /// IP being used without one. The request goes over TLS straight away
+    /// when the port is known to speak it, otherwise TLS is only tried when
+    /// the plain request got no HTTP answer. Returns `None` when neither
+    /// did.
+    pub async fn probe(
+        &self,
+        socket: SocketAddr,
+        hostname: Option<&str>,
+        speaks_tls: bool,
+        connect_timeout: Duration,
+    ) -> Option<HttpInfo> {
+        if !speaks_tls {
+            let request = request(socket, hostname, false);
+            match self.source.connect(socket, connect_timeout).await {
+                Ok(mut stream) => {
+                    if let Some(info) = self.exchange(&mut stream, &request, false).await {
+                        return Some(info);
+                    }
+                }
+                Err(e) => {
+                    debug!("HTTP probe to {} failed: {}", socket, e);
+                    return None;
+                }
+            }
+        }
+
+        let request = request(socket, hostname, true);
+        match self.tls.connect(socket, hostname, connect_timeout).await {
+            Ok(mut stream) => self.exchange(&mut stream, &request, true).await,
+            Err(e) => {
+                debug!("HTTPS probe to {} failed: {}", socket, e);
+                None
+            }
+        }
+    }
+
+    async fn exchange<S: Read + Write + Unpin>(
+        &self,
+        stream: &mut S,
+        request: &[u8],
+        tls: bool,
+    ) -> Option<HttpInfo> {
+        io::timeout(self.wait, stream.write_all(request))
+            .await
+            .ok()?;
+        let response = banner::read_response(stream, self.wait, MAX_RESPONSE_BYTES).await;
+        parse_response(&response, tls)
+    }
+}
+
+/// Builds the `GET /` request, leaving the port out of the `Host` header
+/// when it is the default one of the scheme.
+fn request(socket: SocketAddr, hostname: Option<&str>, tls: bool) -> Vec<u8> {
+    let mut host = match (hostname, socket) {
+        (Some(hostname), _) => hostname.to_owned(),
+        (None, SocketAddr::V4(socket)) => socket.ip().to_string(),
+        (None, SocketAddr::V6(socket)) => format!("[{}]", socket.ip()),
+    };
+    if socket.port() != if tls { 443 } else { 80 } {
+        host = format!("{host}:{}", socket.port());
+    }
+    format!(
+        "GET / HTTP/1.1\r\nHost: {host}\r\nUser-Agent: Azula\r\nAccept: */*\r\nConnection: close\r\n\r\n"
+    )
+    .into_bytes()
+}
+
+/// Picks the status, headers and title out of an HTTP response. Returns
+/// `None` when `response` isn't one.
+fn parse_response(response: &[u8], tls: bool) -> Option<HttpInfo> {
+    let (head, body) = match find(response, b"\r\n\r\n") {
+        Some(end) => (&response[..end], &response[end + 4..]),
+        None => (response, &response[response.len()..]),
+    };
+    let head = String::from_utf8_lossy(head);
+    let mut lines = head.lines();
+
+    let mut status_line = lines.next()?.split_whitespace();
+    if !status_line.next()?.starts_with("HTTP/") {
+        return None;
+    }
+    let status = status_line.next()?.parse().ok()?;
+
+    let mut info = HttpInfo {
+        tls,
+        status,
+        server: None,
+        title: title(body),
+        location: None,
+        content_length: body.len() as u64,
+    };
+    for (name, value) in lines.filter_map(|line| line.split_once(':')) {
+        let value = banner::sanitize(value.as_bytes());
+        if name.eq_ignore_ascii_case("server") {
+            info.server = Some(value);
+        } else if name.eq_ignore_ascii_case("location") {
+            info.location = Some(value);
+        } else if name.eq_ignore_ascii_case("content-length") {
+            if let Ok(length) = value.parse() {
+                info.content_length = length;
+            }
+        }
+    }
+    Some(info)
+}
+
+/// The text of the `<title>` element of a page, with its whitespace
+/// collapsed.
+fn title(body: &[u8]) -> Option<String> {
+    let lowercase = body.to_ascii_lowercase();
+    let tag = find(&lowercase, b"<title")?;
+    let start = tag + find(&lowercase[tag..], b">")? + 1;
+    let end = start + find(&lowercase[start..], b"</title").unwrap_or(lowercase.len() - start);
+
+    let title = String::from_utf8_lossy(&body[start..end])
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ");
+    let title = banner::sanitize(title.as_bytes());
+    (!title.is_empty()).then_some(title)
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_response, HttpProbe};
+    use async_std::task::block_on;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::sync::{mpsc, Arc};
+    use std::time::Duration;
+
+    #[test]
+    fn parses_redirects() {
+        let info = parse_response(
+            b"HTTP/1.1 301 Moved Permanently\r\nserver: nginx/1.25.3\r\nLOCATION: https://azula.test/\r\nContent-Length: 162\r\n\r\n<html>\r\n<head><TITLE>301 Moved\r\n  Permanently</title></head>",
+            false,
+        )
+        .unwrap();
+
+        assert_eq!(info.status, 301);
+        assert_eq!(info.server.as_deref(), Some("nginx/1.25.3"));
+        assert_eq!(info.location.as_deref(), Some("https://azula.test/"));
+        assert_eq!(info.title.as_deref(), Some("301 Moved Permanently"));
+        assert_eq!(info.content_length, 162);
+        assert_eq!(parse_response(b"SSH-2.0-OpenSSH_9.6\r\n", false), None);
+    }
+
+    #[test]
+    fn sends_the_hostname() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let (sender, requests) = mpsc::channel();
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0u8; 1024];
+            let size = stream.read(&mut request).unwrap();
+            let _ = sender.send(String::from_utf8_lossy(&request[..size]).into_owned());
+            let _ = stream.write_all(b"HTTP/1.0 200 OK\r\n\r\n<title>Azula</title>");
+        });
+
+        let info = block_on(HttpProbe::new(Duration::from_millis(500)).probe(
+            address,
+            Some("azula.test"),
+            false,
+            Duration::from_secs(1),
+        ))
+        .expect("the test server answers");
+
+        assert!(!info.tls);
+        assert_eq!(info.status, 200);
+        assert_eq!(info.title.as_deref(), Some("Azula"));
+        assert_eq!(info.content_length, 20);
+        let request = requests.recv().unwrap();
+        assert!(request.starts_with("GET / HTTP/1.1\r\n"));
+        assert!(request.contains(&format!("\r\nHost: azula.test:{}\r\n", address.port())));
+    }
+
+    #[test]
+    fn falls_back_to_tls() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let config = rustls::ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![rustls::Certificate(
+                    include_bytes!("../../fixtures/tls/cert.der").to_vec(),
+                )],
+                rustls::PrivateKey(include_bytes!("../../fixtures/tls/key.der").to_vec()),
+            )
+            .unwrap();
+        let config = Arc::new(config);
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut connection = rustls::ServerConnection::new(Arc::clone(&config)).unwrap();
+                let mut tls = rustls::Stream::new(&mut connection, &mut stream);
+                let mut request = [0u8; 1024];
+                if matches!(tls.read(&mut request), Ok(size) if size > 0) {
+                    let _ = tls.write_all(b"HTTP/1.1 204 No Content\r\nServer: azula\r\n\r\n");
+                    tls.conn.send_close_notify();
+                    let _ = tls.flush();
+                }
+            }
+        });
+
+        let info = block_on(HttpProbe::new(Duration::from_millis(500)).probe(
+            address,
+            None,
+            false,
+            Duration::from_secs(1),
+        ))
+        .expect("the test server answers over TLS");
+
+        assert!(info.tls);
+        assert_eq!(info.status, 204);
+        assert_eq!(info.server.as_deref(), Some("azula"));
+    }
+}
@@ -1,48 +1,135 @@
 use crate::generate::get_parsed_data;
 use crate::port::PortStrategy;
+use crate::targets::TargetSet;
 use log::debug;
 
+mod banner;
+
+mod builder;
+pub use builder::{BuildError, ScannerBuilder};
+
+mod checkpoint;
+pub use checkpoint::ResumeFile;
+use checkpoint::{Checkpoint, Progress, CHECKPOINT_INTERVAL};
+
+mod checksum;
+
+mod congestion;
+use congestion::{CongestionWindow, ProbeOutcome, EXHAUSTED_TRIES};
+
+mod discovery;
+pub use discovery::Discovery;
+
+mod event;
+pub use event::{EventSink, ScanEvent};
+
+mod http;
+pub use http::HttpProbe;
+
+mod probes;
+pub use probes::ServiceProbes;
+
+mod proxy;
+pub use proxy::{Proxy, ProxyKind};
+
+mod rate;
+use rate::RateLimiter;
+
+mod result;
+pub use result::{
+    HostResult, HostStatus, HttpInfo, PortResult, PortState, Protocol, ScanInfo, ScanReport,
+    ScanType, ScriptOutput, Service, TlsInfo, MAX_LISTED_TARGETS,
+};
+
+mod rounds;
+use rounds::Rounds;
+
+mod service;
+pub use service::ServiceDetector;
+
 mod socket;
 use socket::SocketIterator;
 
+mod source;
+pub use source::Source;
+
+#[cfg(target_os = "linux")]
+mod syn;
+#[cfg(target_os = "linux")]
+pub use syn::SynScanner;
+
+mod timing;
+use timing::HostTimeouts;
+
+mod tls;
+pub use timing::{DEFAULT_MAX_RTT_TIMEOUT, DEFAULT_MIN_RTT_TIMEOUT};
+pub use tls::TlsProbe;
+
 use async_std::net::TcpStream;
 use async_std::prelude::*;
 use async_std::{io, net::UdpSocket};
 use colored::Colorize;
 use futures::stream::FuturesUnordered;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, VecDeque};
 use std::{
-    collections::HashSet,
+    collections::HashMap,
     net::{IpAddr, Shutdown, SocketAddr},
     num::NonZeroU8,
-    time::Duration,
+    sync::atomic::{AtomicBool, Ordering},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime},
 };
 
 /// The class for the scanner
-/// IP is data type IpAddr and is the IP address
+/// IPs is the set of IP addresses to scan, which is never expanded
 /// start & end is where the port scan starts and ends
-/// batch_size is how many ports at a time should be scanned
+/// batch_size is the most ports at a time that should be scanned, the scanner starts lower and works its way up while the network keeps up
 /// Timeout is the time Azula should wait before declaring a port closed. As datatype Duration.
+/// Once a host has answered a few times its timeout is derived from the measured round trip time instead.
 /// greppable is whether or not Azula should print things, or wait until the end to print only the ip and open ports.
+/// quiet keeps it from printing anything at all, for embedding it, see `ScannerBuilder`.
 #[cfg(not(tarpaulin_include))]
 #[derive(Debug)]
 pub struct Scanner {
-    ips: Vec<IpAddr>,
+    ips: TargetSet,
     batch_size: u16,
     timeout: Duration,
+    timeouts: HostTimeouts,
     tries: NonZeroU8,
     greppable: bool,
-    port_strategy: PortStrategy,
+    quiet: bool,
+    /// The ports to scan over each protocol, in the order they are scanned.
+    targets: Vec<(Protocol, PortStrategy)>,
     accessible: bool,
     exclude_ports: Vec<u16>,
-    udp: bool,
+    event_sink: Option<Arc<dyn EventSink>>,
+    reported_states: Vec<PortState>,
+    min_rate: Option<u32>,
+    max_rate: Option<u32>,
+    scan_delay: Duration,
+    discovery: Option<Discovery>,
+    banner_wait: Option<Duration>,
+    services: Option<ServiceDetector>,
+    tls: Option<TlsProbe>,
+    http: Option<HttpProbe>,
+    hostnames: HashMap<IpAddr, String>,
+    source: Source,
+    resume: Option<ResumeFile>,
+    stop: Option<Arc<AtomicBool>>,
+    max_scan_time: Option<Duration>,
+    host_timeout: Option<Duration>,
+    /// The seed the order of the sockets is drawn from, when they are
+    /// permuted.
+    permutation: Option<u64>,
+    #[cfg(target_os = "linux")]
+    syn: Option<Arc<SynScanner>>,
 }
 
 // Allowing too many arguments for clippy.
 #[allow(clippy::too_many_arguments)]
 impl Scanner {
     pub fn new(
-        ips: &[IpAddr],
+        ips: TargetSet,
         batch_size: u16,
         timeout: Duration,
         tries: u8,
@@ -55,73 +142,808 @@ impl Scanner {
         Self {
             batch_size,
             timeout,
+            timeouts: HostTimeouts::new(timeout, DEFAULT_MIN_RTT_TIMEOUT, DEFAULT_MAX_RTT_TIMEOUT),
             tries: NonZeroU8::new(std::cmp::max(tries, 1)).unwrap(),
             greppable,
-            port_strategy,
-            ips: ips.iter().map(ToOwned::to_owned).collect(),
+            quiet: false,
+            targets: vec![(
+                if udp { Protocol::Udp } else { Protocol::Tcp },
+                port_strategy,
+            )],
+            ips,
             accessible,
             exclude_ports,
-            udp,
+            event_sink: None,
+            reported_states: vec![PortState::Open],
+            min_rate: None,
+            max_rate: None,
+            scan_delay: Duration::ZERO,
+            discovery: None,
+            banner_wait: None,
+            services: None,
+            tls: None,
+            http: None,
+            hostnames: HashMap::new(),
+            source: Source::default(),
+            resume: None,
+            stop: None,
+            max_scan_time: None,
+            host_timeout: None,
+            permutation: None,
+            #[cfg(target_os = "linux")]
+            syn: None,
+        }
+    }
+
+    /// Keeps ports found in any of `states` in the `ScanReport`, on top of
+    /// the open ones which are always kept.
+    #[must_use]
+    pub fn with_reported_states(mut self, states: &[PortState]) -> Self {
+        for state in states {
+            if !self.reported_states.contains(state) {
+                self.reported_states.push(*state);
+            }
         }
+        self
+    }
+
+    /// Bounds the timeouts derived from the round trip times measured on
+    /// each host. The configured timeout is still used for hosts that have
+    /// not answered often enough yet.
+    #[must_use]
+    pub fn with_rtt_timeout_bounds(mut self, min: Duration, max: Duration) -> Self {
+        self.timeouts = HostTimeouts::new(self.timeout, min, max);
+        self
+    }
+
+    /// Keeps the number of probes sent per second between `min` and `max`,
+    /// for both TCP and UDP scans.
+    #[must_use]
+    pub fn with_rate_limits(mut self, min: Option<u32>, max: Option<u32>) -> Self {
+        self.min_rate = min;
+        self.max_rate = max;
+        self
+    }
+
+    /// Waits at least `delay` between two probes.
+    #[must_use]
+    pub fn with_scan_delay(mut self, delay: Duration) -> Self {
+        self.scan_delay = delay;
+        self
+    }
+
+    /// Pings every target with `discovery` first and only scans the ports of
+    /// the ones that answered, the others are reported as down.
+    #[must_use]
+    pub fn with_discovery(mut self, discovery: Discovery) -> Self {
+        self.discovery = Some(discovery);
+        self
+    }
+
+    /// Reads the banner of open TCP ports, waiting up to `wait` for the
+    /// service to say something.
+    #[must_use]
+    pub fn with_banners(mut self, wait: Duration) -> Self {
+        self.banner_wait = Some(wait);
+        self
+    }
+
+    /// Identifies the services listening on the open ports with `services`
+    /// once the port scan is done.
+    #[must_use]
+    pub fn with_service_detection(mut self, services: ServiceDetector) -> Self {
+        self.services = Some(services);
+        self
+    }
+
+    /// Shakes hands with every open TCP port through `tls` once the port
+    /// scan is done, recording the TLS session and certificate.
+    #[must_use]
+    pub fn with_tls_probe(mut self, tls: TlsProbe) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// Sends `GET /` to every open TCP port through `http` once the port
+    /// scan is done, recording how web servers answer.
+    #[must_use]
+    pub fn with_http_probe(mut self, http: HttpProbe) -> Self {
+        self.http = Some(http);
+        self
+    }
+
+    /// Also scans the ports of `port_strategy` over `protocol`, on top of
+    /// the ones given to `new`. Ports are scanned one protocol after the
+    /// other, in the order they were added.
+    #[must_use]
+    pub fn with_protocol_ports(mut self, protocol: Protocol, port_strategy: PortStrategy) -> Self {
+        self.targets.push((protocol, port_strategy));
+        self
+    }
+
+    /// Tells which hostname each IP was resolved from, see
+    /// `parse_addresses_with_hostnames`. They end up in the `ScanReport`
+    /// and are sent as SNI during TLS handshakes.
+    #[must_use]
+    pub fn with_hostnames(mut self, hostnames: HashMap<IpAddr, String>) -> Self {
+        self.hostnames = hostnames;
+        self
+    }
+
+    /// Sends the connect and UDP probes from `source` instead of letting
+    /// the kernel pick the address, interface and port.
+    #[must_use]
+    pub fn with_source(mut self, source: Source) -> Self {
+        self.source = source;
+        self
+    }
+
+    /// Saves the progress of the scan to `resume` as it goes, and carries
+    /// on from the checkpoint already in it. The port strategies have to
+    /// be picked with the seed of `resume` for the checkpoint to be used.
+    #[must_use]
+    pub fn with_resume_file(mut self, resume: ResumeFile) -> Self {
+        self.resume = Some(resume);
+        self
+    }
+
+    /// Stops the scan once `stop` is raised: no new probe is sent, the ones
+    /// in flight are waited for and the `ScanReport` holds what was found
+    /// until then, see `interrupt::stop_on_signals`.
+    #[must_use]
+    pub fn with_stop_signal(mut self, stop: Arc<AtomicBool>) -> Self {
+        self.stop = Some(stop);
+        self
+    }
+
+    /// Goes through the IP and port pairs of each protocol in a random order
+    /// drawn from `seed`, rather than through every IP for one port before
+    /// moving on to the next port, see `SocketIterator::permuted`.
+    #[must_use]
+    pub fn with_permuted_sockets(mut self, seed: u64) -> Self {
+        self.permutation = Some(seed);
+        self
+    }
+
+    /// Gives the whole run, discovery and inspection included, `limit` to
+    /// finish. Once it is up no new probe is sent, the ones in flight are
+    /// dropped and the hosts that weren't done are marked incomplete. The
+    /// resume file is kept, so a later run can carry on from there.
+    #[must_use]
+    pub fn with_max_scan_time(mut self, limit: Duration) -> Self {
+        self.max_scan_time = Some(limit);
+        self
+    }
+
+    /// Gives up on the ports of a host `limit` after its first probe was
+    /// sent, marking it incomplete. The probes already sent are still
+    /// waited for.
+    #[must_use]
+    pub fn with_host_timeout(mut self, limit: Duration) -> Self {
+        self.host_timeout = Some(limit);
+        self
+    }
+
+    /// Scans TCP ports with half-open SYN probes sent through `syn` instead
+    /// of full connects.
+    #[cfg(target_os = "linux")]
+    #[must_use]
+    pub fn with_syn_scan(mut self, syn: Arc<SynScanner>) -> Self {
+        self.syn = Some(syn);
+        self
+    }
+
+    /// Sends the events of every scan run by this scanner to `sink` as
+    /// they happen, see `ScanEvent`.
+    #[must_use]
+    pub fn with_event_sink(mut self, sink: Arc<dyn EventSink>) -> Self {
+        self.event_sink = Some(sink);
+        self
     }
 
     /// Runs scan_range with chunk sizes
     /// If you want to run Azula normally, this is the entry point used
-    /// Returns all open ports as `Vec<u16>`
-    pub async fn run(&self) -> Vec<SocketAddr> {
-        let ports: Vec<u16> = self
-            .port_strategy
-            .order()
+    /// Returns a `ScanReport` with an entry per target, or per target
+    /// something was found on when there are too many of them to list
+    pub async fn run(&self) -> ScanReport {
+        let started = Instant::now();
+        let deadline = self.max_scan_time.map(|limit| started + limit);
+        let targets: Vec<(Protocol, Vec<u16>)> = self
+            .targets
             .iter()
-            .filter(|&port| !self.exclude_ports.contains(port))
-            .copied()
+            .map(|(protocol, port_strategy)| {
+                let ports = port_strategy
+                    .order()
+                    .into_iter()
+                    .filter(|port| !self.exclude_ports.contains(port))
+                    .collect();
+                (*protocol, ports)
+            })
             .collect();
-        let mut socket_iterator: SocketIterator = SocketIterator::new(&self.ips, &ports);
-        let mut open_sockets: Vec<SocketAddr> = Vec::new();
+        let port_count: usize = targets.iter().map(|(_, ports)| ports.len()).sum();
+        let fingerprint = checkpoint::fingerprint(self.ips.ranges(), &targets, self.permutation);
+        let resumed = self.resumed_checkpoint(fingerprint);
+        let started_at = resumed.map_or_else(
+            || {
+                SystemTime::now()
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .map_or(0, |since_epoch| since_epoch.as_secs())
+            },
+            |checkpoint| checkpoint.started_at,
+        );
+        let elapsed_before = resumed.map_or(Duration::ZERO, |checkpoint| checkpoint.elapsed);
+        // The hosts with sockets taken from the order that are not done
+        // yet, no more than there are probes in flight and sockets to send
+        // again, and how many sockets were taken. Where the scan is at with
+        // the other hosts is worked out from the latter, see `Rounds`.
+        let mut unfinished: HashMap<IpAddr, usize> = HashMap::new();
+        let mut taken = 0;
+        // Shared with the probes, every try of which takes a token.
+        let rate = Mutex::new(RateLimiter::new(
+            self.min_rate,
+            self.max_rate,
+            self.scan_delay,
+        ));
         let mut ftrs = FuturesUnordered::new();
-        let mut errors: HashSet<String> = HashSet::new();
+        // Sockets whose probe ran out of local resources, sent again ahead
+        // of the others, and how many times that happened to each one.
+        let mut retries: VecDeque<(usize, (Protocol, SocketAddr))> = VecDeque::new();
+        let mut exhausted: HashMap<usize, u8> = HashMap::new();
+        let mut given_up = 0;
+        let mut congestion = CongestionWindow::new(usize::from(self.batch_size));
         let udp_map = get_parsed_data();
 
-        for _ in 0..self.batch_size {
-            if let Some(socket) = socket_iterator.next() {
-                ftrs.push(self.scan_socket(socket, udp_map.clone()));
-            } else {
-                break;
-            }
-        }
-
         debug!("Start scanning sockets. \nBatch size {}\nNumber of ip-s {}\nNumber of ports {}\nTargets all together {} ",
             self.batch_size,
             self.ips.len(),
-            &ports.len(),
-            (self.ips.len() * ports.len()));
+            port_count,
+            self.ips.len().saturating_mul(port_count as u128));
+        self.emit(|| ScanEvent::ScanStarted {
+            targets: self.ips.len(),
+            ports: port_count,
+            started_at,
+        });
 
-        while let Some(result) = ftrs.next().await {
-            if let Some(socket) = socket_iterator.next() {
-                ftrs.push(self.scan_socket(socket, udp_map.clone()));
+        let (mut hosts, live): (BTreeMap<IpAddr, HostResult>, TargetSet) = match resumed {
+            Some(checkpoint) => (
+                checkpoint
+                    .hosts
+                    .iter()
+                    .map(|host| (host.ip, host.clone()))
+                    .collect(),
+                checkpoint.live.clone(),
+            ),
+            None => (BTreeMap::new(), self.discover_hosts(deadline).await),
+        };
+        // Hosts discovery didn't get to can't be told apart from the ones
+        // that are down, there is nothing worth carrying on from.
+        let discovered = !self.stopped() && !past(deadline);
+        let hosts_down = if discovered {
+            self.ips.len().saturating_sub(live.len())
+        } else {
+            0
+        };
+        let mut progress =
+            resumed.map_or_else(Progress::default, |checkpoint| checkpoint.progress.clone());
+        let mut last_checkpoint = Instant::now();
+        let checkpoint =
+            |seed, progress: &Progress, hosts: &BTreeMap<IpAddr, HostResult>| Checkpoint {
+                seed,
+                fingerprint,
+                started_at,
+                elapsed: elapsed_before + started.elapsed(),
+                live: live.clone(),
+                progress: progress.clone(),
+                hosts: hosts.values().cloned().collect(),
+            };
+        let finished_before = progress.clone();
+        let mut rounds = Rounds::new(&live, &targets, self.permutation, self.host_timeout);
+        let mut socket_iterator = targets
+            .iter()
+            .flat_map(|(protocol, ports)| {
+                let sockets = match self.permutation {
+                    Some(seed) => SocketIterator::permuted(
+                        &live,
+                        ports,
+                        &mut rounds::order_rng(seed, *protocol),
+                    ),
+                    None => SocketIterator::new(&live, ports),
+                };
+                sockets.map(move |socket| (*protocol, socket))
+            })
+            .enumerate()
+            .peekable();
+
+        loop {
+            // Falling behind the minimum rate lets us go past the congestion
+            // window, but never past what it may grow to.
+            let in_flight = if rate::lock(&rate).behind_min_rate() {
+                congestion.max()
+            } else {
+                congestion.window()
+            };
+            while ftrs.len() < in_flight
+                && !self.stopped()
+                && !past(deadline)
+                && congestion.pause().is_none()
+            {
+                let (index, (protocol, socket)) = match retries.pop_front() {
+                    Some(retry) => retry,
+                    None => {
+                        let Some((index, (protocol, socket))) = socket_iterator.next() else {
+                            break;
+                        };
+                        taken = index + 1;
+                        rounds.reach(index);
+                        if finished_before.is_finished(index) {
+                            // Done by the run this one carries on from, gone
+                            // through only to keep track of the rounds.
+                            continue;
+                        }
+                        *unfinished.entry(socket.ip()).or_default() += 1;
+                        (index, (protocol, socket))
+                    }
+                };
+                let ip = socket.ip();
+                if rounds.times_out_at(index, ip) {
+                    match hosts.get_mut(&ip) {
+                        Some(host) => self.mark_incomplete(host),
+                        // Flagged at the end if it makes the report.
+                        None => self.emit(|| ScanEvent::HostIncomplete { ip }),
+                    }
+                }
+                if rounds.timed_out(index, ip) || hosts.get(&ip).is_some_and(|host| host.incomplete)
+                {
+                    // Given up on, the socket is as good as done.
+                    progress.finish(index);
+                    finish_socket(&mut unfinished, ip);
+                    continue;
+                }
+                if !rate::lock(&rate).try_acquire() {
+                    retries.push_front((index, (protocol, socket)));
+                    break;
+                }
+                if rounds.is_first_round(index) && !exhausted.contains_key(&index) {
+                    self.emit(|| ScanEvent::HostStarted { ip });
+                }
+                ftrs.push(self.probe(index, socket, protocol, udp_map.clone(), &rate));
+            }
+
+            // When the rate limit or a pause is what holds us back, wake up
+            // as soon as the next probe may be sent rather than waiting for
+            // a result.
+            let held_back = ftrs.len() < in_flight
+                && (!retries.is_empty() || socket_iterator.peek().is_some())
+                && !self.stopped()
+                && !past(deadline);
+            let next = match congestion.pause().or_else(|| rate::lock(&rate).wait_time()) {
+                Some(wait) if held_back && ftrs.is_empty() => {
+                    until(deadline, async_std::task::sleep(wait)).await;
+                    continue;
+                }
+                Some(wait) if held_back => {
+                    match until(deadline, async_std::future::timeout(wait, ftrs.next())).await {
+                        Some(Ok(next)) => next,
+                        _ => continue,
+                    }
+                }
+                _ => match until(deadline, ftrs.next()).await {
+                    Some(next) => next,
+                    None => break,
+                },
+            };
+            let Some((index, protocol, socket, result)) = next else {
+                break;
+            };
+
+            if let Err(e) = &result {
+                if ProbeOutcome::is_resource_exhaustion(e) {
+                    let max = congestion.max();
+                    congestion.record(ProbeOutcome::ResourceExhausted);
+                    if congestion.max() < max {
+                        crate::warning!(
+                            format!(
+                                "Ran out of local resources ({e}), sending at most {} probes at once until that stops happening. A lower batch size or a higher --ulimit avoids this.",
+                                congestion.max()
+                            ),
+                            self.silent(),
+                            self.accessible
+                        );
+                    }
+                    let tries = exhausted.entry(index).or_default();
+                    *tries += 1;
+                    if *tries < EXHAUSTED_TRIES {
+                        // Sent again once the probes in flight had time to
+                        // give their sockets back.
+                        retries.push_back((index, (protocol, socket)));
+                        continue;
+                    }
+                    given_up += 1;
+                    self.mark_incomplete(self.host_result(&mut hosts, socket.ip()));
+                }
             }
+            progress.finish(index);
+            exhausted.remove(&index);
+            finish_socket(&mut unfinished, socket.ip());
 
             match result {
-                Ok(socket) => open_sockets.push(socket),
+                Ok(port_result) => {
+                    congestion.record(match port_result.state {
+                        PortState::Open | PortState::Closed => ProbeOutcome::Answered,
+                        PortState::Filtered | PortState::OpenFiltered => ProbeOutcome::Dropped,
+                    });
+                    self.emit(|| {
+                        let ip = socket.ip();
+                        let port = port_result.clone();
+                        match port.state {
+                            PortState::Open => ScanEvent::PortOpen { ip, port },
+                            PortState::Closed => ScanEvent::PortClosed { ip, port },
+                            PortState::Filtered | PortState::OpenFiltered => {
+                                ScanEvent::PortFiltered { ip, port }
+                            }
+                        }
+                    });
+                    if self.reported_states.contains(&port_result.state) {
+                        self.host_result(&mut hosts, socket.ip())
+                            .ports
+                            .push(port_result);
+                    }
+                }
                 Err(e) => {
-                    let error_string = e.to_string();
-                    if errors.len() < self.ips.len() * 1000 {
-                        errors.insert(error_string);
+                    let error = e.to_string();
+                    let host = self.host_result(&mut hosts, socket.ip());
+                    if !host.errors.contains(&error) {
+                        host.errors.push(error);
                     }
                 }
             }
+
+            if last_checkpoint.elapsed() >= CHECKPOINT_INTERVAL {
+                self.save_checkpoint(|seed| checkpoint(seed, &progress, &hosts));
+                last_checkpoint = Instant::now();
+            }
+        }
+
+        if given_up > 0 {
+            crate::warning!(
+                format!("Gave up on {given_up} sockets that kept running out of local resources, their hosts are incomplete."),
+                self.silent(),
+                self.accessible
+            );
+        }
+        for host in hosts.values_mut() {
+            host.ports.sort_by_key(|port| (port.protocol, port.port));
+        }
+        let inspecting = self.services.is_some() || self.tls.is_some() || self.http.is_some();
+        let out_of_time = past(deadline);
+        if discovered && (inspecting || self.stopped() || out_of_time) {
+            // Inspecting the ports can take a while too, a run picking up
+            // from here goes straight to it.
+            self.save_checkpoint(|seed| checkpoint(seed, &progress, &hosts));
+        }
+        if self.ips.len() <= MAX_LISTED_TARGETS {
+            for ip in &self.ips {
+                let host = self.host_result(&mut hosts, ip);
+                if discovered && !live.contains(ip) {
+                    host.status = HostStatus::Down;
+                } else if out_of_time && !discovered {
+                    // Discovery didn't get to all of them, none were scanned.
+                    self.mark_incomplete(host);
+                }
+            }
+        }
+        for host in hosts.values_mut() {
+            // The event went out when the host timeout gave up on it.
+            if rounds.timed_out_by(host.ip, taken) {
+                host.incomplete = true;
+            }
+            // The probes still in flight were dropped along with the ones
+            // never sent.
+            if out_of_time
+                && (rounds.left_behind(host.ip, taken) || unfinished.contains_key(&host.ip))
+            {
+                self.mark_incomplete(host);
+            }
+        }
+        let hosts_unscanned = match (out_of_time, discovered) {
+            (false, _) => 0,
+            (true, true) => live.len() - rounds.reached(taken),
+            (true, false) => self.ips.len(),
+        };
+        let mut hosts: Vec<HostResult> = hosts.into_values().collect();
+        let mut complete = !self.stopped() && !out_of_time;
+        if inspecting && complete {
+            complete = self.inspect_open_ports(&mut hosts, deadline).await;
+        }
+        if let (Some(resume), true) = (&self.resume, complete) {
+            if let Err(e) = resume.remove() {
+                debug!("Failed to remove the resume file: {}", e);
+            }
+        }
+
+        debug!("Hosts scanned: {:?}", &hosts);
+        ScanReport {
+            scans: targets
+                .iter()
+                .map(|(protocol, ports)| ScanInfo::new(self.scan_type(*protocol), *protocol, ports))
+                .collect(),
+            hosts,
+            targets: self.ips.len(),
+            hosts_down,
+            hosts_unscanned,
+            started_at,
+            elapsed: elapsed_before + started.elapsed(),
+            interrupted: self.stopped(),
+        }
+    }
+
+    /// How the ports of `protocol` are scanned.
+    fn scan_type(&self, protocol: Protocol) -> ScanType {
+        match protocol {
+            Protocol::Udp => ScanType::Udp,
+            #[cfg(target_os = "linux")]
+            Protocol::Tcp if self.syn.is_some() => ScanType::Syn,
+            Protocol::Tcp => ScanType::Connect,
+        }
+    }
+
+    /// Whether nothing should be printed, the results being read from the
+    /// `ScanReport` instead.
+    fn silent(&self) -> bool {
+        self.greppable || self.quiet
+    }
+
+    fn stopped(&self) -> bool {
+        self.stop
+            .as_ref()
+            .is_some_and(|stop| stop.load(Ordering::SeqCst))
+    }
+
+    /// The result of `ip` in `hosts`, added the first time there is
+    /// something to report about the host.
+    fn host_result<'h>(
+        &self,
+        hosts: &'h mut BTreeMap<IpAddr, HostResult>,
+        ip: IpAddr,
+    ) -> &'h mut HostResult {
+        hosts.entry(ip).or_insert_with(|| HostResult {
+            hostname: self.hostnames.get(&ip).cloned(),
+            ..HostResult::new(ip)
+        })
+    }
+
+    /// Marks `host` as given up on, see `HostResult::incomplete`.
+    fn mark_incomplete(&self, host: &mut HostResult) {
+        if !host.incomplete {
+            host.incomplete = true;
+            self.emit(|| ScanEvent::HostIncomplete { ip: host.ip });
+        }
+    }
+
+    /// Returns the targets left to scan once the ones that don't answer
+    /// discovery are left out, when there is discovery.
+    async fn discover_hosts(&self, deadline: Option<Instant>) -> TargetSet {
+        let Some(discovery) = &self.discovery else {
+            return self.ips.clone();
+        };
+
+        let alive = self.discover(discovery, deadline).await;
+        if self.stopped() || past(deadline) {
+            // Not every host was pinged, none of them is known to be down.
+            return TargetSet::new();
+        }
+        if self.event_sink.is_some() {
+            for ip in self.ips.iter().filter(|ip| !alive.contains(*ip)) {
+                self.emit(|| ScanEvent::HostDown { ip });
+            }
         }
-        debug!("Typical socket connection errors {:?}", errors);
-        debug!("Open Sockets found: {:?}", &open_sockets);
-        open_sockets
+        debug!("{} of {} hosts are up", alive.len(), self.ips.len());
+        alive
+    }
+
+    /// The checkpoint of the resume file, if there is one and it was saved
+    /// by a scan of the same targets and ports.
+    fn resumed_checkpoint(&self, fingerprint: u64) -> Option<&Checkpoint> {
+        let checkpoint = self.resume.as_ref()?.checkpoint()?;
+        if checkpoint.fingerprint != fingerprint {
+            crate::warning!(
+                "The resume file was saved by a scan of other targets or ports, starting over.",
+                self.silent(),
+                self.accessible
+            );
+            return None;
+        }
+        Some(checkpoint)
+    }
+
+    /// Saves the checkpoint `checkpoint` builds from the seed of the
+    /// resume file, if there is one.
+    fn save_checkpoint(&self, checkpoint: impl FnOnce(u64) -> Checkpoint) {
+        let Some(resume) = &self.resume else {
+            return;
+        };
+        if let Err(e) = resume.save(&checkpoint(resume.seed())) {
+            crate::warning!(
+                format!(
+                    "Failed to save the progress to {}: {e}",
+                    resume.path().display()
+                ),
+                self.silent(),
+                self.accessible
+            );
+        }
+    }
+
+    /// Pings every target with `discovery` and returns the ones that
+    /// answered, keeping as many sockets busy as the batch size allows.
+    async fn discover(&self, discovery: &Discovery, deadline: Option<Instant>) -> TargetSet {
+        let hosts_in_flight = (usize::from(self.batch_size) / discovery.sockets_per_host()).max(1);
+        let mut targets = self.ips.iter();
+        let mut pings = FuturesUnordered::new();
+        let mut alive = Vec::new();
+
+        loop {
+            while pings.len() < hosts_in_flight && !self.stopped() && !past(deadline) {
+                let Some(ip) = targets.next() else {
+                    break;
+                };
+                pings.push(async move { (ip, discovery.is_alive(ip).await) });
+            }
+
+            let Some(Some((ip, is_alive))) = until(deadline, pings.next()).await else {
+                break;
+            };
+            if is_alive {
+                alive.push(ip);
+            }
+        }
+
+        alive.into_iter().collect()
+    }
+
+    /// Runs the probes that need an open port, service detection, TLS and
+    /// HTTP, against every open port, probing as many ports at once as the
+    /// batch size allows.
+    ///
+    /// Returns whether every port was inspected, the hosts left with ports
+    /// to inspect once `deadline` passed are marked incomplete.
+    async fn inspect_open_ports(
+        &self,
+        hosts: &mut [HostResult],
+        deadline: Option<Instant>,
+    ) -> bool {
+        let mut left: Vec<usize> = hosts
+            .iter()
+            .map(|host| {
+                host.ports
+                    .iter()
+                    .filter(|port| port.state == PortState::Open)
+                    .count()
+            })
+            .collect();
+        let mut targets = hosts.iter().enumerate().flat_map(|(host_index, host)| {
+            host.ports
+                .iter()
+                .enumerate()
+                .filter(|(_, port)| port.state == PortState::Open)
+                .map(move |(port_index, port)| {
+                    (
+                        host_index,
+                        port_index,
+                        host.ip,
+                        host.hostname.clone(),
+                        port.clone(),
+                    )
+                })
+        });
+        let mut inspections = FuturesUnordered::new();
+        let mut inspected = Vec::new();
+
+        loop {
+            while inspections.len() < usize::from(self.batch_size)
+                && !self.stopped()
+                && !past(deadline)
+            {
+                let Some((host_index, port_index, ip, hostname, port)) = targets.next() else {
+                    break;
+                };
+                inspections.push(async move {
+                    let port = self.inspect(ip, hostname.as_deref(), port).await;
+                    (host_index, port_index, port)
+                });
+            }
+
+            let Some(Some(result)) = until(deadline, inspections.next()).await else {
+                break;
+            };
+            left[result.0] -= 1;
+            inspected.push(result);
+        }
+
+        for (host_index, port_index, port) in inspected {
+            hosts[host_index].ports[port_index] = port;
+        }
+        if past(deadline) {
+            for (host, left) in hosts.iter_mut().zip(left.iter()) {
+                if *left > 0 {
+                    self.mark_incomplete(host);
+                }
+            }
+        }
+        left.iter().all(|left| *left == 0)
+    }
+
+    async fn inspect(
+        &self,
+        ip: IpAddr,
+        hostname: Option<&str>,
+        mut port: PortResult,
+    ) -> PortResult {
+        let socket = SocketAddr::new(ip, port.port);
+        let timeout = self.timeouts.timeout(ip);
+
+        if let Some(services) = &self.services {
+            port.service = services.detect(socket, port.protocol, timeout).await;
+            if let Some(service) = &port.service {
+                self.fmt_service(socket, service);
+            }
+        }
+        if let (Some(tls), Protocol::Tcp) = (&self.tls, port.protocol) {
+            port.tls = tls.probe(socket, hostname, timeout).await;
+            if let Some(info) = &port.tls {
+                self.fmt_tls(socket, info);
+            }
+        }
+        if let (Some(http), Protocol::Tcp) = (&self.http, port.protocol) {
+            port.http = http
+                .probe(socket, hostname, port.tls.is_some(), timeout)
+                .await;
+            if let Some(info) = &port.http {
+                self.fmt_http(socket, info);
+            }
+        }
+
+        port
+    }
+
+    /// Hands the event built by `event` to the event sink, if there is one.
+    fn emit(&self, event: impl FnOnce() -> ScanEvent) {
+        if let Some(sink) = &self.event_sink {
+            sink.emit(&event());
+        }
+    }
+
+    /// Scans a socket and hands it back together with the outcome and its
+    /// index in the scan order, so the caller knows which host the result
+    /// belongs to, how far the scan got and what to send again.
+    async fn probe(
+        &self,
+        index: usize,
+        socket: SocketAddr,
+        protocol: Protocol,
+        udp_map: BTreeMap<Vec<u16>, Vec<u8>>,
+        rate: &Mutex<RateLimiter>,
+    ) -> (usize, Protocol, SocketAddr, io::Result<PortResult>) {
+        (
+            index,
+            protocol,
+            socket,
+            self.scan_socket(socket, protocol, udp_map, rate).await,
+        )
     }
 
     /// Given a socket, scan it self.tries times.
     /// Turns the address into a SocketAddr
     /// Deals with the `<result>` type
-    /// If it experiences error ErrorKind::Other then too many files are open and it Panics!
-    /// Else any other error, it returns the error in Result as a string
-    /// If no errors occur, it returns the port number in Result to signify the port is open.
+    /// Running out of file descriptors or other local resources hands the
+    /// error back straight away, the caller slows down and tries again.
+    /// A refused connection marks the port closed straight away, timeouts and
+    /// unreachable errors are retried and mark the port filtered once we run
+    /// out of tries.
+    /// Every try after the first waits for a token of `rate`, the caller
+    /// took the one of the first try.
+    /// Else any other error, it returns the error in Result
+    /// If the connection succeeds, it returns a `PortResult` to signify the port is open.
     /// This function mainly deals with the logic of Results handling.
     /// # Example
     ///
@@ -133,16 +955,32 @@ impl Scanner {
     async fn scan_socket(
         &self,
         socket: SocketAddr,
+        protocol: Protocol,
         udp_map: BTreeMap<Vec<u16>, Vec<u8>>,
-    ) -> io::Result<SocketAddr> {
-        if self.udp {
-            return self.scan_udp_socket(socket, udp_map).await;
+        rate: &Mutex<RateLimiter>,
+    ) -> io::Result<PortResult> {
+        if protocol == Protocol::Udp {
+            return self.scan_udp_socket(socket, udp_map, rate).await;
+        }
+        #[cfg(target_os = "linux")]
+        if let Some(syn) = &self.syn {
+            return self.scan_syn_socket(syn, socket, rate).await;
         }
 
         let tries = self.tries.get();
         for nr_try in 1..=tries {
+            if nr_try > 1 {
+                rate::acquire(rate).await;
+            }
+            let started = Instant::now();
             match self.connect(socket).await {
-                Ok(tcp_stream) => {
+                Ok(mut tcp_stream) => {
+                    let rtt = started.elapsed();
+                    self.timeouts.record(socket.ip(), rtt);
+                    let banner = match self.banner_wait {
+                        Some(wait) => banner::read_banner(&mut tcp_stream, wait).await,
+                        None => None,
+                    };
                     debug!(
                         "Connection was successful, shutting down stream {}",
                         &socket
@@ -150,20 +988,35 @@ impl Scanner {
                     if let Err(e) = tcp_stream.shutdown(Shutdown::Both) {
                         debug!("Shutdown stream error {}", &e);
                     }
-                    self.fmt_ports(socket);
+                    self.fmt_ports(socket, Protocol::Tcp, banner.as_deref());
 
                     debug!("Return Ok after {} tries", nr_try);
-                    return Ok(socket);
+                    let mut port =
+                        PortResult::new(socket.port(), Protocol::Tcp, PortState::Open, rtt);
+                    port.banner = banner;
+                    return Ok(port);
                 }
                 Err(e) => {
-                    let mut error_string = e.to_string();
+                    // Trying again right away would run into the same wall,
+                    // hand the error back so the scan can slow down instead.
+                    if ProbeOutcome::is_resource_exhaustion(&e) {
+                        return Err(e);
+                    }
 
-                    assert!(!error_string.to_lowercase().contains("too many open files"), "Too many open files. Please reduce batch size. The default is 5000. Try -b 2500.");
+                    match PortState::from_connect_error(e.kind()) {
+                        // A refused connection is a definite answer, asking again won't change it.
+                        Some(state) if state == PortState::Closed || nr_try == tries => {
+                            let rtt = started.elapsed();
+                            if state == PortState::Closed {
+                                self.timeouts.record(socket.ip(), rtt);
+                            }
+                            return Ok(PortResult::new(socket.port(), Protocol::Tcp, state, rtt));
+                        }
+                        _ => {}
+                    }
 
                     if nr_try == tries {
-                        error_string.push(' ');
-                        error_string.push_str(&socket.ip().to_string());
-                        return Err(io::Error::new(io::ErrorKind::Other, error_string));
+                        return Err(e);
                     }
                 }
             };
@@ -171,11 +1024,53 @@ impl Scanner {
         unreachable!();
     }
 
+    /// Same as `scan_socket` but with SYN probes, silence on every try
+    /// marks the port filtered.
+    #[cfg(target_os = "linux")]
+    async fn scan_syn_socket(
+        &self,
+        syn: &SynScanner,
+        socket: SocketAddr,
+        rate: &Mutex<RateLimiter>,
+    ) -> io::Result<PortResult> {
+        let timeout = self.timeouts.timeout(socket.ip());
+        for nr_try in 1..=self.tries.get() {
+            if nr_try > 1 {
+                rate::acquire(rate).await;
+            }
+            let started = Instant::now();
+            if let Some(state) = syn.probe(socket, timeout).await? {
+                let rtt = started.elapsed();
+                self.timeouts.record(socket.ip(), rtt);
+                let mut port = PortResult::new(socket.port(), Protocol::Tcp, state, rtt);
+                if state == PortState::Open {
+                    // Banners need a full connection, which only open ports get.
+                    if let Some(wait) = self.banner_wait {
+                        if let Ok(mut stream) = self.connect(socket).await {
+                            port.banner = banner::read_banner(&mut stream, wait).await;
+                            let _ = stream.shutdown(Shutdown::Both);
+                        }
+                    }
+                    self.fmt_ports(socket, Protocol::Tcp, port.banner.as_deref());
+                }
+                return Ok(port);
+            }
+        }
+
+        Ok(PortResult::new(
+            socket.port(),
+            Protocol::Tcp,
+            PortState::Filtered,
+            timeout,
+        ))
+    }
+
     async fn scan_udp_socket(
         &self,
         socket: SocketAddr,
         udp_map: BTreeMap<Vec<u16>, Vec<u8>>,
-    ) -> io::Result<SocketAddr> {
+        rate: &Mutex<RateLimiter>,
+    ) -> io::Result<PortResult> {
         let mut payload: Vec<u8> = Vec::new();
         for (key, value) in udp_map {
             if key.contains(&socket.port()) {
@@ -183,16 +1078,31 @@ impl Scanner {
             }
         }
 
+        let wait = self.timeouts.timeout(socket.ip());
         let tries = self.tries.get();
-        for _ in 1..=tries {
-            match self.udp_scan(socket, &payload, self.timeout).await {
-                Ok(true) => return Ok(socket),
-                Ok(false) => continue,
-                Err(e) => return Err(e),
+        for nr_try in 1..=tries {
+            if nr_try > 1 {
+                rate::acquire(rate).await;
+            }
+            let started = Instant::now();
+            if let Some(state) = self.udp_scan(socket, &payload, wait).await? {
+                return Ok(PortResult::new(
+                    socket.port(),
+                    Protocol::Udp,
+                    state,
+                    started.elapsed(),
+                ));
             }
         }
 
-        Ok(socket)
+        // Silence can either mean the service ignored our payload or that a
+        // firewall dropped it, so like nmap we can't tell open from filtered.
+        Ok(PortResult::new(
+            socket.port(),
+            Protocol::Udp,
+            PortState::OpenFiltered,
+            wait,
+        ))
     }
 
     /// Performs the connection to the socket with timeout
@@ -206,16 +1116,13 @@ impl Scanner {
     /// let socket = SocketAddr::new(ip, port);
     /// scanner.connect(socket);
     /// // returns Result which is either Ok(stream) for port is open, or Er for port is closed.
-    /// // Timeout occurs after self.timeout seconds
+    /// // Timeout occurs after the timeout picked for the host
     /// ```
     ///
     async fn connect(&self, socket: SocketAddr) -> io::Result<TcpStream> {
-        let stream = io::timeout(
-            self.timeout,
-            async move { TcpStream::connect(socket).await },
-        )
-            .await?;
-        Ok(stream)
+        self.source
+            .connect(socket, self.timeouts.timeout(socket.ip()))
+            .await
     }
 
     /// Binds to a UDP socket so we can send and receive packets
@@ -233,12 +1140,7 @@ impl Scanner {
     /// ```
     ///
     async fn udp_bind(&self, socket: SocketAddr) -> io::Result<UdpSocket> {
-        let local_addr = match socket {
-            SocketAddr::V4(_) => "0.0.0.0:0".parse::<SocketAddr>().unwrap(),
-            SocketAddr::V6(_) => "[::]:0".parse::<SocketAddr>().unwrap(),
-        };
-
-        UdpSocket::bind(local_addr).await
+        self.source.bind_udp(socket).await
     }
 
     /// Performs a UDP scan on the specified socket with a payload and wait duration
@@ -254,158 +1156,739 @@ impl Scanner {
     /// let payload = vec![0, 1, 2, 3];
     /// let wait = Duration::from_secs(1);
     /// let result = scanner.udp_scan(socket, payload, wait).await;
-    /// // returns Result which is either Ok(true) if response received, or Ok(false) if timed out.
+    /// // returns Ok(Some(Open)) if a response was received, Ok(Some(Closed)) if the host
+    /// // answered with ICMP port unreachable, Ok(Some(Filtered)) for other ICMP unreachable
+    /// // errors, or Ok(None) if timed out.
     /// // Err is returned for other I/O errors.
     async fn udp_scan(
         &self,
         socket: SocketAddr,
         payload: &[u8],
         wait: Duration,
-    ) -> io::Result<bool> {
+    ) -> io::Result<Option<PortState>> {
         match self.udp_bind(socket).await {
             Ok(udp_socket) => {
                 let mut buf = [0u8; 1024];
 
+                // Connecting the socket makes the kernel report ICMP errors
+                // for this destination back to us as errors on send and recv.
                 udp_socket.connect(socket).await?;
-                udp_socket.send(payload).await?;
+                let received = match udp_socket.send(payload).await {
+                    Ok(_) => io::timeout(wait, udp_socket.recv(&mut buf)).await,
+                    Err(e) => Err(e),
+                };
 
-                match io::timeout(wait, udp_socket.recv(&mut buf)).await {
+                match received {
                     Ok(size) => {
                         debug!("Received {} bytes", size);
-                        self.fmt_ports(socket);
-                        Ok(true)
+                        self.fmt_ports(socket, Protocol::Udp, None);
+                        Ok(Some(PortState::Open))
                     }
-                    Err(e) => {
-                        if e.kind() == io::ErrorKind::TimedOut {
-                            Ok(false)
-                        } else {
-                            Err(e)
+                    Err(e) => match e.kind() {
+                        io::ErrorKind::TimedOut => Ok(None),
+                        io::ErrorKind::ConnectionRefused => Ok(Some(PortState::Closed)),
+                        io::ErrorKind::HostUnreachable | io::ErrorKind::NetworkUnreachable => {
+                            Ok(Some(PortState::Filtered))
                         }
-                    }
+                        _ => Err(e),
+                    },
                 }
             }
             Err(e) => {
-                println!("Err E binding sock {:?}", e);
+                debug!("Failed to bind a UDP socket: {}", e);
                 Err(e)
             }
         }
     }
 
-    /// Formats and prints the port status
-    fn fmt_ports(&self, socket: SocketAddr) {
-        if !self.greppable {
+    /// Formats and prints the port status, with its banner when there is one.
+    /// UDP ports are marked as such, like `192.168.0.1:53/udp`.
+    fn fmt_ports(&self, socket: SocketAddr, protocol: Protocol, banner: Option<&str>) {
+        if !self.silent() {
+            let banner = banner
+                .map(|banner| format!(" {banner}"))
+                .unwrap_or_default();
+            let socket = match protocol {
+                Protocol::Tcp => socket.to_string(),
+                Protocol::Udp => format!("{socket}/udp"),
+            };
             if self.accessible {
-                println!("Open {socket}");
+                println!("Open {socket}{banner}");
             } else {
-                println!("Open {}", socket.to_string().purple());
+                println!("Open {}{}", socket.purple(), banner);
             }
         }
     }
+
+    /// Prints the TLS version and certificate found behind an open port
+    fn fmt_tls(&self, socket: SocketAddr, info: &TlsInfo) {
+        if !self.silent() {
+            let mut details = format!(
+                "{} {} (valid until {})",
+                info.version, info.subject, info.not_after
+            );
+            if let Some(error) = &info.handshake_error {
+                details = format!(
+                    "{details}, takes {} only, the handshake failed: {error}",
+                    info.versions.join(", ")
+                );
+            }
+            if self.accessible {
+                println!("TLS {socket} {details}");
+            } else {
+                println!("TLS {} {}", socket.to_string().purple(), details);
+            }
+        }
+    }
+
+    /// Prints how the web server behind an open port answered
+    fn fmt_http(&self, socket: SocketAddr, info: &HttpInfo) {
+        if !self.silent() {
+            let mut details = info.status.to_string();
+            for value in [&info.server, &info.title].into_iter().flatten() {
+                details = format!("{details} {value}");
+            }
+            if let Some(location) = &info.location {
+                details = format!("{details} -> {location}");
+            }
+            if self.accessible {
+                println!("HTTP {socket} {details}");
+            } else {
+                println!("HTTP {} {}", socket.to_string().purple(), details);
+            }
+        }
+    }
+
+    /// Prints the service found behind an open port
+    fn fmt_service(&self, socket: SocketAddr, service: &Service) {
+        if !self.silent() {
+            if self.accessible {
+                println!("Service {socket} {service}");
+            } else {
+                println!("Service {} {}", socket.to_string().purple(), service);
+            }
+        }
+    }
+}
+
+/// Counts one more socket of `ip` as done, forgetting about the host once
+/// none of the ones taken are left.
+fn finish_socket(unfinished: &mut HashMap<IpAddr, usize>, ip: IpAddr) {
+    if let Some(left) = unfinished.get_mut(&ip) {
+        *left -= 1;
+        if *left == 0 {
+            unfinished.remove(&ip);
+        }
+    }
+}
+
+/// Whether `deadline` passed, there being none means there is all the
+/// time in the world.
+fn past(deadline: Option<Instant>) -> bool {
+    deadline.is_some_and(|deadline| Instant::now() >= deadline)
+}
+
+/// Waits for `future`, or for `deadline` to pass if it comes first, in
+/// which case `None` is returned.
+async fn until<T>(deadline: Option<Instant>, future: impl Future<Output = T>) -> Option<T> {
+    match deadline {
+        Some(deadline) => {
+            async_std::future::timeout(deadline.saturating_duration_since(Instant::now()), future)
+                .await
+                .ok()
+        }
+        None => Some(future.await),
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::input::{PortRange, ScanOrder};
+    use crate::targets::IpRange;
     use async_std::task::block_on;
     use std::{net::IpAddr, time::Duration};
 
+    /// A scanner of the ports of `port_strategy` over `protocol`, sending
+    /// at most 10 probes at once and giving each port a single try.
+    fn builder<T: Into<IpRange>>(
+        targets: impl IntoIterator<Item = T>,
+        protocol: Protocol,
+        port_strategy: PortStrategy,
+        timeout: Duration,
+    ) -> ScannerBuilder {
+        ScannerBuilder::new()
+            .targets(targets)
+            .ports(protocol, port_strategy)
+            .batch_size(10)
+            .tries(1)
+            .timeout(timeout)
+    }
+
     #[test]
     fn scanner_runs() {
         // Makes sure the program still runs and doesn't panic
-        let addrs = vec!["127.0.0.1".parse::<IpAddr>().unwrap()];
+        let addrs = ["127.0.0.1".parse::<IpAddr>().unwrap()];
         let range = PortRange {
             start: 1,
             end: 1_000,
         };
         let strategy = PortStrategy::pick(&Some(range), None, ScanOrder::Random);
-        let scanner = Scanner::new(
-            &addrs,
-            10,
-            Duration::from_millis(100),
-            1,
-            true,
-            strategy,
-            true,
-            vec![9000],
-            false,
-        );
+        let scanner = builder(addrs, Protocol::Tcp, strategy, Duration::from_millis(100))
+            .exclude_ports(vec![9000])
+            .build()
+            .unwrap();
         block_on(scanner.run());
         // if the scan fails, it wouldn't be able to assert_eq! as it panicked!
         assert_eq!(1, 1);
     }
+    #[test]
+    fn scanner_reports_open_port() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let addrs = ["127.0.0.1".parse::<IpAddr>().unwrap()];
+        let strategy = PortStrategy::pick(&None, Some(vec![port]), ScanOrder::Serial);
+        let scanner = builder(addrs, Protocol::Tcp, strategy, Duration::from_millis(500))
+            .build()
+            .unwrap();
+        let result = block_on(scanner.run());
+
+        assert_eq!(result.hosts.len(), 1);
+        assert_eq!(result.hosts[0].ip, addrs[0]);
+        assert_eq!(result.hosts[0].open_ports(), vec![port]);
+        assert_eq!(result.hosts[0].ports[0].protocol, Protocol::Tcp);
+        assert_eq!(result.open_sockets(), vec![SocketAddr::new(addrs[0], port)]);
+    }
+    #[test]
+    fn scanner_grabs_banners() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        std::thread::spawn(move || {
+            use std::io::Write;
+            while let Ok((mut stream, _)) = listener.accept() {
+                let _ = stream.write_all(b"SSH-2.0-azula\r\n");
+            }
+        });
+        let addrs = ["127.0.0.1".parse::<IpAddr>().unwrap()];
+        let strategy = PortStrategy::pick(&None, Some(vec![port]), ScanOrder::Serial);
+        let scanner = builder(addrs, Protocol::Tcp, strategy, Duration::from_millis(500))
+            .build()
+            .unwrap()
+            .with_banners(Duration::from_millis(500));
+        let result = block_on(scanner.run());
+
+        assert_eq!(
+            result.hosts[0].ports[0].banner.as_deref(),
+            Some("SSH-2.0-azula")
+        );
+    }
+
+    #[test]
+    fn scanner_detects_services() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        std::thread::spawn(move || {
+            use std::io::Write;
+            while let Ok((mut stream, _)) = listener.accept() {
+                let _ = stream.write_all(b"SSH-2.0-OpenSSH_9.6p1\r\n");
+            }
+        });
+        let addrs = ["127.0.0.1".parse::<IpAddr>().unwrap()];
+        let strategy = PortStrategy::pick(&None, Some(vec![port]), ScanOrder::Serial);
+        let scanner = builder(addrs, Protocol::Tcp, strategy, Duration::from_millis(500))
+            .build()
+            .unwrap()
+            .with_service_detection(ServiceDetector::new(
+                ServiceProbes::bundled(),
+                7,
+                Duration::from_millis(500),
+            ));
+        let result = block_on(scanner.run());
+
+        let service = result.hosts[0].ports[0].service.as_ref().unwrap();
+        assert_eq!(service.to_string(), "ssh OpenSSH 9.6p1 (protocol 2.0)");
+    }
+
+    #[test]
+    fn scanner_probes_tls() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let config = rustls::ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![rustls::Certificate(
+                    include_bytes!("../../fixtures/tls/cert.der").to_vec(),
+                )],
+                rustls::PrivateKey(include_bytes!("../../fixtures/tls/key.der").to_vec()),
+            )
+            .unwrap();
+        let config = Arc::new(config);
+        std::thread::spawn(move || {
+            while let Ok((mut stream, _)) = listener.accept() {
+                let mut connection = rustls::ServerConnection::new(Arc::clone(&config)).unwrap();
+                while connection.is_handshaking() && connection.complete_io(&mut stream).is_ok() {}
+            }
+        });
+        let ip = "127.0.0.1".parse::<IpAddr>().unwrap();
+        let strategy = PortStrategy::pick(&None, Some(vec![port]), ScanOrder::Serial);
+        let scanner = builder([ip], Protocol::Tcp, strategy, Duration::from_millis(500))
+            .build()
+            .unwrap()
+            .with_hostnames(HashMap::from([(ip, "azula.test".to_owned())]))
+            .with_tls_probe(TlsProbe::new(Duration::from_secs(2)));
+        let result = block_on(scanner.run());
+
+        assert_eq!(result.hosts[0].hostname.as_deref(), Some("azula.test"));
+        let tls = result.hosts[0].ports[0].tls.as_ref().unwrap();
+        assert_eq!(tls.subject, "CN=azula.test, O=Azula");
+    }
+
+    #[test]
+    fn scanner_reports_closed_port_when_asked() {
+        let port = {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            listener.local_addr().unwrap().port()
+        };
+        let addrs = ["127.0.0.1".parse::<IpAddr>().unwrap()];
+        let strategy = PortStrategy::pick(&None, Some(vec![port]), ScanOrder::Serial);
+        let scanner = builder(addrs, Protocol::Tcp, strategy, Duration::from_millis(500))
+            .build()
+            .unwrap();
+        let result = block_on(scanner.run());
+        assert!(result.hosts[0].ports.is_empty());
+        assert_eq!(result.targets, 1);
+
+        let strategy = PortStrategy::pick(&None, Some(vec![port]), ScanOrder::Serial);
+        let scanner = builder(addrs, Protocol::Tcp, strategy, Duration::from_millis(500))
+            .build()
+            .unwrap()
+            .with_reported_states(&[PortState::Closed]);
+        let result = block_on(scanner.run());
+
+        assert_eq!(result.hosts[0].ports.len(), 1);
+        assert_eq!(result.hosts[0].ports[0].port, port);
+        assert_eq!(result.hosts[0].ports[0].state, PortState::Closed);
+        assert!(result.hosts[0].open_ports().is_empty());
+    }
+
+    #[test]
+    fn scanner_skips_hosts_down_at_discovery() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let addrs = [
+            "127.0.0.1".parse::<IpAddr>().unwrap(),
+            // A discard only prefix, nothing there ever answers.
+            "100::1".parse::<IpAddr>().unwrap(),
+        ];
+        let strategy = PortStrategy::pick(&None, Some(vec![port]), ScanOrder::Serial);
+        let scanner = builder(addrs, Protocol::Tcp, strategy, Duration::from_millis(200))
+            .build()
+            .unwrap()
+            .with_discovery(Discovery::new(vec![port], Duration::from_millis(200)));
+        let result = block_on(scanner.run());
+
+        assert_eq!(result.hosts.len(), 2);
+        assert_eq!(result.hosts[0].status, HostStatus::Up);
+        assert_eq!(result.hosts[0].open_ports(), vec![port]);
+        assert_eq!(result.hosts[1].status, HostStatus::Down);
+        assert!(result.hosts[1].ports.is_empty());
+        assert_eq!(result.targets, 2);
+        assert_eq!(result.hosts_down, 1);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    #[ignore = "raw sockets need root or CAP_NET_RAW"]
+    fn syn_scanner_reports_open_and_closed_ports() {
+        let syn = SynScanner::new(Source::default()).unwrap();
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let open = listener.local_addr().unwrap().port();
+        let closed = {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            listener.local_addr().unwrap().port()
+        };
+        let addrs = ["127.0.0.1".parse::<IpAddr>().unwrap()];
+        let strategy = PortStrategy::pick(&None, Some(vec![open, closed]), ScanOrder::Serial);
+        let scanner = builder(addrs, Protocol::Tcp, strategy, Duration::from_millis(500))
+            .build()
+            .unwrap()
+            .with_reported_states(&[PortState::Closed])
+            .with_syn_scan(Arc::new(syn));
+        let result = block_on(scanner.run());
+
+        let states: Vec<(u16, PortState)> = result.hosts[0]
+            .ports
+            .iter()
+            .map(|port| (port.port, port.state))
+            .collect();
+        let mut expected = vec![(open, PortState::Open), (closed, PortState::Closed)];
+        expected.sort_unstable_by_key(|&(port, _)| port);
+        assert_eq!(states, expected);
+    }
+
+    #[test]
+    fn scanner_respects_max_rate() {
+        let addrs = ["127.0.0.1".parse::<IpAddr>().unwrap()];
+        let ports: Vec<u16> = (40_000..40_020).collect();
+        let strategy = PortStrategy::pick(&None, Some(ports), ScanOrder::Serial);
+        let scanner = builder(addrs, Protocol::Tcp, strategy, Duration::from_millis(100))
+            .build()
+            .unwrap()
+            .with_rate_limits(None, Some(100));
+
+        let started = std::time::Instant::now();
+        block_on(scanner.run());
+
+        // A burst of 5 probes, then 15 more at 100 per second.
+        assert!(started.elapsed() >= Duration::from_millis(140));
+    }
+
+    #[test]
+    fn retries_respect_max_rate() {
+        let silent: Vec<std::net::UdpSocket> = (0..5)
+            .map(|_| std::net::UdpSocket::bind("127.0.0.1:0").unwrap())
+            .collect();
+        let ports = silent
+            .iter()
+            .map(|socket| socket.local_addr().unwrap().port())
+            .collect();
+        let addrs = ["127.0.0.1".parse::<IpAddr>().unwrap()];
+        let strategy = PortStrategy::pick(&None, Some(ports), ScanOrder::Serial);
+        let scanner = builder(addrs, Protocol::Udp, strategy, Duration::from_millis(10))
+            .tries(4)
+            .build()
+            .unwrap()
+            .with_rate_limits(None, Some(50));
+
+        let started = std::time::Instant::now();
+        block_on(scanner.run());
+
+        // 4 tries of 5 ports, a burst of 2 and the other 18 at 50 per second.
+        assert!(started.elapsed() >= Duration::from_millis(340));
+    }
+
+    #[test]
+    fn udp_scanner_classifies_ports() {
+        let silent = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let responder = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let open = responder.local_addr().unwrap().port();
+        // Bound last so the kernel can't hand the freed port to the others.
+        let closed = {
+            let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+            socket.local_addr().unwrap().port()
+        };
+        std::thread::spawn(move || {
+            let mut buf = [0u8; 1024];
+            while let Ok((_, peer)) = responder.recv_from(&mut buf) {
+                let _ = responder.send_to(b"pong", peer);
+            }
+        });
+
+        let addrs = ["127.0.0.1".parse::<IpAddr>().unwrap()];
+        let ports = vec![open, closed, silent.local_addr().unwrap().port()];
+        let strategy = PortStrategy::pick(&None, Some(ports.clone()), ScanOrder::Serial);
+        let scanner = builder(addrs, Protocol::Udp, strategy, Duration::from_millis(200))
+            .build()
+            .unwrap()
+            .with_reported_states(&[PortState::Closed, PortState::OpenFiltered]);
+        let result = block_on(scanner.run());
+
+        let states: Vec<(u16, PortState)> = result.hosts[0]
+            .ports
+            .iter()
+            .map(|port| (port.port, port.state))
+            .collect();
+        let mut expected = vec![
+            (ports[0], PortState::Open),
+            (ports[1], PortState::Closed),
+            (ports[2], PortState::OpenFiltered),
+        ];
+        expected.sort_unstable_by_key(|&(port, _)| port);
+        assert_eq!(states, expected);
+        assert!(result.hosts[0]
+            .ports
+            .iter()
+            .all(|port| port.protocol == Protocol::Udp));
+    }
+
+    #[test]
+    fn scanner_scans_tcp_and_udp_together() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let tcp = listener.local_addr().unwrap().port();
+        let responder = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let udp = responder.local_addr().unwrap().port();
+        std::thread::spawn(move || {
+            let mut buf = [0u8; 1024];
+            while let Ok((_, peer)) = responder.recv_from(&mut buf) {
+                let _ = responder.send_to(b"pong", peer);
+            }
+        });
+
+        let addrs = ["127.0.0.1".parse::<IpAddr>().unwrap()];
+        let scanner = builder(
+            addrs,
+            Protocol::Tcp,
+            PortStrategy::pick(&None, Some(vec![udp, tcp]), ScanOrder::Serial),
+            Duration::from_millis(200),
+        )
+        .build()
+        .unwrap()
+        .with_protocol_ports(
+            Protocol::Udp,
+            PortStrategy::pick(&None, Some(vec![udp]), ScanOrder::Serial),
+        );
+        let result = block_on(scanner.run());
+
+        let ports: Vec<(Protocol, u16)> = result.hosts[0]
+            .ports
+            .iter()
+            .map(|port| (port.protocol, port.port))
+            .collect();
+        assert_eq!(ports, [(Protocol::Tcp, tcp), (Protocol::Udp, udp)]);
+        drop(listener);
+    }
+
+    #[test]
+    fn scanner_resumes_from_a_checkpoint() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let open = listener.local_addr().unwrap().port();
+        // Scanned before the interruption, it would show up closed now.
+        let scanned = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let ip = "127.0.0.1".parse::<IpAddr>().unwrap();
+        let path = std::env::temp_dir().join(format!("azula-scanner-{}.json", std::process::id()));
+
+        let mut host = HostResult::new(ip);
+        host.ports.push(PortResult::new(
+            scanned,
+            Protocol::Tcp,
+            PortState::Open,
+            Duration::from_millis(1),
+        ));
+        let mut progress = Progress::default();
+        progress.finish(0);
+        let resume = ResumeFile::open(path.clone()).unwrap();
+        resume
+            .save(&Checkpoint {
+                seed: resume.seed(),
+                fingerprint: checkpoint::fingerprint(
+                    &[ip.into()],
+                    &[(Protocol::Tcp, vec![scanned, open])],
+                    None,
+                ),
+                started_at: 1_700_000_000,
+                elapsed: Duration::from_secs(60),
+                live: TargetSet::from(ip),
+                progress,
+                hosts: vec![host],
+            })
+            .unwrap();
+
+        let scanner = builder(
+            [ip],
+            Protocol::Tcp,
+            PortStrategy::pick(&None, Some(vec![scanned, open]), ScanOrder::Serial),
+            Duration::from_millis(200),
+        )
+        .build()
+        .unwrap()
+        .with_resume_file(ResumeFile::open(path.clone()).unwrap());
+        let result = block_on(scanner.run());
+
+        assert_eq!(result.started_at, 1_700_000_000);
+        assert!(result.elapsed >= Duration::from_secs(60));
+        let mut ports = result.hosts[0].open_ports();
+        ports.sort_unstable();
+        let mut expected = vec![scanned, open];
+        expected.sort_unstable();
+        assert_eq!(ports, expected);
+        assert!(!path.exists());
+        drop(listener);
+    }
+
+    #[test]
+    fn scanner_gives_up_on_hosts_past_their_timeout() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let open = listener.local_addr().unwrap().port();
+        let ip = "127.0.0.1".parse::<IpAddr>().unwrap();
+
+        let scanner = builder(
+            [ip],
+            Protocol::Tcp,
+            PortStrategy::pick(&None, Some(vec![open, 1, 2]), ScanOrder::Serial),
+            Duration::from_millis(200),
+        )
+        .batch_size(1)
+        .build()
+        .unwrap()
+        .with_reported_states(&[PortState::Closed])
+        .with_host_timeout(Duration::ZERO);
+        let result = block_on(scanner.run());
+
+        // Only the first probe was sent before the host ran out of time.
+        assert!(result.hosts[0].incomplete);
+        assert_eq!(result.hosts[0].ports.len(), 1);
+        assert_eq!(result.hosts[0].open_ports(), vec![open]);
+        drop(listener);
+    }
+
+    #[test]
+    fn scanner_stops_at_its_max_scan_time() {
+        let ip = "127.0.0.1".parse::<IpAddr>().unwrap();
+
+        let scanner = builder(
+            [ip],
+            Protocol::Tcp,
+            PortStrategy::pick(&None, Some(vec![1, 2]), ScanOrder::Serial),
+            Duration::from_millis(200),
+        )
+        .build()
+        .unwrap()
+        .with_reported_states(&[PortState::Closed])
+        .with_max_scan_time(Duration::ZERO);
+        let result = block_on(scanner.run());
+
+        assert!(!result.interrupted);
+        assert_eq!(result.hosts.len(), 1);
+        assert!(result.hosts[0].incomplete);
+        assert!(result.hosts[0].ports.is_empty());
+        assert_eq!(result.hosts_unscanned, 1);
+    }
+
+    #[test]
+    fn scanner_keeps_no_state_per_target_of_large_ranges() {
+        let range = crate::targets::IpRange::new(
+            "127.0.0.0".parse().unwrap(),
+            "127.15.255.255".parse().unwrap(),
+        )
+        .unwrap();
+
+        let scanner = builder(
+            [range],
+            Protocol::Tcp,
+            PortStrategy::pick(&None, Some(vec![1, 2]), ScanOrder::Serial),
+            Duration::from_millis(200),
+        )
+        .batch_size(100)
+        .build()
+        .unwrap()
+        .with_permuted_sockets(7)
+        .with_host_timeout(Duration::from_millis(1))
+        .with_max_scan_time(Duration::from_millis(300));
+        let result = block_on(scanner.run());
+
+        // Neither the hosts given up on for their host timeout nor the ones
+        // cut short get an entry of their own, only the closed ports would.
+        assert!(result.hosts.is_empty());
+        assert_eq!(result.targets, 1 << 20);
+        assert!(result.hosts_unscanned > 0);
+        assert!(result.hosts_unscanned < 1 << 20);
+    }
+
+    #[test]
+    fn scanner_goes_through_permuted_sockets() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let open = listener.local_addr().unwrap().port();
+        let ips: [IpAddr; 2] = ["127.0.0.1".parse().unwrap(), "127.0.0.2".parse().unwrap()];
+
+        let scanner = builder(
+            ips,
+            Protocol::Tcp,
+            PortStrategy::pick(&None, Some(vec![1, 2, open]), ScanOrder::Serial),
+            Duration::from_millis(200),
+        )
+        .build()
+        .unwrap()
+        .with_reported_states(&[PortState::Closed])
+        .with_permuted_sockets(7);
+        let result = block_on(scanner.run());
+
+        assert_eq!(result.hosts[0].open_ports(), vec![open]);
+        assert_eq!(result.hosts[0].ports.len(), 3);
+        assert_eq!(result.hosts[1].ports.len(), 3);
+        drop(listener);
+    }
+
+    #[test]
+    fn scanner_stops_when_signalled() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let open = listener.local_addr().unwrap().port();
+        let ip = "127.0.0.1".parse::<IpAddr>().unwrap();
+        let stop = Arc::new(AtomicBool::new(true));
+
+        let scanner = builder(
+            [ip],
+            Protocol::Tcp,
+            PortStrategy::pick(&None, Some(vec![open]), ScanOrder::Serial),
+            Duration::from_millis(200),
+        )
+        .build()
+        .unwrap()
+        .with_stop_signal(Arc::clone(&stop));
+        let result = block_on(scanner.run());
+
+        assert!(result.interrupted);
+        assert_eq!(result.hosts[0].status, HostStatus::Up);
+        assert!(result.hosts[0].open_ports().is_empty());
+        assert_eq!(result.hosts_unscanned, 0);
+        drop(listener);
+    }
+
     #[test]
     fn ipv6_scanner_runs() {
         // Makes sure the program still runs and doesn't panic
-        let addrs = vec!["::1".parse::<IpAddr>().unwrap()];
+        let addrs = ["::1".parse::<IpAddr>().unwrap()];
         let range = PortRange {
             start: 1,
             end: 1_000,
         };
         let strategy = PortStrategy::pick(&Some(range), None, ScanOrder::Random);
-        let scanner = Scanner::new(
-            &addrs,
-            10,
-            Duration::from_millis(100),
-            1,
-            true,
-            strategy,
-            true,
-            vec![9000],
-            false,
-        );
+        let scanner = builder(addrs, Protocol::Tcp, strategy, Duration::from_millis(100))
+            .exclude_ports(vec![9000])
+            .build()
+            .unwrap();
         block_on(scanner.run());
         // if the scan fails, it wouldn't be able to assert_eq! as it panicked!
         assert_eq!(1, 1);
     }
     #[test]
     fn quad_zero_scanner_runs() {
-        let addrs = vec!["0.0.0.0".parse::<IpAddr>().unwrap()];
+        let addrs = ["0.0.0.0".parse::<IpAddr>().unwrap()];
         let range = PortRange {
             start: 1,
             end: 1_000,
         };
         let strategy = PortStrategy::pick(&Some(range), None, ScanOrder::Random);
-        let scanner = Scanner::new(
-            &addrs,
-            10,
-            Duration::from_millis(100),
-            1,
-            true,
-            strategy,
-            true,
-            vec![9000],
-            false,
-        );
+        let scanner = builder(addrs, Protocol::Tcp, strategy, Duration::from_millis(100))
+            .exclude_ports(vec![9000])
+            .build()
+            .unwrap();
         block_on(scanner.run());
         assert_eq!(1, 1);
     }
     #[test]
     fn google_dns_runs() {
-        let addrs = vec!["8.8.8.8".parse::<IpAddr>().unwrap()];
+        let addrs = ["8.8.8.8".parse::<IpAddr>().unwrap()];
         let range = PortRange {
             start: 400,
             end: 445,
         };
         let strategy = PortStrategy::pick(&Some(range), None, ScanOrder::Random);
-        let scanner = Scanner::new(
-            &addrs,
-            10,
-            Duration::from_millis(100),
-            1,
-            true,
-            strategy,
-            true,
-            vec![9000],
-            false,
-        );
+        let scanner = builder(addrs, Protocol::Tcp, strategy, Duration::from_millis(100))
+            .exclude_ports(vec![9000])
+            .build()
+            .unwrap();
         block_on(scanner.run());
         assert_eq!(1, 1);
     }
     #[test]
     fn infer_ulimit_lowering_no_panic() {
         // Test behaviour on MacOS where ulimit is not automatically lowered
-        let addrs = vec!["8.8.8.8".parse::<IpAddr>().unwrap()];
+        let addrs = ["8.8.8.8".parse::<IpAddr>().unwrap()];
 
         // mac should have this automatically scaled down
         let range = PortRange {
@@ -413,17 +1896,10 @@ mod tests {
             end: 600,
         };
         let strategy = PortStrategy::pick(&Some(range), None, ScanOrder::Random);
-        let scanner = Scanner::new(
-            &addrs,
-            10,
-            Duration::from_millis(100),
-            1,
-            true,
-            strategy,
-            true,
-            vec![9000],
-            false,
-        );
+        let scanner = builder(addrs, Protocol::Tcp, strategy, Duration::from_millis(100))
+            .exclude_ports(vec![9000])
+            .build()
+            .unwrap();
         block_on(scanner.run());
         assert_eq!(1, 1);
     }
@@ -431,23 +1907,16 @@ mod tests {
     #[test]
     fn udp_scan_runs() {
         // Makes sure the program still runs and doesn't panic
-        let addrs = vec!["127.0.0.1".parse::<IpAddr>().unwrap()];
+        let addrs = ["127.0.0.1".parse::<IpAddr>().unwrap()];
         let range = PortRange {
             start: 1,
             end: 1_000,
         };
         let strategy = PortStrategy::pick(&Some(range), None, ScanOrder::Random);
-        let scanner = Scanner::new(
-            &addrs,
-            10,
-            Duration::from_millis(100),
-            1,
-            true,
-            strategy,
-            true,
-            vec![9000],
-            true,
-        );
+        let scanner = builder(addrs, Protocol::Udp, strategy, Duration::from_millis(100))
+            .exclude_ports(vec![9000])
+            .build()
+            .unwrap();
         block_on(scanner.run());
         // if the scan fails, it wouldn't be able to assert_eq! as it panicked!
         assert_eq!(1, 1);
@@ -455,68 +1924,47 @@ mod tests {
     #[test]
     fn udp_ipv6_runs() {
         // Makes sure the program still runs and doesn't panic
-        let addrs = vec!["::1".parse::<IpAddr>().unwrap()];
+        let addrs = ["::1".parse::<IpAddr>().unwrap()];
         let range = PortRange {
             start: 1,
             end: 1_000,
         };
         let strategy = PortStrategy::pick(&Some(range), None, ScanOrder::Random);
-        let scanner = Scanner::new(
-            &addrs,
-            10,
-            Duration::from_millis(100),
-            1,
-            true,
-            strategy,
-            true,
-            vec![9000],
-            true,
-        );
+        let scanner = builder(addrs, Protocol::Udp, strategy, Duration::from_millis(100))
+            .exclude_ports(vec![9000])
+            .build()
+            .unwrap();
         block_on(scanner.run());
         // if the scan fails, it wouldn't be able to assert_eq! as it panicked!
         assert_eq!(1, 1);
     }
     #[test]
     fn udp_quad_zero_scanner_runs() {
-        let addrs = vec!["0.0.0.0".parse::<IpAddr>().unwrap()];
+        let addrs = ["0.0.0.0".parse::<IpAddr>().unwrap()];
         let range = PortRange {
             start: 1,
             end: 1_000,
         };
         let strategy = PortStrategy::pick(&Some(range), None, ScanOrder::Random);
-        let scanner = Scanner::new(
-            &addrs,
-            10,
-            Duration::from_millis(100),
-            1,
-            true,
-            strategy,
-            true,
-            vec![9000],
-            true,
-        );
+        let scanner = builder(addrs, Protocol::Udp, strategy, Duration::from_millis(100))
+            .exclude_ports(vec![9000])
+            .build()
+            .unwrap();
         block_on(scanner.run());
         assert_eq!(1, 1);
     }
     #[test]
     fn udp_google_dns_runs() {
-        let addrs = vec!["8.8.8.8".parse::<IpAddr>().unwrap()];
+        let addrs = ["8.8.8.8".parse::<IpAddr>().unwrap()];
         let range = PortRange {
             start: 100,
             end: 150,
         };
         let strategy = PortStrategy::pick(&Some(range), None, ScanOrder::Random);
-        let scanner = Scanner::new(
-            &addrs,
-            10,
-            Duration::from_millis(100),
-            1,
-            true,
-            strategy,
-            true,
-            vec![9000],
-            true,
-        );
+        let scanner = builder(addrs, Protocol::Udp, strategy, Duration::from_millis(100))
+            .exclude_ports(vec![9000])
+            .build()
+            .unwrap();
         block_on(scanner.run());
         assert_eq!(1, 1);
     }
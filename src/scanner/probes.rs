@@ -0,0 +1,674 @@
+use super::result::{Protocol, Service};
+use log::debug;
+use once_cell::sync::{Lazy, OnceCell};
+use regex::bytes::{Captures, Regex, RegexBuilder};
+use std::io;
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::time::Duration;
+
+/// The service probe database bundled with Azula, a trimmed down copy of
+/// the one nmap ships.
+const BUNDLED_PROBES: &str = include_str!("../../nmap-service-probes");
+/// How long to wait for the answer to probes without a `totalwaitms`.
+const DEFAULT_TOTAL_WAIT: Duration = Duration::from_secs(5);
+/// Rarity of probes without a `rarity`, only the NULL probe in practice.
+const DEFAULT_RARITY: u8 = 5;
+
+/// The `$1`, `$P(1)`, `$SUBST(1,"from","to")` and `$I(1,">")` references
+/// version info templates can use.
+static TEMPLATE_REFERENCE: Lazy<regex::Regex> = Lazy::new(|| {
+    regex::Regex::new(
+        r#"^\$(?:(\d)|P\((\d)\)|SUBST\((\d),"([^"]*)","([^"]*)"\)|I\((\d),"([<>])"\))"#,
+    )
+    .unwrap()
+});
+
+/// A parsed `nmap-service-probes` database.
+///
+/// Match regexes are compiled the first time they are needed, the full
+/// nmap database has thousands of them and most never get used. nmap
+/// relies on PCRE, so the few patterns using what the `regex` crate lacks,
+/// like back references and look-arounds, are skipped.
+#[derive(Debug)]
+pub struct ServiceProbes {
+    probes: Vec<Probe>,
+    excluded: Vec<(Option<Protocol>, RangeInclusive<u16>)>,
+}
+
+/// A `Probe` of the database along with the directives that follow it.
+#[derive(Debug)]
+pub struct Probe {
+    pub protocol: Protocol,
+    pub name: String,
+    pub payload: Vec<u8>,
+    pub total_wait: Duration,
+    rarity: u8,
+    ports: Vec<RangeInclusive<u16>>,
+    fallback: Vec<String>,
+    matches: Vec<Match>,
+}
+
+/// A `match` or `softmatch` line.
+#[derive(Debug)]
+struct Match {
+    service: String,
+    soft: bool,
+    pattern: String,
+    case_insensitive: bool,
+    dot_matches_new_line: bool,
+    regex: OnceCell<Option<Regex>>,
+    template: Template,
+    line: usize,
+}
+
+/// The version info of a match, which may refer to the groups captured
+/// by its regex.
+#[derive(Debug, Default)]
+struct Template {
+    product: Option<String>,
+    version: Option<String>,
+    extra_info: Option<String>,
+    hostname: Option<String>,
+    os_type: Option<String>,
+    device_type: Option<String>,
+    cpe: Vec<String>,
+}
+
+/// The service an answer was recognised as.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Identification {
+    /// A `match` line recognised it, there is nothing left to find out.
+    Hard(Service),
+    /// A `softmatch` line only told which service it is, other probes may
+    /// still find out its product and version.
+    Soft(Service),
+}
+
+impl ServiceProbes {
+    /// The database bundled with Azula.
+    pub fn bundled() -> Self {
+        Self::parse(BUNDLED_PROBES).expect("the bundled service probes are valid")
+    }
+
+    /// Reads the database at `path`, usually the one installed with nmap.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        Self::parse(&String::from_utf8_lossy(&std::fs::read(path)?))
+    }
+
+    pub fn parse(text: &str) -> io::Result<Self> {
+        let mut probes: Vec<Probe> = Vec::new();
+        let mut excluded = Vec::new();
+
+        for (index, line) in text.lines().enumerate() {
+            let number = index + 1;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (directive, rest) = line.split_once(' ').unwrap_or((line, ""));
+            let rest = rest.trim_start();
+
+            if directive == "Exclude" {
+                excluded.extend(parse_exclude(rest).ok_or_else(|| invalid(number, directive))?);
+                continue;
+            }
+            if directive == "Probe" {
+                probes.push(parse_probe(rest).ok_or_else(|| invalid(number, directive))?);
+                continue;
+            }
+
+            let probe = probes
+                .last_mut()
+                .ok_or_else(|| invalid(number, directive))?;
+            match directive {
+                "match" | "softmatch" => probe.matches.push(
+                    parse_match(rest, directive == "softmatch", number)
+                        .ok_or_else(|| invalid(number, directive))?,
+                ),
+                "ports" => {
+                    probe.ports = parse_ports(rest).ok_or_else(|| invalid(number, directive))?
+                }
+                "rarity" => probe.rarity = rest.parse().map_err(|_| invalid(number, directive))?,
+                "totalwaitms" => {
+                    probe.total_wait = Duration::from_millis(
+                        rest.parse().map_err(|_| invalid(number, directive))?,
+                    );
+                }
+                "fallback" => {
+                    probe.fallback = rest.split(',').map(|name| name.trim().to_owned()).collect();
+                }
+                // sslports, tcpwrappedms and the like don't matter to us.
+                _ => debug!("Ignoring {} on line {}", directive, number),
+            }
+        }
+
+        Ok(Self { probes, excluded })
+    }
+
+    /// The probes worth sending to `port`, in the order nmap sends them:
+    /// the NULL probe first, then the probes registered for the port, then
+    /// the other ones that are not rarer than `intensity`.
+    pub fn probes_for(&self, port: u16, protocol: Protocol, intensity: u8) -> Vec<&Probe> {
+        let excluded = self.excluded.iter().any(|(excluded, ports)| {
+            excluded.is_none_or(|excluded| excluded == protocol) && ports.contains(&port)
+        });
+        if excluded {
+            return Vec::new();
+        }
+
+        let candidates = self
+            .probes
+            .iter()
+            .filter(|probe| probe.protocol == protocol);
+        let mut probes: Vec<&Probe> = candidates.clone().filter(|probe| probe.is_null()).collect();
+        probes.extend(
+            candidates
+                .clone()
+                .filter(|probe| !probe.is_null() && probe.registered_for(port)),
+        );
+        probes.extend(candidates.filter(|probe| {
+            !probe.is_null() && !probe.registered_for(port) && probe.rarity <= intensity
+        }));
+        probes
+    }
+
+    /// Runs `response`, the answer to `probe`, through the matches of the
+    /// probe and of its fallbacks. Once a softmatch named the service,
+    /// passing it as `service` keeps to the matches for that service.
+    pub fn identify(
+        &self,
+        probe: &Probe,
+        response: &[u8],
+        service: Option<&str>,
+    ) -> Option<Identification> {
+        self.matches_for(probe)
+            .filter(|m| service.is_none_or(|service| m.service == service))
+            .find_map(|m| m.identify(response))
+    }
+
+    /// Tells whether the answer to `probe` could match `service` at all.
+    pub fn can_identify(&self, probe: &Probe, service: &str) -> bool {
+        self.matches_for(probe).any(|m| m.service == service)
+    }
+
+    /// The matches of `probe`, then of its fallbacks, then of the NULL
+    /// probe for TCP since a service may send its banner before answering.
+    fn matches_for<'a>(&'a self, probe: &'a Probe) -> impl Iterator<Item = &'a Match> {
+        let fallbacks = probe
+            .fallback
+            .iter()
+            .filter_map(|name| self.find(probe.protocol, name));
+        let null = (probe.protocol == Protocol::Tcp && !probe.is_null())
+            .then(|| self.find(Protocol::Tcp, "NULL"))
+            .flatten();
+
+        std::iter::once(probe)
+            .chain(fallbacks)
+            .chain(null)
+            .flat_map(|probe| &probe.matches)
+    }
+
+    fn find(&self, protocol: Protocol, name: &str) -> Option<&Probe> {
+        self.probes
+            .iter()
+            .find(|probe| probe.protocol == protocol && probe.name == name)
+    }
+}
+
+impl Probe {
+    fn is_null(&self) -> bool {
+        self.name == "NULL"
+    }
+
+    fn registered_for(&self, port: u16) -> bool {
+        self.ports.iter().any(|ports| ports.contains(&port))
+    }
+}
+
+impl Match {
+    fn identify(&self, response: &[u8]) -> Option<Identification> {
+        let captures = self.regex()?.captures(response)?;
+        let service = self.template.fill(&self.service, &captures);
+        Some(if self.soft {
+            Identification::Soft(service)
+        } else {
+            Identification::Hard(service)
+        })
+    }
+
+    fn regex(&self) -> Option<&Regex> {
+        self.regex
+            .get_or_init(|| {
+                if has_back_reference(&self.pattern) {
+                    debug!(
+                        "Skipping the match on line {}, it uses back references",
+                        self.line
+                    );
+                    return None;
+                }
+                RegexBuilder::new(&self.pattern)
+                    .unicode(false)
+                    .octal(true)
+                    .case_insensitive(self.case_insensitive)
+                    .dot_matches_new_line(self.dot_matches_new_line)
+                    .build()
+                    .map_err(|e| debug!("Skipping the match on line {}: {}", self.line, e))
+                    .ok()
+            })
+            .as_ref()
+    }
+}
+
+impl Template {
+    fn fill(&self, name: &str, captures: &Captures) -> Service {
+        let fill = |field: &Option<String>| {
+            field
+                .as_deref()
+                .map(|template| substitute(template, captures))
+                .filter(|value| !value.is_empty())
+        };
+        Service {
+            name: name.to_owned(),
+            product: fill(&self.product),
+            version: fill(&self.version),
+            extra_info: fill(&self.extra_info),
+            hostname: fill(&self.hostname),
+            os_type: fill(&self.os_type),
+            device_type: fill(&self.device_type),
+            cpe: self
+                .cpe
+                .iter()
+                .map(|template| substitute(template, captures))
+                .collect(),
+        }
+    }
+}
+
+/// Replaces the references of a version info template with what the regex
+/// captured.
+fn substitute(template: &str, captures: &Captures) -> String {
+    let group = |number: &str| {
+        number
+            .parse()
+            .ok()
+            .and_then(|number| captures.get(number))
+            .map_or(&b""[..], |group| group.as_bytes())
+    };
+
+    let mut filled = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(at) = rest.find('$') {
+        filled.push_str(&rest[..at]);
+        rest = &rest[at..];
+        let Some(reference) = TEMPLATE_REFERENCE.captures(rest) else {
+            filled.push('$');
+            rest = &rest[1..];
+            continue;
+        };
+
+        if let Some(number) = reference.get(1) {
+            filled.push_str(&String::from_utf8_lossy(group(number.as_str())));
+        } else if let Some(number) = reference.get(2) {
+            filled.extend(
+                group(number.as_str())
+                    .iter()
+                    .filter(|byte| byte.is_ascii_graphic() || **byte == b' ')
+                    .map(|&byte| char::from(byte)),
+            );
+        } else if let Some(number) = reference.get(3) {
+            filled.push_str(
+                &String::from_utf8_lossy(group(number.as_str()))
+                    .replace(&reference[4], &reference[5]),
+            );
+        } else if let Some(number) = reference.get(6) {
+            let bytes = group(number.as_str());
+            let bytes = &bytes[..bytes.len().min(8)];
+            let value = if &reference[7] == ">" {
+                bytes
+                    .iter()
+                    .fold(0u64, |value, &byte| value << 8 | u64::from(byte))
+            } else {
+                bytes
+                    .iter()
+                    .rev()
+                    .fold(0u64, |value, &byte| value << 8 | u64::from(byte))
+            };
+            filled.push_str(&value.to_string());
+        }
+        rest = &rest[reference[0].len()..];
+    }
+    filled.push_str(rest);
+
+    filled.trim().to_owned()
+}
+
+/// `Probe <protocol> <name> q|<payload>|`, maybe followed by `no-payload`.
+fn parse_probe(rest: &str) -> Option<Probe> {
+    let mut parts = rest.splitn(3, ' ');
+    let protocol = match parts.next()? {
+        "TCP" => Protocol::Tcp,
+        "UDP" => Protocol::Udp,
+        _ => return None,
+    };
+    let name = parts.next()?.to_owned();
+    let (payload, _) = delimited(parts.next()?.strip_prefix('q')?)?;
+
+    Some(Probe {
+        protocol,
+        name,
+        payload: unescape(payload),
+        total_wait: DEFAULT_TOTAL_WAIT,
+        rarity: DEFAULT_RARITY,
+        ports: Vec::new(),
+        fallback: Vec::new(),
+        matches: Vec::new(),
+    })
+}
+
+/// `<service> m|<regex>|<flags> <version info>` where the version info is
+/// made of `p/product/`, `v/version/`, `i/extra info/`, `h/hostname/`,
+/// `o/os type/`, `d/device type/` and `cpe:/cpe/` fields.
+fn parse_match(rest: &str, soft: bool, line: usize) -> Option<Match> {
+    let (service, rest) = rest.split_once(' ')?;
+    let (pattern, rest) = delimited(rest.strip_prefix('m')?)?;
+    let (flags, mut rest) = rest.split_at(rest.find(' ').unwrap_or(rest.len()));
+
+    let mut template = Template::default();
+    loop {
+        rest = rest.trim_start();
+        if rest.is_empty() {
+            break;
+        }
+        let (field, value) = match rest.strip_prefix("cpe:") {
+            Some(value) => ("cpe", value),
+            None => rest.split_at(rest.chars().next()?.len_utf8()),
+        };
+        let (value, after) = delimited(value)?;
+        rest = after;
+        let value = Some(value.to_owned());
+        match field {
+            "p" => template.product = value,
+            "v" => template.version = value,
+            "i" => template.extra_info = value,
+            "h" => template.hostname = value,
+            "o" => template.os_type = value,
+            "d" => template.device_type = value,
+            "cpe" => {
+                template.cpe.push(format!("cpe:/{}", value?));
+                // The `a` flag only tells nmap the CPE belongs to the
+                // application rather than the operating system.
+                rest = rest.strip_prefix('a').unwrap_or(rest);
+            }
+            _ => return None,
+        }
+    }
+
+    Some(Match {
+        service: service.to_owned(),
+        soft,
+        pattern: pattern.to_owned(),
+        case_insensitive: flags.contains('i'),
+        dot_matches_new_line: flags.contains('s'),
+        regex: OnceCell::new(),
+        template,
+        line,
+    })
+}
+
+/// A comma separated list of ports and port ranges, like `21,80-85`.
+fn parse_ports(text: &str) -> Option<Vec<RangeInclusive<u16>>> {
+    text.split(',')
+        .map(|ports| {
+            let ports = ports.trim();
+            let (start, end) = ports.split_once('-').unwrap_or((ports, ports));
+            Some(start.parse().ok()?..=end.parse().ok()?)
+        })
+        .collect()
+}
+
+/// A list of ports where `T:` and `U:` limit the ports that follow to TCP
+/// or UDP, like `53,T:9100-9107`.
+fn parse_exclude(text: &str) -> Option<Vec<(Option<Protocol>, RangeInclusive<u16>)>> {
+    let mut protocol = None;
+    let mut excluded = Vec::new();
+    for ports in text.split(',') {
+        let mut ports = ports.trim();
+        if let Some(rest) = ports.strip_prefix("T:") {
+            protocol = Some(Protocol::Tcp);
+            ports = rest;
+        } else if let Some(rest) = ports.strip_prefix("U:") {
+            protocol = Some(Protocol::Udp);
+            ports = rest;
+        }
+        for range in parse_ports(ports)? {
+            excluded.push((protocol, range));
+        }
+    }
+    Some(excluded)
+}
+
+/// Splits `|value|rest`, where the delimiter is whatever character comes
+/// first, into the value and what follows it.
+fn delimited(text: &str) -> Option<(&str, &str)> {
+    let delimiter = text.chars().next()?;
+    let text = &text[delimiter.len_utf8()..];
+    let end = text.find(delimiter)?;
+    Some((&text[..end], &text[end + delimiter.len_utf8()..]))
+}
+
+/// Turns the C style escapes of a probe payload into the bytes they stand
+/// for.
+fn unescape(text: &str) -> Vec<u8> {
+    let text = text.as_bytes();
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut index = 0;
+    while index < text.len() {
+        let byte = text[index];
+        index += 1;
+        if byte != b'\\' || index == text.len() {
+            bytes.push(byte);
+            continue;
+        }
+
+        let escaped = text[index];
+        index += 1;
+        bytes.push(match escaped {
+            b'0' => 0,
+            b'a' => 0x07,
+            b'b' => 0x08,
+            b'f' => 0x0c,
+            b'n' => b'\n',
+            b'r' => b'\r',
+            b't' => b'\t',
+            b'v' => 0x0b,
+            b'x' => {
+                let hex = text
+                    .get(index..index + 2)
+                    .and_then(|hex| std::str::from_utf8(hex).ok())
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+                match hex {
+                    Some(value) => {
+                        index += 2;
+                        value
+                    }
+                    None => b'x',
+                }
+            }
+            other => other,
+        });
+    }
+    bytes
+}
+
+/// Tells whether a PCRE pattern uses back references like `\1`, which the
+/// `regex` crate would read as octal escapes.
+fn has_back_reference(pattern: &str) -> bool {
+    let mut bytes = pattern.bytes();
+    while let Some(byte) = bytes.next() {
+        if byte == b'\\' && bytes.next().is_some_and(|next| matches!(next, b'1'..=b'9')) {
+            return true;
+        }
+    }
+    false
+}
+
+fn invalid(line: usize, directive: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("invalid {directive} on line {line} of the service probes"),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Identification, ServiceProbes};
+    use crate::scanner::{Protocol, Service};
+
+    const PROBES: &str = r#"
+Exclude 53,T:9100-9102
+Probe TCP NULL q||
+totalwaitms 6000
+match ftp m|^220 ([\w.]+) FTP server \(Version ([\w.]+)\)|i p/$P(1) ftpd/ v/$SUBST(2,"_",".")/
+softmatch ssh m|^SSH-([\d.]+)-| i/protocol $1/
+
+Probe TCP GetRequest q|GET / HTTP/1.0\r\n\r\n|
+rarity 1
+ports 80-85,8080
+
+Probe TCP Rare q|\x01\x02\\|
+rarity 8
+ports 9999
+match ssh m|^SSH-2\.0-RareSSH (\d+)\r\n$| p/RareSSH/ v/$1/ cpe:/a:rare:rare_ssh:$1/a
+match length m|^\x03(..)|s i/$I(1,">") bytes/
+
+Probe UDP Echo q|ping|
+"#;
+
+    #[test]
+    fn picks_probes_like_nmap() {
+        let probes = ServiceProbes::parse(PROBES).unwrap();
+        let names = |port, protocol, intensity| {
+            probes
+                .probes_for(port, protocol, intensity)
+                .iter()
+                .map(|probe| probe.name.as_str())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(names(80, Protocol::Tcp, 7), ["NULL", "GetRequest"]);
+        assert_eq!(names(22, Protocol::Tcp, 0), ["NULL"]);
+        assert_eq!(names(9999, Protocol::Tcp, 0), ["NULL", "Rare"]);
+        assert_eq!(names(22, Protocol::Tcp, 9), ["NULL", "GetRequest", "Rare"]);
+        assert_eq!(names(7, Protocol::Udp, 7), ["Echo"]);
+        assert!(names(9101, Protocol::Tcp, 9).is_empty());
+        assert!(names(53, Protocol::Udp, 9).is_empty());
+
+        let rare = probes.probes_for(9999, Protocol::Tcp, 0)[1];
+        assert_eq!(rare.payload, b"\x01\x02\\");
+    }
+
+    #[test]
+    fn fills_in_version_info() {
+        let probes = ServiceProbes::parse(PROBES).unwrap();
+        let null = probes.probes_for(21, Protocol::Tcp, 0)[0];
+
+        assert_eq!(
+            probes.identify(
+                null,
+                b"220 files.example FTP SERVER (Version 2_3_4)\r\n",
+                None
+            ),
+            Some(Identification::Hard(Service {
+                name: "ftp".to_owned(),
+                product: Some("files.example ftpd".to_owned()),
+                version: Some("2.3.4".to_owned()),
+                ..Service::default()
+            }))
+        );
+        assert_eq!(probes.identify(null, b"HTTP/1.0 200 OK\r\n", None), None);
+    }
+
+    #[test]
+    fn softmatches_narrow_down_the_search() {
+        let probes = ServiceProbes::parse(PROBES).unwrap();
+        let tcp = probes.probes_for(9999, Protocol::Tcp, 9);
+        let (null, rare, get) = (tcp[0], tcp[1], tcp[2]);
+
+        let soft = probes.identify(null, b"SSH-2.0-RareSSH 7\r\n", None);
+        assert_eq!(
+            soft,
+            Some(Identification::Soft(Service {
+                name: "ssh".to_owned(),
+                extra_info: Some("protocol 2.0".to_owned()),
+                ..Service::default()
+            }))
+        );
+
+        // GetRequest has no matches of its own, but falls back to NULL.
+        assert!(probes.can_identify(get, "ssh"));
+        assert!(!probes.can_identify(get, "http"));
+        assert!(probes.can_identify(rare, "ssh"));
+        assert_eq!(
+            probes.identify(rare, b"SSH-2.0-RareSSH 7\r\n", Some("ssh")),
+            Some(Identification::Hard(Service {
+                name: "ssh".to_owned(),
+                product: Some("RareSSH".to_owned()),
+                version: Some("7".to_owned()),
+                cpe: vec!["cpe:/a:rare:rare_ssh:7".to_owned()],
+                ..Service::default()
+            }))
+        );
+        assert_eq!(probes.identify(rare, b"\x03\x01\x00", Some("ssh")), None);
+        assert_eq!(
+            probes.identify(rare, b"\x03\x01\x00", None),
+            Some(Identification::Hard(Service {
+                name: "length".to_owned(),
+                extra_info: Some("256 bytes".to_owned()),
+                ..Service::default()
+            }))
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_databases() {
+        assert!(ServiceProbes::parse("match ftp m|^220|").is_err());
+        assert!(ServiceProbes::parse("Probe SCTP NULL q||").is_err());
+        assert!(ServiceProbes::parse("Probe TCP NULL q||\nmatch ftp m|^220").is_err());
+        assert!(ServiceProbes::parse("Probe TCP NULL q||\nports 80-http").is_err());
+    }
+
+    #[test]
+    fn bundled_probes_identify_common_services() {
+        let probes = ServiceProbes::bundled();
+        for probe in &probes.probes {
+            for m in &probe.matches {
+                assert!(m.regex().is_some(), "match on line {} compiles", m.line);
+            }
+        }
+
+        let tcp = probes.probes_for(22, Protocol::Tcp, 7);
+        let Some(Identification::Hard(ssh)) = probes.identify(
+            tcp[0],
+            b"SSH-2.0-OpenSSH_9.6p1 Ubuntu-3ubuntu13.5\r\n",
+            None,
+        ) else {
+            panic!("OpenSSH is recognised");
+        };
+        assert_eq!(ssh.product.as_deref(), Some("OpenSSH"));
+        assert_eq!(ssh.version.as_deref(), Some("9.6p1 Ubuntu 3ubuntu13.5"));
+        assert_eq!(ssh.os_type.as_deref(), Some("Linux"));
+        assert_eq!(ssh.cpe[0], "cpe:/a:openbsd:openssh:9.6p1");
+
+        let tcp = probes.probes_for(8080, Protocol::Tcp, 7);
+        let get = tcp.iter().find(|probe| probe.name == "GetRequest").unwrap();
+        let Some(Identification::Hard(http)) = probes.identify(
+            get,
+            b"HTTP/1.1 200 OK\r\nDate: today\r\nServer: nginx/1.24.0\r\n\r\n",
+            None,
+        ) else {
+            panic!("nginx is recognised");
+        };
+        assert_eq!(http.to_string(), "http nginx 1.24.0");
+    }
+}
@@ -0,0 +1,507 @@
+use async_std::io::{self, ReadExt, WriteExt};
+use async_std::net::TcpStream;
+use base64::Engine;
+use serde_derive::Deserialize;
+use std::fmt;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::str::FromStr;
+
+/// Longest HTTP CONNECT answer head that is read before giving up on it.
+const MAX_HEAD_BYTES: usize = 8 * 1024;
+
+/// The protocols a proxy can be asked to open a tunnel with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProxyKind {
+    Socks5,
+    Http,
+}
+
+/// A proxy that TCP connections are relayed through, written as
+/// `socks5://[user:password@]host:port` or `http://[user:password@]host:port`.
+///
+/// Several of them make a chain: the connection goes to the first one,
+/// which is asked to open a tunnel to the second one and so on, the last
+/// one opening the tunnel to the target. Only what the last one answers
+/// says something about the port, any other failure is the chain's.
+///
+/// Neither `Display` nor `Debug` show the password, proxies end up in the
+/// logs.
+#[derive(Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Proxy {
+    kind: ProxyKind,
+    address: SocketAddr,
+    credentials: Option<(String, String)>,
+}
+
+impl Proxy {
+    pub fn new(kind: ProxyKind, address: SocketAddr) -> Self {
+        Self {
+            kind,
+            address,
+            credentials: None,
+        }
+    }
+
+    /// Authenticates with `user` and `password`.
+    #[must_use]
+    pub fn with_credentials(mut self, user: &str, password: &str) -> Self {
+        self.credentials = Some((user.to_owned(), password.to_owned()));
+        self
+    }
+
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// Asks the proxy at the other end of `stream` for a tunnel to `next`.
+    /// The outer error is a failure to talk to the proxy, the inner one is
+    /// the proxy telling us it couldn't reach `next`, with a kind that
+    /// matches what a direct connection would have failed with.
+    async fn open(
+        &self,
+        stream: &mut TcpStream,
+        next: SocketAddr,
+    ) -> io::Result<Result<(), io::Error>> {
+        match self.kind {
+            ProxyKind::Socks5 => self.open_socks5(stream, next).await,
+            ProxyKind::Http => self.open_http(stream, next).await,
+        }
+    }
+
+    async fn open_socks5(
+        &self,
+        stream: &mut TcpStream,
+        next: SocketAddr,
+    ) -> io::Result<Result<(), io::Error>> {
+        const NO_AUTHENTICATION: u8 = 0x00;
+        const USERNAME_PASSWORD: u8 = 0x02;
+
+        let greeting: &[u8] = match self.credentials {
+            Some(_) => &[5, 2, NO_AUTHENTICATION, USERNAME_PASSWORD],
+            None => &[5, 1, NO_AUTHENTICATION],
+        };
+        stream.write_all(greeting).await?;
+        let mut choice = [0u8; 2];
+        stream.read_exact(&mut choice).await?;
+        match (choice, &self.credentials) {
+            ([5, NO_AUTHENTICATION], _) => {}
+            ([5, USERNAME_PASSWORD], Some((user, password))) => {
+                if user.len() > 255 || password.len() > 255 {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "SOCKS5 credentials are limited to 255 bytes",
+                    ));
+                }
+                let mut request = vec![1, user.len() as u8];
+                request.extend_from_slice(user.as_bytes());
+                request.push(password.len() as u8);
+                request.extend_from_slice(password.as_bytes());
+                stream.write_all(&request).await?;
+                let mut status = [0u8; 2];
+                stream.read_exact(&mut status).await?;
+                if status[1] != 0 {
+                    return Err(io::Error::new(
+                        io::ErrorKind::PermissionDenied,
+                        "the credentials were refused",
+                    ));
+                }
+            }
+            ([5, _], _) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    "none of our authentication methods were accepted",
+                ))
+            }
+            _ => return Err(malformed("SOCKS5")),
+        }
+
+        let mut request = vec![5, 1, 0];
+        match next {
+            SocketAddr::V4(next) => {
+                request.push(1);
+                request.extend_from_slice(&next.ip().octets());
+            }
+            SocketAddr::V6(next) => {
+                request.push(4);
+                request.extend_from_slice(&next.ip().octets());
+            }
+        }
+        request.extend_from_slice(&next.port().to_be_bytes());
+        stream.write_all(&request).await?;
+
+        let mut reply = [0u8; 4];
+        stream.read_exact(&mut reply).await?;
+        if reply[0] != 5 {
+            return Err(malformed("SOCKS5"));
+        }
+        // The address the proxy bound follows, it has to be read past for
+        // the tunnel to start in the right place.
+        let bound = match reply[3] {
+            1 => 4,
+            4 => 16,
+            3 => {
+                let mut length = [0u8; 1];
+                stream.read_exact(&mut length).await?;
+                usize::from(length[0])
+            }
+            _ => return Err(malformed("SOCKS5")),
+        };
+        stream.read_exact(&mut vec![0u8; bound + 2]).await?;
+
+        let kind = match reply[1] {
+            0x00 => return Ok(Ok(())),
+            0x03 => io::ErrorKind::NetworkUnreachable,
+            0x04 => io::ErrorKind::HostUnreachable,
+            0x05 => io::ErrorKind::ConnectionRefused,
+            0x06 => io::ErrorKind::TimedOut,
+            _ => io::ErrorKind::Other,
+        };
+        Ok(Err(io::Error::new(
+            kind,
+            format!("SOCKS5 reply {:#04x}", reply[1]),
+        )))
+    }
+
+    async fn open_http(
+        &self,
+        stream: &mut TcpStream,
+        next: SocketAddr,
+    ) -> io::Result<Result<(), io::Error>> {
+        let mut request = format!("CONNECT {next} HTTP/1.1\r\nHost: {next}\r\n");
+        if let Some((user, password)) = &self.credentials {
+            let token =
+                base64::engine::general_purpose::STANDARD.encode(format!("{user}:{password}"));
+            request.push_str(&format!("Proxy-Authorization: Basic {token}\r\n"));
+        }
+        request.push_str("\r\n");
+        stream.write_all(request.as_bytes()).await?;
+
+        // The tunnel starts right after the head, so it is read a byte at a
+        // time to not take anything from it.
+        let mut head = Vec::new();
+        while !head.ends_with(b"\r\n\r\n") {
+            if head.len() == MAX_HEAD_BYTES {
+                return Err(malformed("HTTP"));
+            }
+            let mut byte = [0u8; 1];
+            stream.read_exact(&mut byte).await?;
+            head.push(byte[0]);
+        }
+        let head = String::from_utf8_lossy(&head);
+        let mut status_line = head.split_whitespace();
+        let status: u16 = match (status_line.next(), status_line.next()) {
+            (Some(version), Some(status)) if version.starts_with("HTTP/") => {
+                status.parse().map_err(|_| malformed("HTTP"))?
+            }
+            _ => return Err(malformed("HTTP")),
+        };
+
+        // Proxies don't agree on how to tell a refused connection from an
+        // unreachable host, 502 and 503 are what most answer either with.
+        let kind = match status {
+            200..=299 => return Ok(Ok(())),
+            407 => {
+                return Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    "the proxy wants credentials",
+                ))
+            }
+            502 | 503 => io::ErrorKind::ConnectionRefused,
+            504 => io::ErrorKind::TimedOut,
+            _ => io::ErrorKind::Other,
+        };
+        Ok(Err(io::Error::new(kind, format!("HTTP status {status}"))))
+    }
+}
+
+impl fmt::Display for Proxy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let scheme = match self.kind {
+            ProxyKind::Socks5 => "socks5",
+            ProxyKind::Http => "http",
+        };
+        write!(f, "{scheme}://{}", self.address)
+    }
+}
+
+impl fmt::Debug for Proxy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Proxy")
+            .field("kind", &self.kind)
+            .field("address", &self.address)
+            .field(
+                "credentials",
+                &self
+                    .credentials
+                    .as_ref()
+                    .map(|(user, _)| (user, "<hidden>")),
+            )
+            .finish()
+    }
+}
+
+impl FromStr for Proxy {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let (scheme, rest) = input
+            .split_once("://")
+            .ok_or_else(|| format!("{input} is not a proxy URL, like socks5://127.0.0.1:1080"))?;
+        let kind = match scheme.to_ascii_lowercase().as_str() {
+            "socks5" | "socks5h" => ProxyKind::Socks5,
+            "http" => ProxyKind::Http,
+            _ => {
+                return Err(format!(
+                    "proxies must be socks5:// or http://, not {scheme}://"
+                ))
+            }
+        };
+        let (credentials, host) = match rest.trim_end_matches('/').rsplit_once('@') {
+            Some((credentials, host)) => (Some(credentials), host),
+            None => (None, rest.trim_end_matches('/')),
+        };
+        let address = host
+            .to_socket_addrs()
+            .map_err(|e| format!("can't resolve the proxy {host}: {e}"))?
+            .next()
+            .ok_or_else(|| format!("can't resolve the proxy {host}"))?;
+
+        let proxy = Proxy::new(kind, address);
+        Ok(match credentials {
+            Some(credentials) => {
+                let (user, password) = credentials.split_once(':').unwrap_or((credentials, ""));
+                proxy.with_credentials(user, password)
+            }
+            None => proxy,
+        })
+    }
+}
+
+impl TryFrom<String> for Proxy {
+    type Error = String;
+
+    fn try_from(input: String) -> Result<Self, Self::Error> {
+        input.parse()
+    }
+}
+
+/// Turns the connection to the first proxy of `proxies` into a tunnel to
+/// `target`.
+///
+/// Only when the last proxy can't reach the target is the error of a kind
+/// `PortState::from_connect_error` knows, anything going wrong before that
+/// is a failure of the chain and says nothing about the port.
+pub(crate) async fn tunnel(
+    stream: &mut TcpStream,
+    proxies: &[Proxy],
+    target: SocketAddr,
+) -> io::Result<()> {
+    for (hop, proxy) in proxies.iter().enumerate() {
+        let next = proxies.get(hop + 1).map_or(target, Proxy::address);
+        let refusal = match proxy.open(stream, next).await {
+            Ok(Ok(())) => continue,
+            Ok(Err(refusal)) => refusal,
+            Err(e) => return Err(chain_error(proxy, &e)),
+        };
+        let kind = if hop + 1 == proxies.len() {
+            refusal.kind()
+        } else {
+            io::ErrorKind::Other
+        };
+        return Err(io::Error::new(
+            kind,
+            format!("proxy {proxy} can't reach {next}: {refusal}"),
+        ));
+    }
+    Ok(())
+}
+
+/// Wraps a failure to talk to `proxy` so it can't be taken for an answer
+/// of the port.
+pub(crate) fn chain_error(proxy: &Proxy, e: &io::Error) -> io::Error {
+    io::Error::other(format!("proxy {proxy}: {e}"))
+}
+
+fn malformed(protocol: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("malformed {protocol} proxy answer"),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Proxy, ProxyKind};
+    use crate::scanner::Source;
+    use async_std::task::block_on;
+    use std::io::{self, BufRead, BufReader, Read, Write};
+    use std::net::{SocketAddr, TcpListener, TcpStream};
+    use std::time::Duration;
+
+    /// A SOCKS5 proxy that only takes `azula:secret` and relays the tunnel
+    /// both ways.
+    fn socks5_proxy() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            for client in listener.incoming() {
+                let client = client.unwrap();
+                std::thread::spawn(move || serve_socks5(client));
+            }
+        });
+        address
+    }
+
+    fn serve_socks5(mut client: TcpStream) -> io::Result<()> {
+        let mut greeting = [0u8; 2];
+        client.read_exact(&mut greeting)?;
+        client.read_exact(&mut vec![0u8; greeting[1].into()])?;
+        client.write_all(&[5, 2])?;
+
+        let mut version = [0u8; 2];
+        client.read_exact(&mut version)?;
+        let mut user = vec![0u8; version[1].into()];
+        client.read_exact(&mut user)?;
+        let mut length = [0u8; 1];
+        client.read_exact(&mut length)?;
+        let mut password = vec![0u8; length[0].into()];
+        client.read_exact(&mut password)?;
+        if user != b"azula" || password != b"secret" {
+            return client.write_all(&[1, 1]);
+        }
+        client.write_all(&[1, 0])?;
+
+        let mut request = [0u8; 10];
+        client.read_exact(&mut request)?;
+        let target = SocketAddr::from((
+            [request[4], request[5], request[6], request[7]],
+            u16::from_be_bytes([request[8], request[9]]),
+        ));
+        match TcpStream::connect(target) {
+            Ok(upstream) => {
+                client.write_all(&[5, 0, 0, 1, 127, 0, 0, 1, 0, 0])?;
+                relay(client, upstream)
+            }
+            Err(_) => client.write_all(&[5, 5, 0, 1, 0, 0, 0, 0, 0, 0]),
+        }
+    }
+
+    /// An HTTP CONNECT proxy that answers 502 when the target is closed.
+    fn http_proxy() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            for client in listener.incoming() {
+                let client = client.unwrap();
+                std::thread::spawn(move || serve_http(client));
+            }
+        });
+        address
+    }
+
+    fn serve_http(client: TcpStream) -> io::Result<()> {
+        let mut client = BufReader::new(client);
+        let mut request_line = String::new();
+        client.read_line(&mut request_line)?;
+        let mut line = String::new();
+        while client.read_line(&mut line)? > 2 {
+            line.clear();
+        }
+        let target = request_line.split_whitespace().nth(1).unwrap_or_default();
+        let mut client = client.into_inner();
+        match TcpStream::connect(target) {
+            Ok(upstream) => {
+                client.write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")?;
+                relay(client, upstream)
+            }
+            Err(_) => client.write_all(b"HTTP/1.1 502 Bad Gateway\r\n\r\n"),
+        }
+    }
+
+    fn relay(client: TcpStream, upstream: TcpStream) -> io::Result<()> {
+        let (mut client_reader, mut upstream_writer) = (client.try_clone()?, upstream.try_clone()?);
+        std::thread::spawn(move || io::copy(&mut client_reader, &mut upstream_writer));
+        let (mut upstream_reader, mut client_writer) = (upstream, client);
+        io::copy(&mut upstream_reader, &mut client_writer).map(|_| ())
+    }
+
+    fn closed_port() -> SocketAddr {
+        TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+    }
+
+    #[test]
+    fn parses_proxy_urls() {
+        let proxy: Proxy = "SOCKS5://azula:s3:cret@127.0.0.1:1080".parse().unwrap();
+
+        assert_eq!(
+            proxy,
+            Proxy::new(ProxyKind::Socks5, "127.0.0.1:1080".parse().unwrap())
+                .with_credentials("azula", "s3:cret")
+        );
+        assert_eq!(proxy.to_string(), "socks5://127.0.0.1:1080");
+        let debug = format!("{proxy:?}");
+        assert!(debug.contains("azula"));
+        assert!(!debug.contains("s3:cret"));
+        assert!("ftp://127.0.0.1:21".parse::<Proxy>().is_err());
+        assert!("127.0.0.1:1080".parse::<Proxy>().is_err());
+    }
+
+    #[test]
+    fn socks5_replies_say_whether_ports_are_open() {
+        let open = TcpListener::bind("127.0.0.1:0").unwrap();
+        let proxy =
+            Proxy::new(ProxyKind::Socks5, socks5_proxy()).with_credentials("azula", "secret");
+        let source = Source::default().with_proxies(vec![proxy]);
+        let timeout = Duration::from_secs(2);
+
+        let mut stream = block_on(source.connect(open.local_addr().unwrap(), timeout)).unwrap();
+        let (mut accepted, _) = open.accept().unwrap();
+        accepted.write_all(b"SSH-2.0-azula\r\n").unwrap();
+        let mut banner = [0u8; 15];
+        block_on(async_std::io::ReadExt::read_exact(&mut stream, &mut banner)).unwrap();
+        assert_eq!(&banner, b"SSH-2.0-azula\r\n");
+
+        let error = block_on(source.connect(closed_port(), timeout)).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::ConnectionRefused);
+    }
+
+    #[test]
+    fn refused_credentials_are_not_a_closed_port() {
+        let open = TcpListener::bind("127.0.0.1:0").unwrap();
+        let proxy =
+            Proxy::new(ProxyKind::Socks5, socks5_proxy()).with_credentials("azula", "guess");
+        let source = Source::default().with_proxies(vec![proxy]);
+
+        let error = block_on(source.connect(open.local_addr().unwrap(), Duration::from_secs(2)))
+            .unwrap_err();
+
+        assert_eq!(error.kind(), io::ErrorKind::Other);
+        assert!(error.to_string().contains("credentials were refused"));
+    }
+
+    #[test]
+    fn chains_proxies() {
+        let open = TcpListener::bind("127.0.0.1:0").unwrap();
+        let source = Source::default().with_proxies(vec![
+            Proxy::new(ProxyKind::Socks5, socks5_proxy()).with_credentials("azula", "secret"),
+            Proxy::new(ProxyKind::Http, http_proxy()),
+        ]);
+        let timeout = Duration::from_secs(2);
+
+        assert!(block_on(source.connect(open.local_addr().unwrap(), timeout)).is_ok());
+        let error = block_on(source.connect(closed_port(), timeout)).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::ConnectionRefused);
+
+        // The HTTP proxy is down, which is the chain failing, not the port.
+        let source = Source::default().with_proxies(vec![
+            Proxy::new(ProxyKind::Socks5, socks5_proxy()).with_credentials("azula", "secret"),
+            Proxy::new(ProxyKind::Http, closed_port()),
+        ]);
+        let error = block_on(source.connect(open.local_addr().unwrap(), timeout)).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::Other);
+    }
+}
@@ -0,0 +1,207 @@
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+/// How much of the allowed rate may be sent in a single burst.
+const BURST: Duration = Duration::from_millis(50);
+
+/// Keeps the number of probes sent per second between an optional minimum
+/// and an optional maximum, and probes at least `delay` apart.
+///
+/// The maximum is enforced with a token bucket refilled at `max` tokens
+/// per second, every probe takes one token. The minimum is only a hint
+/// telling the scanner it is falling behind and should send more probes
+/// than the congestion window would allow.
+#[derive(Debug)]
+pub struct RateLimiter {
+    min: Option<f64>,
+    max: Option<f64>,
+    tokens: f64,
+    capacity: f64,
+    refilled_at: Instant,
+    delay: Duration,
+    sent_at: Option<Instant>,
+    started_at: Instant,
+    sent: u64,
+}
+
+impl RateLimiter {
+    pub fn new(min: Option<u32>, max: Option<u32>, delay: Duration) -> Self {
+        let max = max.map(f64::from);
+        let capacity = max.map_or(0.0, |max| (max * BURST.as_secs_f64()).max(1.0));
+        let now = Instant::now();
+        Self {
+            min: min.map(f64::from),
+            max,
+            tokens: capacity,
+            capacity,
+            refilled_at: now,
+            delay,
+            sent_at: None,
+            started_at: now,
+            sent: 0,
+        }
+    }
+
+    /// Takes a token for a probe about to be sent, returns false when the
+    /// probe has to wait to stay under the maximum rate or the delay.
+    pub fn try_acquire(&mut self) -> bool {
+        self.try_acquire_at(Instant::now())
+    }
+
+    /// How long to wait for the next token, `None` when there is no maximum
+    /// rate nor delay or a token is available right away.
+    pub fn wait_time(&mut self) -> Option<Duration> {
+        self.wait_time_at(Instant::now())
+    }
+
+    /// Tells whether fewer probes than the minimum rate asks for have been
+    /// sent so far.
+    pub fn behind_min_rate(&self) -> bool {
+        self.behind_min_rate_at(Instant::now())
+    }
+
+    fn try_acquire_at(&mut self, now: Instant) -> bool {
+        if self.delay_left(now).is_some() {
+            return false;
+        }
+        if self.max.is_some() {
+            self.refill(now);
+            if self.tokens < 1.0 {
+                return false;
+            }
+            self.tokens -= 1.0;
+        }
+        self.sent_at = Some(now);
+        self.sent += 1;
+        true
+    }
+
+    fn wait_time_at(&mut self, now: Instant) -> Option<Duration> {
+        let delay_left = self.delay_left(now);
+        let token_wait = self.max.and_then(|max| {
+            self.refill(now);
+            (self.tokens < 1.0).then(|| Duration::from_secs_f64((1.0 - self.tokens) / max))
+        });
+        delay_left.max(token_wait)
+    }
+
+    fn delay_left(&self, now: Instant) -> Option<Duration> {
+        let since_sent = now.duration_since(self.sent_at?);
+        (since_sent < self.delay).then(|| self.delay - since_sent)
+    }
+
+    fn behind_min_rate_at(&self, now: Instant) -> bool {
+        self.min.is_some_and(|min| {
+            #[allow(clippy::cast_precision_loss)]
+            let sent = self.sent as f64;
+            sent < min * now.duration_since(self.started_at).as_secs_f64()
+        })
+    }
+
+    fn refill(&mut self, now: Instant) {
+        if let Some(max) = self.max {
+            let elapsed = now.duration_since(self.refilled_at).as_secs_f64();
+            self.tokens = (self.tokens + elapsed * max).min(self.capacity);
+            self.refilled_at = now;
+        }
+    }
+}
+
+/// Locks a rate limiter shared by the scan loop, which takes a token for
+/// the first try of every probe, and the probes taking one for each try
+/// after that.
+pub fn lock(rate: &Mutex<RateLimiter>) -> MutexGuard<'_, RateLimiter> {
+    rate.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Waits until `rate` lets another probe out and takes its token.
+pub async fn acquire(rate: &Mutex<RateLimiter>) {
+    loop {
+        let wait = {
+            let mut rate = lock(rate);
+            if rate.try_acquire() {
+                return;
+            }
+            rate.wait_time().unwrap_or_default()
+        };
+        async_std::task::sleep(wait).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{acquire, lock, RateLimiter};
+    use async_std::task::block_on;
+    use std::sync::Mutex;
+    use std::time::{Duration, Instant};
+
+    const NO_DELAY: Duration = Duration::ZERO;
+
+    #[test]
+    fn unlimited_without_max_rate() {
+        let mut rate = RateLimiter::new(None, None, NO_DELAY);
+        assert!((0..10_000).all(|_| rate.try_acquire()));
+        assert_eq!(rate.wait_time(), None);
+        assert!(!rate.behind_min_rate());
+    }
+
+    #[test]
+    fn max_rate_empties_the_bucket() {
+        let mut rate = RateLimiter::new(None, Some(100), NO_DELAY);
+        let now = rate.started_at;
+
+        // 100 probes per second allow bursts of 5 probes.
+        assert!((0..5).all(|_| rate.try_acquire_at(now)));
+        assert!(!rate.try_acquire_at(now));
+        let wait = rate.wait_time_at(now).unwrap();
+        assert!(wait > Duration::from_millis(9) && wait <= Duration::from_millis(10));
+
+        let later = now + Duration::from_millis(20);
+        assert_eq!(rate.wait_time_at(later), None);
+        assert!(rate.try_acquire_at(later));
+        assert!(rate.try_acquire_at(later));
+        assert!(!rate.try_acquire_at(later));
+    }
+
+    #[test]
+    fn falls_behind_min_rate() {
+        let mut rate = RateLimiter::new(Some(100), None, NO_DELAY);
+        let now = rate.started_at;
+        assert!(!rate.behind_min_rate_at(now));
+        assert!(rate.behind_min_rate_at(now + Duration::from_millis(100)));
+
+        for _ in 0..10 {
+            rate.try_acquire_at(now);
+        }
+        assert!(!rate.behind_min_rate_at(now + Duration::from_millis(100)));
+    }
+
+    #[test]
+    fn acquire_waits_for_a_token() {
+        let rate = Mutex::new(RateLimiter::new(None, Some(100), NO_DELAY));
+        let started = Instant::now();
+
+        // A burst of 5, then one token every 10ms.
+        block_on(async {
+            for _ in 0..10 {
+                acquire(&rate).await;
+            }
+        });
+        assert!(started.elapsed() >= Duration::from_millis(40));
+        assert_eq!(lock(&rate).sent, 10);
+    }
+
+    #[test]
+    fn delay_spaces_probes_out() {
+        let mut rate = RateLimiter::new(None, None, Duration::from_millis(400));
+        let now = rate.started_at;
+
+        assert!(rate.try_acquire_at(now));
+        assert!(!rate.try_acquire_at(now + Duration::from_millis(100)));
+        assert_eq!(
+            rate.wait_time_at(now + Duration::from_millis(100)),
+            Some(Duration::from_millis(300))
+        );
+        assert!(rate.try_acquire_at(now + Duration::from_millis(400)));
+    }
+}
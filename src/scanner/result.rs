@@ -0,0 +1,441 @@
+use clap::ValueEnum;
+use serde::{Deserializer, Serializer};
+use serde_derive::{Deserialize, Serialize};
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+/// The transport protocol a port was scanned with.
+#[derive(
+    Serialize, Deserialize, Debug, ValueEnum, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+    Tcp,
+    Udp,
+}
+
+/// The state a port was found in.
+///   - open means the port accepted the connection or answered the probe.
+///   - closed means the host actively refused the connection (RST).
+///   - filtered means nothing answered before the timeout, or the probe
+///     was rejected as unreachable on the way, usually by a firewall.
+///   - open|filtered is used for UDP ports that stayed silent, since both
+///     an open service ignoring the payload and a firewall dropping it look
+///     the same from here.
+#[derive(Serialize, Deserialize, Debug, ValueEnum, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum PortState {
+    Open,
+    Closed,
+    Filtered,
+    #[serde(rename = "open|filtered", alias = "open-filtered")]
+    OpenFiltered,
+}
+
+impl PortState {
+    /// Works out the state of a port from the error returned while
+    /// connecting to it, or `None` when the error says nothing about the
+    /// port itself, for example when we ran out of file descriptors.
+    pub fn from_connect_error(kind: io::ErrorKind) -> Option<Self> {
+        match kind {
+            io::ErrorKind::ConnectionRefused | io::ErrorKind::ConnectionReset => {
+                Some(PortState::Closed)
+            }
+            io::ErrorKind::TimedOut
+            | io::ErrorKind::HostUnreachable
+            | io::ErrorKind::NetworkUnreachable => Some(PortState::Filtered),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PortState::Open => "open",
+            PortState::Closed => "closed",
+            PortState::Filtered => "filtered",
+            PortState::OpenFiltered => "open|filtered",
+        }
+    }
+}
+
+/// Whether a host looked alive.
+///   - up means it answered host discovery, or discovery was skipped.
+///   - down means it answered none of the discovery pings, so its ports
+///     were not scanned. Those are counted in `ScanReport::hosts_down`,
+///     and only listed when there are few enough targets, see
+///     `MAX_LISTED_TARGETS`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum HostStatus {
+    Up,
+    Down,
+}
+
+/// Outcome of probing a single port on a host.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct PortResult {
+    pub port: u16,
+    pub protocol: Protocol,
+    pub state: PortState,
+    /// Time between sending the probe and getting the answer back, or
+    /// giving up on it for filtered ports.
+    #[serde(
+        rename = "rtt_ms",
+        serialize_with = "serialize_millis",
+        deserialize_with = "deserialize_millis"
+    )]
+    pub rtt: Duration,
+    /// What the service sent right after the connection was accepted, with
+    /// anything unprintable escaped. Only grabbed when asked for.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub banner: Option<String>,
+    /// The service found listening on the port by service detection.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub service: Option<Service>,
+    /// What the port presented during a TLS handshake, when it speaks TLS
+    /// and was asked.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tls: Option<TlsInfo>,
+    /// How the port answered `GET /`, when it speaks HTTP and was asked.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub http: Option<HttpInfo>,
+}
+
+impl PortResult {
+    pub fn new(port: u16, protocol: Protocol, state: PortState, rtt: Duration) -> Self {
+        Self {
+            port,
+            protocol,
+            state,
+            rtt,
+            banner: None,
+            service: None,
+            tls: None,
+            http: None,
+        }
+    }
+}
+
+/// What service detection found out about the service behind a port, as
+/// described by the match of the service probe database that recognised
+/// its answer.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Service {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub product: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub extra_info: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub os_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device_type: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub cpe: Vec<String>,
+}
+
+impl fmt::Display for Service {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.name)?;
+        for part in [&self.product, &self.version].into_iter().flatten() {
+            write!(f, " {part}")?;
+        }
+        if let Some(extra_info) = &self.extra_info {
+            write!(f, " ({extra_info})")?;
+        }
+        Ok(())
+    }
+}
+
+/// The outcome of a TLS handshake and the certificate that came with it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TlsInfo {
+    /// Negotiated protocol version, like `TLSv1.3`.
+    pub version: String,
+    /// Negotiated cipher suite, like `TLS13_AES_256_GCM_SHA384`.
+    pub cipher: String,
+    /// Subject of the certificate, like `CN=example.com, O=Example`.
+    pub subject: String,
+    pub issuer: String,
+    /// The subject alternative names of the certificate, like
+    /// `DNS:example.com` or `IP:192.0.2.1`.
+    pub sans: Vec<String>,
+    /// Start of the validity period of the certificate, in RFC 3339.
+    pub not_before: String,
+    /// End of the validity period of the certificate, in RFC 3339.
+    pub not_after: String,
+    /// Why the full handshake failed, when it did. `version`, `cipher` and
+    /// the certificate then come from the answer to a hello offering older
+    /// versions and cipher suites, see `TlsProbe`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub handshake_error: Option<String>,
+    /// Every version the service took in those hellos, newest first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub versions: Vec<String>,
+}
+
+/// How a web server answered `GET /`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct HttpInfo {
+    /// Whether the request went over TLS.
+    pub tls: bool,
+    pub status: u16,
+    /// The `Server` header.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub server: Option<String>,
+    /// The `<title>` of the page, with its whitespace collapsed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    /// The `Location` header, which redirects aren't followed to.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub location: Option<String>,
+    /// The `Content-Length` header, or the size of the body that was read
+    /// when there is none.
+    pub content_length: u64,
+}
+
+/// Output of a single script run against a host.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ScriptOutput {
+    pub script: String,
+    /// The protocol of the open ports the script was run against.
+    pub protocol: Protocol,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Everything known about one of the scanned targets.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct HostResult {
+    pub ip: IpAddr,
+    /// The hostname the IP was resolved from, when the target was given as one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+    pub status: HostStatus,
+    /// Whether the scan gave up on the host before it was done with it, for
+    /// running out of time or of local resources, so ports may be missing
+    /// from `ports`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub incomplete: bool,
+    pub ports: Vec<PortResult>,
+    pub scripts: Vec<ScriptOutput>,
+    /// What went wrong while probing ports of the host in ways that say
+    /// nothing about the state of the port, like a local socket error,
+    /// each distinct error once. The ports it happened to are missing from
+    /// `ports`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<String>,
+}
+
+impl HostResult {
+    pub fn new(ip: IpAddr) -> Self {
+        Self {
+            ip,
+            hostname: None,
+            status: HostStatus::Up,
+            incomplete: false,
+            ports: Vec::new(),
+            scripts: Vec::new(),
+            errors: Vec::new(),
+        }
+    }
+
+    /// Port numbers found open on this host, in the order they are stored.
+    pub fn open_ports(&self) -> Vec<u16> {
+        self.ports
+            .iter()
+            .filter(|port| port.state == PortState::Open)
+            .map(|port| port.port)
+            .collect()
+    }
+
+    /// Same as `open_ports`, but only the ones found over `protocol`.
+    pub fn open_ports_over(&self, protocol: Protocol) -> Vec<u16> {
+        self.ports
+            .iter()
+            .filter(|port| port.state == PortState::Open && port.protocol == protocol)
+            .map(|port| port.port)
+            .collect()
+    }
+}
+
+/// How many targets a scan may have for every one of them to get a
+/// `HostResult`, as many as a `/16` holds.
+pub const MAX_LISTED_TARGETS: u128 = 1 << 16;
+
+/// How the ports of a protocol were scanned.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum ScanType {
+    /// Full TCP connections.
+    Connect,
+    /// Half-open TCP connections, see `Scanner::with_syn_scan`.
+    Syn,
+    Udp,
+}
+
+/// The scan of the ports of one protocol, what nmap calls the scan info.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct ScanInfo {
+    #[serde(rename = "type")]
+    pub scan_type: ScanType,
+    pub protocol: Protocol,
+    /// How many ports were scanned.
+    pub ports: usize,
+    /// The ports scanned, as ascending ranges such as `1-1024,8080`.
+    pub services: String,
+}
+
+impl ScanInfo {
+    pub fn new(scan_type: ScanType, protocol: Protocol, ports: &[u16]) -> Self {
+        let mut sorted = ports.to_vec();
+        sorted.sort_unstable();
+        sorted.dedup();
+        let mut ranges: Vec<(u16, u16)> = Vec::new();
+        for port in &sorted {
+            match ranges.last_mut() {
+                Some((_, end)) if end.checked_add(1) == Some(*port) => *end = *port,
+                _ => ranges.push((*port, *port)),
+            }
+        }
+        let services = ranges
+            .iter()
+            .map(|&(start, end)| {
+                if start == end {
+                    start.to_string()
+                } else {
+                    format!("{start}-{end}")
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(",");
+        Self {
+            scan_type,
+            protocol,
+            ports: sorted.len(),
+            services,
+        }
+    }
+}
+
+/// The report of a whole scan as returned by `Scanner::run`.
+///
+/// There is one `HostResult` for every target, the ones down included, in
+/// ascending order of IP. Scans of more than `MAX_LISTED_TARGETS` targets
+/// only list the ones something was found about, a port in one of the
+/// reported states, an error or the scan giving up on it before it was
+/// done with it, ranges can hold far too many targets to list. The other
+/// targets are only counted then.
+#[derive(Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct ScanReport {
+    /// How the ports of each protocol were scanned.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub scans: Vec<ScanInfo>,
+    pub hosts: Vec<HostResult>,
+    /// How many targets the scanner was given.
+    pub targets: u128,
+    /// How many targets didn't answer host discovery, so their ports
+    /// weren't scanned.
+    pub hosts_down: u128,
+    /// How many targets the scan ran out of time before sending a single
+    /// probe to, see `Scanner::with_max_scan_time`.
+    pub hosts_unscanned: u128,
+    /// Seconds since the Unix epoch at which the scan started.
+    pub started_at: u64,
+    #[serde(rename = "elapsed_ms", serialize_with = "serialize_millis")]
+    pub elapsed: Duration,
+    /// Whether the scan was stopped before it was done, leaving out the
+    /// ports it didn't get to, see `Scanner::with_stop_signal`.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub interrupted: bool,
+}
+
+impl ScanReport {
+    /// Returns every open socket found during the scan.
+    pub fn open_sockets(&self) -> Vec<SocketAddr> {
+        self.hosts
+            .iter()
+            .flat_map(|host| {
+                host.open_ports()
+                    .into_iter()
+                    .map(move |port| SocketAddr::new(host.ip, port))
+            })
+            .collect()
+    }
+}
+
+pub(super) fn serialize_millis<S: Serializer>(
+    duration: &Duration,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_secs_f64() * 1_000.0)
+}
+
+pub(super) fn deserialize_millis<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Duration, D::Error> {
+    let millis: f64 = serde::Deserialize::deserialize(deserializer)?;
+    Duration::try_from_secs_f64(millis / 1_000.0).map_err(serde::de::Error::custom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{HostResult, PortResult, PortState, Protocol, ScanReport};
+    use std::io::ErrorKind;
+    use std::net::{IpAddr, SocketAddr};
+    use std::time::Duration;
+
+    #[test]
+    fn port_state_from_connect_errors() {
+        assert_eq!(
+            PortState::from_connect_error(ErrorKind::ConnectionRefused),
+            Some(PortState::Closed)
+        );
+        assert_eq!(
+            PortState::from_connect_error(ErrorKind::TimedOut),
+            Some(PortState::Filtered)
+        );
+        assert_eq!(
+            PortState::from_connect_error(ErrorKind::HostUnreachable),
+            Some(PortState::Filtered)
+        );
+        assert_eq!(PortState::from_connect_error(ErrorKind::Other), None);
+    }
+
+    #[test]
+    fn open_sockets_across_hosts() {
+        let first: IpAddr = "127.0.0.1".parse().unwrap();
+        let second: IpAddr = "192.168.0.1".parse().unwrap();
+        let mut host = HostResult::new(first);
+        host.ports.push(PortResult::new(
+            80,
+            Protocol::Tcp,
+            PortState::Open,
+            Duration::from_millis(3),
+        ));
+        host.ports.push(PortResult::new(
+            81,
+            Protocol::Tcp,
+            PortState::Closed,
+            Duration::from_millis(3),
+        ));
+        let result = ScanReport {
+            hosts: vec![host, HostResult::new(second)],
+            targets: 2,
+            started_at: 0,
+            elapsed: Duration::from_secs(1),
+            ..Default::default()
+        };
+
+        assert_eq!(result.open_sockets(), vec![SocketAddr::new(first, 80)]);
+        assert!(result.hosts[1].open_ports().is_empty());
+    }
+}
@@ -0,0 +1,305 @@
+//! Where the scan is at with each host, worked out from the position of a
+//! socket in the scan order rather than kept track of per host, which
+//! would cost memory for every target of a range.
+//!
+//! Both orders go through the sockets of a protocol in rounds holding one
+//! socket of every live host. The serial order holds a port and goes
+//! through the hosts, and the random one steps through the sockets by a
+//! number coprime with their count, so with the host count too. A host
+//! comes up at the same position in every round of a protocol, its first
+//! socket is in the first round and its last one in the last round.
+
+use super::Protocol;
+use crate::port::Permutation;
+use crate::targets::TargetSet;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+/// How many of the times hosts started at are kept, see `Rounds::starts`.
+const MAX_START_SAMPLES: usize = 4_096;
+
+/// The random number generator the order of the sockets of `protocol` is
+/// drawn from, each protocol getting an order of its own.
+pub(super) fn order_rng(seed: u64, protocol: Protocol) -> StdRng {
+    StdRng::seed_from_u64(seed ^ protocol as u64)
+}
+
+/// The rounds a scan goes through the sockets of `live` in, along with the
+/// times needed to tell when a host went past its host timeout.
+pub(super) struct Rounds<'r> {
+    live: &'r TargetSet,
+    /// How many rounds each protocol with ports to scan takes, one per
+    /// port, and the order its sockets are gone through in when random.
+    blocks: Vec<(u128, Option<Permutation>)>,
+    host_timeout: Option<Duration>,
+    /// When the scan got to some of the sockets of the first round, the
+    /// hosts whose first socket comes in between count as started at the
+    /// earlier one. Thinned out to every other one when there are too
+    /// many, which halves the resolution.
+    starts: Vec<(u128, Instant)>,
+    resolution: Duration,
+    /// When the scan got to the first socket of each round.
+    round_starts: Vec<Instant>,
+}
+
+impl<'r> Rounds<'r> {
+    /// The times are only taken when there is a `host_timeout`.
+    pub fn new(
+        live: &'r TargetSet,
+        targets: &[(Protocol, Vec<u16>)],
+        permutation: Option<u64>,
+        host_timeout: Option<Duration>,
+    ) -> Self {
+        let blocks = targets
+            .iter()
+            .filter(|(_, ports)| !ports.is_empty())
+            .map(|(protocol, ports)| {
+                let size = live.len().saturating_mul(ports.len() as u128);
+                let order =
+                    permutation.map(|seed| Permutation::new(size, &mut order_rng(seed, *protocol)));
+                (ports.len() as u128, order)
+            })
+            .collect();
+        Self {
+            live,
+            blocks,
+            host_timeout,
+            starts: Vec::new(),
+            resolution: host_timeout
+                .map_or(Duration::ZERO, |limit| limit / 16)
+                .max(Duration::from_millis(1)),
+            round_starts: Vec::new(),
+        }
+    }
+
+    /// Takes note of the scan getting to the socket at `index` of the
+    /// order, which it does one socket after the other.
+    pub fn reach(&mut self, index: usize) {
+        self.reach_at(index, Instant::now());
+    }
+
+    fn reach_at(&mut self, index: usize, now: Instant) {
+        if self.host_timeout.is_none() {
+            return;
+        }
+        let index = index as u128;
+        let hosts = self.live.len();
+        if index.is_multiple_of(hosts) {
+            self.round_starts.push(now);
+        }
+        if index >= hosts
+            || self
+                .starts
+                .last()
+                .is_some_and(|&(_, at)| now.saturating_duration_since(at) < self.resolution)
+        {
+            return;
+        }
+        if self.starts.len() == MAX_START_SAMPLES {
+            let mut keep = false;
+            self.starts.retain(|_| {
+                keep = !keep;
+                keep
+            });
+            self.resolution *= 2;
+        }
+        self.starts.push((index, now));
+    }
+
+    /// Whether the socket at `index` is in the first round, the one every
+    /// host starts with.
+    pub fn is_first_round(&self, index: usize) -> bool {
+        (index as u128) < self.live.len()
+    }
+
+    /// How many hosts the scan got to once it took the first `taken`
+    /// sockets of the order.
+    pub fn reached(&self, taken: usize) -> u128 {
+        (taken as u128).min(self.live.len())
+    }
+
+    /// Whether the host timeout gave up on `ip` by the round of the socket
+    /// at `index`, the host having been started at least a host timeout
+    /// before the round was.
+    pub fn timed_out(&self, index: usize, ip: IpAddr) -> bool {
+        self.past_timeout(index as u128 / self.live.len(), ip)
+    }
+
+    /// Whether the socket at `index` is the first one of `ip` the host
+    /// timeout gave up on.
+    pub fn times_out_at(&self, index: usize, ip: IpAddr) -> bool {
+        let round = index as u128 / self.live.len();
+        self.past_timeout(round, ip) && !self.past_timeout(round - 1, ip)
+    }
+
+    /// Whether the host timeout gave up on `ip` once the scan took the
+    /// first `taken` sockets of the order.
+    pub fn timed_out_by(&self, ip: IpAddr, taken: usize) -> bool {
+        let taken = taken as u128;
+        let Some(mut round) = taken.checked_sub(1).map(|last| last / self.live.len()) else {
+            return false;
+        };
+        // The round of the last socket of the host taken.
+        if self
+            .position(round, ip)
+            .is_none_or(|position| position >= taken)
+        {
+            let Some(previous) = round.checked_sub(1) else {
+                return false;
+            };
+            round = previous;
+        }
+        self.past_timeout(round, ip)
+    }
+
+    /// Whether some sockets of `ip` were never taken once the scan took
+    /// the first `taken` sockets of the order.
+    pub fn left_behind(&self, ip: IpAddr, taken: usize) -> bool {
+        let rounds: u128 = self.blocks.iter().map(|(rounds, _)| rounds).sum();
+        rounds
+            .checked_sub(1)
+            .and_then(|last| self.position(last, ip))
+            .is_some_and(|last| last >= taken as u128)
+    }
+
+    fn past_timeout(&self, round: u128, ip: IpAddr) -> bool {
+        let (Some(limit), Some(started)) = (self.host_timeout, self.started(ip)) else {
+            return false;
+        };
+        round > 0
+            && usize::try_from(round)
+                .ok()
+                .and_then(|round| self.round_starts.get(round))
+                .is_some_and(|at| at.saturating_duration_since(started) >= limit)
+    }
+
+    /// When the scan got to the first socket of `ip`, give or take the
+    /// resolution of the samples.
+    fn started(&self, ip: IpAddr) -> Option<Instant> {
+        let first = self.position(0, ip)?;
+        let after = self.starts.partition_point(|&(index, _)| index <= first);
+        Some(self.starts.get(after.checked_sub(1)?)?.1)
+    }
+
+    /// The index in the order of the socket of `ip` in `round`.
+    fn position(&self, round: u128, ip: IpAddr) -> Option<u128> {
+        let host = self.live.index_of(ip)?;
+        let hosts = self.live.len();
+        let mut first_round = 0;
+        for (rounds, order) in &self.blocks {
+            if round < first_round + rounds {
+                let place = order
+                    .as_ref()
+                    .map_or(host, |order| order.position_of(host, hosts));
+                return round.checked_mul(hosts)?.checked_add(place);
+            }
+            first_round += rounds;
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{order_rng, Rounds, MAX_START_SAMPLES};
+    use crate::scanner::socket::SocketIterator;
+    use crate::scanner::Protocol;
+    use crate::targets::{IpRange, TargetSet};
+    use std::net::IpAddr;
+    use std::time::{Duration, Instant};
+
+    fn targets() -> Vec<(Protocol, Vec<u16>)> {
+        vec![
+            (Protocol::Tcp, vec![22, 80, 443]),
+            (Protocol::Udp, vec![53]),
+        ]
+    }
+
+    #[test]
+    fn finds_the_sockets_of_every_host_in_both_orders() {
+        let live: TargetSet = ["10.0.0.1", "10.0.0.2", "10.0.0.3", "::1", "::2"]
+            .iter()
+            .map(|ip| ip.parse::<IpAddr>().unwrap())
+            .collect();
+        let targets = targets();
+        for permutation in [None, Some(42)] {
+            let rounds = Rounds::new(&live, &targets, permutation, None);
+            let sockets: Vec<IpAddr> = targets
+                .iter()
+                .flat_map(|(protocol, ports)| match permutation {
+                    Some(seed) => {
+                        SocketIterator::permuted(&live, ports, &mut order_rng(seed, *protocol))
+                    }
+                    None => SocketIterator::new(&live, ports),
+                })
+                .map(|socket| socket.ip())
+                .collect();
+            for (index, ip) in sockets.iter().enumerate() {
+                let round = index as u128 / live.len();
+                assert_eq!(rounds.position(round, *ip), Some(index as u128));
+            }
+            for ip in &live {
+                let last = sockets.iter().rposition(|socket| socket == &ip).unwrap();
+                assert!(rounds.left_behind(ip, last));
+                assert!(!rounds.left_behind(ip, last + 1));
+            }
+            assert_eq!(rounds.reached(3), 3);
+            assert_eq!(rounds.reached(sockets.len()), 5);
+        }
+    }
+
+    #[test]
+    fn gives_up_on_hosts_a_host_timeout_after_they_started() {
+        let live: TargetSet = (1..=4)
+            .map(|host| format!("10.0.0.{host}").parse::<IpAddr>().unwrap())
+            .collect();
+        let targets = targets();
+        let limit = Duration::from_secs(1);
+        let mut rounds = Rounds::new(&live, &targets, None, Some(limit));
+        let ip = |host: u8| IpAddr::from([10, 0, 0, host]);
+        let start = Instant::now();
+        // A socket every 100ms, the scan gets to 10.0.0.1 at 0ms and to
+        // 10.0.0.4 at 300ms, and starts its rounds 400ms apart.
+        for index in 0..16 {
+            rounds.reach_at(index, start + Duration::from_millis(100 * index as u64));
+        }
+
+        assert!(rounds.is_first_round(3));
+        assert!(!rounds.is_first_round(4));
+        assert!(!rounds.timed_out(4, ip(1)));
+        assert!(!rounds.timed_out(8, ip(1)));
+        assert!(rounds.timed_out(12, ip(1)));
+        assert!(rounds.times_out_at(12, ip(1)));
+        assert!(!rounds.timed_out(15, ip(4)));
+        assert!(rounds.timed_out_by(ip(1), 16));
+        assert!(!rounds.timed_out_by(ip(1), 12));
+        assert!(!rounds.timed_out_by(ip(4), 16));
+    }
+
+    #[test]
+    fn keeps_a_bounded_number_of_times_for_huge_ranges() {
+        let live: TargetSet = [IpRange::new(
+            "2001:db8::".parse().unwrap(),
+            "2001:db8::ffff:ffff:ffff:ffff".parse().unwrap(),
+        )
+        .unwrap()]
+        .into_iter()
+        .collect();
+        let targets = targets();
+        let limit = Duration::from_millis(16);
+        let mut rounds = Rounds::new(&live, &targets, Some(7), Some(limit));
+        let start = Instant::now();
+        for index in 0..1_000_000 {
+            rounds.reach_at(index, start + Duration::from_micros(index as u64 * 10));
+        }
+
+        assert!(rounds.starts.len() <= MAX_START_SAMPLES);
+        assert_eq!(rounds.round_starts.len(), 1);
+        assert_eq!(rounds.reached(1_000_000), 1_000_000);
+        let first = live.get(0).unwrap();
+        assert!(rounds.left_behind(first, 1_000_000));
+        assert!(!rounds.timed_out_by(first, 1_000_000));
+    }
+}
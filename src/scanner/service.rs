@@ -0,0 +1,214 @@
+use super::banner;
+use super::probes::{Identification, ServiceProbes};
+use super::result::{Protocol, Service};
+use super::Source;
+use async_std::io::{self, WriteExt};
+use log::debug;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+/// Most bytes of an answer that are run through the matches.
+const MAX_RESPONSE_BYTES: usize = 16 * 1024;
+
+/// Finds out which service listens on an open port the way nmap's version
+/// detection does.
+///
+/// The probes of the database that fit the port are sent one after the
+/// other, each over a new connection, until the answer to one of them hits
+/// a `match` line. An answer that only hits a `softmatch` line names the
+/// service, from then on only the probes that could tell its version are
+/// sent, and the service is reported without one if none of them does.
+#[derive(Debug)]
+pub struct ServiceDetector {
+    probes: ServiceProbes,
+    intensity: u8,
+    max_wait: Duration,
+    source: Source,
+}
+
+impl ServiceDetector {
+    /// `intensity` goes from 0 to 9 like nmap's `--version-intensity`, the
+    /// higher it is the rarer the probes that are tried. No probe waits
+    /// longer than `max_wait` for an answer.
+    pub fn new(probes: ServiceProbes, intensity: u8, max_wait: Duration) -> Self {
+        Self {
+            probes,
+            intensity,
+            max_wait,
+            source: Source::default(),
+        }
+    }
+
+    /// Sends the probes from `source`.
+    #[must_use]
+    pub fn with_source(mut self, source: Source) -> Self {
+        self.source = source;
+        self
+    }
+
+    /// Identifies the service behind `socket`, giving up on connections
+    /// that take longer than `connect_timeout`. Returns `None` when no
+    /// probe got an answer the database knows about.
+    pub async fn detect(
+        &self,
+        socket: SocketAddr,
+        protocol: Protocol,
+        connect_timeout: Duration,
+    ) -> Option<Service> {
+        let mut soft: Option<Service> = None;
+
+        for probe in self
+            .probes
+            .probes_for(socket.port(), protocol, self.intensity)
+        {
+            let service = soft.as_ref().map(|service| service.name.as_str());
+            if service.is_some_and(|service| !self.probes.can_identify(probe, service)) {
+                continue;
+            }
+
+            let wait = probe.total_wait.min(self.max_wait);
+            let response = match protocol {
+                Protocol::Tcp => {
+                    exchange_tcp(&self.source, socket, &probe.payload, connect_timeout, wait).await
+                }
+                Protocol::Udp => exchange_udp(&self.source, socket, &probe.payload, wait).await,
+            };
+            let response = match response {
+                Ok(response) => response,
+                // The port closed since it was scanned, no use going on.
+                Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => break,
+                Err(e) => {
+                    debug!("Probe {} to {} failed: {}", probe.name, socket, e);
+                    continue;
+                }
+            };
+            if response.is_empty() {
+                continue;
+            }
+
+            match self.probes.identify(probe, &response, service) {
+                Some(Identification::Hard(service)) => return Some(service),
+                Some(Identification::Soft(service)) if soft.is_none() => soft = Some(service),
+                _ => {}
+            }
+        }
+
+        soft
+    }
+}
+
+/// Sends `payload` over a new connection to `socket` and reads the answer.
+/// The NULL probe has no payload and only listens.
+async fn exchange_tcp(
+    source: &Source,
+    socket: SocketAddr,
+    payload: &[u8],
+    connect_timeout: Duration,
+    wait: Duration,
+) -> io::Result<Vec<u8>> {
+    let mut stream = source.connect(socket, connect_timeout).await?;
+    if !payload.is_empty() {
+        stream.write_all(payload).await?;
+    }
+    Ok(banner::read_response(&mut stream, wait, MAX_RESPONSE_BYTES).await)
+}
+
+/// Sends `payload` to `socket` and waits for a datagram back, silence is
+/// an empty answer.
+async fn exchange_udp(
+    source: &Source,
+    socket: SocketAddr,
+    payload: &[u8],
+    wait: Duration,
+) -> io::Result<Vec<u8>> {
+    let udp_socket = source.bind_udp(socket).await?;
+    udp_socket.connect(socket).await?;
+    udp_socket.send(payload).await?;
+
+    let mut buf = vec![0u8; MAX_RESPONSE_BYTES];
+    match io::timeout(wait, udp_socket.recv(&mut buf)).await {
+        Ok(size) => {
+            buf.truncate(size);
+            Ok(buf)
+        }
+        Err(e) if e.kind() == io::ErrorKind::TimedOut => Ok(Vec::new()),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ServiceDetector;
+    use crate::scanner::{Protocol, ServiceProbes};
+    use async_std::task::block_on;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::time::Duration;
+
+    const PROBES: &str = r#"
+Probe TCP NULL q||
+softmatch http m|^HTTP/1\.[01] |
+
+Probe TCP Hello q|HELLO\r\n|
+rarity 1
+match http m|^HTTP/1\.0 200 .*\r\nServer: azula-test/([\d.]+)\r\n|s p/Azula test server/ v/$1/
+"#;
+
+    /// Accepts `connections` connections and answers `HELLO` with an HTTP
+    /// response, staying quiet otherwise.
+    fn serve(listener: TcpListener, connections: usize) {
+        std::thread::spawn(move || {
+            for stream in listener.incoming().take(connections) {
+                let mut stream = stream.unwrap();
+                stream
+                    .set_read_timeout(Some(Duration::from_millis(300)))
+                    .unwrap();
+                let mut request = [0u8; 64];
+                if let Ok(size) = stream.read(&mut request) {
+                    if request[..size].starts_with(b"HELLO") {
+                        let _ = stream.write_all(
+                            b"HTTP/1.0 200 OK\r\nServer: azula-test/1.2\r\nContent-Length: 0\r\n\r\n",
+                        );
+                    }
+                }
+            }
+        });
+    }
+
+    #[test]
+    fn probes_until_a_match_is_found() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        serve(listener, 2);
+        let detector = ServiceDetector::new(
+            ServiceProbes::parse(PROBES).unwrap(),
+            7,
+            Duration::from_millis(500),
+        );
+
+        let service = block_on(detector.detect(address, Protocol::Tcp, Duration::from_secs(1)))
+            .expect("the test server is recognised");
+
+        assert_eq!(service.name, "http");
+        assert_eq!(service.product.as_deref(), Some("Azula test server"));
+        assert_eq!(service.version.as_deref(), Some("1.2"));
+    }
+
+    #[test]
+    fn unknown_services_stay_unknown() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        serve(listener, 1);
+        // Intensity 0 leaves out the only probe the test server answers.
+        let detector = ServiceDetector::new(
+            ServiceProbes::parse(PROBES).unwrap(),
+            0,
+            Duration::from_millis(200),
+        );
+
+        assert_eq!(
+            block_on(detector.detect(address, Protocol::Tcp, Duration::from_secs(1))),
+            None
+        );
+    }
+}
@@ -1,30 +1,60 @@
+use crate::port::Permutation;
+use crate::targets::{self, TargetSet};
 use itertools::{iproduct, Product};
-use std::net::{IpAddr, SocketAddr};
+use rand::Rng;
+use std::net::SocketAddr;
 
 pub struct SocketIterator<'s> {
+    order: Order<'s>,
+}
+
+enum Order<'s> {
     // product_it is a cartesian product iterator over
-    // the slices of ports and IP addresses.
+    // the slice of ports and the set of IP addresses.
     //
     // The IP/port order is intentionally reversed here since we want
     // the itertools::iproduct! macro below to generate the pairs with
     // all the IPs for one port before moving on to the next one
     // ("hold the port, go through all the IPs, then advance the port...").
     // See also the comments in the iterator implementation for an example.
-    product_it:
-        Product<Box<std::slice::Iter<'s, u16>>, Box<std::slice::Iter<'s, std::net::IpAddr>>>,
+    Product(Product<Box<std::slice::Iter<'s, u16>>, Box<targets::Iter<'s>>>),
+    // Every IP and port pair has an index, the IP changing fastest, and
+    // the indices are gone through in a random order.
+    Permuted {
+        ips: &'s TargetSet,
+        ports: &'s [u16],
+        indices: Permutation,
+    },
 }
 
-/// An iterator that receives a slice of IPs and ports and returns a Socket
+/// An iterator that receives a set of IPs and a slice of ports and returns a Socket
 /// for each IP and port pair until all of these combinations are exhausted.
 /// The goal of this iterator is to go over every IP and port combination
 /// without generating a big memory footprint. The alternative would be
-/// generating a vector containing all these combinations.
+/// generating a vector containing all these combinations, or even every
+/// IP of the set.
 impl<'s> SocketIterator<'s> {
-    pub fn new(ips: &'s [IpAddr], ports: &'s [u16]) -> Self {
+    pub fn new(ips: &'s TargetSet, ports: &'s [u16]) -> Self {
         let ports_it = Box::new(ports.iter());
         let ips_it = Box::new(ips.iter());
         Self {
-            product_it: iproduct!(ports_it, ips_it),
+            order: Order::Product(iproduct!(ports_it, ips_it)),
+        }
+    }
+
+    /// Goes through every IP and port combination too, but in the order of
+    /// a random permutation of all of them drawn from `rng`. Like zmap and
+    /// masscan do, this spreads the probes over hosts and subnets instead
+    /// of hitting every IP on the same port in a row, still without storing
+    /// the combinations.
+    pub fn permuted<R: Rng>(ips: &'s TargetSet, ports: &'s [u16], rng: &mut R) -> Self {
+        let size = ips.len().saturating_mul(ports.len() as u128);
+        Self {
+            order: Order::Permuted {
+                ips,
+                ports,
+                indices: Permutation::new(size, rng),
+            },
         }
     }
 }
@@ -44,25 +74,42 @@ impl Iterator for SocketIterator<'_> {
     /// it.next(); // 192.168.0.1:443
     /// it.next(); // None
     fn next(&mut self) -> Option<Self::Item> {
-        self.product_it
-            .next()
-            .map(|(port, ip)| SocketAddr::new(*ip, *port))
+        match &mut self.order {
+            Order::Product(product_it) => product_it
+                .next()
+                .map(|(port, ip)| SocketAddr::new(ip, *port)),
+            Order::Permuted {
+                ips,
+                ports,
+                indices,
+            } => indices.next().map(|index| {
+                let ip_count = ips.len();
+                #[allow(clippy::cast_possible_truncation)]
+                SocketAddr::new(
+                    ips.get(index % ip_count)
+                        .expect("Permutation indices stay within the targets"),
+                    ports[(index / ip_count) as usize],
+                )
+            }),
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::SocketIterator;
+    use crate::targets::{IpRange, TargetSet};
     use std::net::{IpAddr, SocketAddr};
 
     #[test]
     fn goes_through_every_ip_port_combination() {
-        let addrs = vec![
+        let addrs = [
             "127.0.0.1".parse::<IpAddr>().unwrap(),
             "192.168.0.1".parse::<IpAddr>().unwrap(),
         ];
         let ports: Vec<u16> = vec![22, 80, 443];
-        let mut it = SocketIterator::new(&addrs, &ports);
+        let targets: TargetSet = addrs.iter().copied().collect();
+        let mut it = SocketIterator::new(&targets, &ports);
 
         assert_eq!(Some(SocketAddr::new(addrs[0], ports[0])), it.next());
         assert_eq!(Some(SocketAddr::new(addrs[1], ports[0])), it.next());
@@ -72,4 +119,47 @@ mod tests {
         assert_eq!(Some(SocketAddr::new(addrs[1], ports[2])), it.next());
         assert_eq!(None, it.next());
     }
+
+    #[test]
+    fn permutes_every_ip_port_combination() {
+        let addrs: Vec<IpAddr> = (1..=50).map(|n| IpAddr::from([10, 0, 0, n])).collect();
+        let ports: Vec<u16> = (1..=20).collect();
+        let targets: TargetSet = addrs.iter().copied().collect();
+
+        let sockets: Vec<SocketAddr> =
+            SocketIterator::permuted(&targets, &ports, &mut rand::thread_rng()).collect();
+        let mut sorted = sockets.clone();
+        sorted.sort_unstable();
+        let mut expected: Vec<SocketAddr> = SocketIterator::new(&targets, &ports).collect();
+        expected.sort_unstable();
+        assert_eq!(expected, sorted);
+
+        // Going through a host's ports is spread over the whole scan rather
+        // than done in a burst.
+        let first_host: Vec<usize> = sockets
+            .iter()
+            .enumerate()
+            .filter(|(_, socket)| socket.ip() == addrs[0])
+            .map(|(n, _)| n)
+            .collect();
+        assert!(first_host.windows(2).all(|pair| pair[1] - pair[0] > 1));
+    }
+
+    #[test]
+    fn goes_through_huge_ranges_without_expanding_them() {
+        let start: IpAddr = "2001:db8::".parse().unwrap();
+        let end: IpAddr = "2001:db8::ffff:ffff:ffff:ffff".parse().unwrap();
+        let targets: TargetSet = vec![IpRange::new(start, end).unwrap()].into();
+        let ports: Vec<u16> = vec![80, 443];
+
+        let mut it = SocketIterator::new(&targets, &ports);
+        assert_eq!(Some(SocketAddr::new(start, 80)), it.next());
+        let sockets: Vec<SocketAddr> =
+            SocketIterator::permuted(&targets, &ports, &mut rand::thread_rng())
+                .take(1_000)
+                .collect();
+        assert!(sockets
+            .iter()
+            .all(|socket| targets.contains(socket.ip()) && ports.contains(&socket.port())));
+    }
 }
\ No newline at end of file
@@ -0,0 +1,239 @@
+use super::congestion::ProbeOutcome;
+use super::proxy::{self, Proxy};
+use async_io::Async;
+use async_std::io;
+use async_std::net::{TcpStream, UdpSocket};
+use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+/// Where probes leave from: the local address, the network interface and
+/// the port they are sent from. Whatever is left unset is picked by the
+/// kernel, like it does for any other connection.
+///
+/// TCP connections can also be relayed through a chain of proxies, the
+/// rest of the source then applies to the connection to the first one.
+///
+/// Binding to an interface uses `SO_BINDTODEVICE`, which only exists on
+/// Linux and needs `CAP_NET_RAW`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Source {
+    ip: Option<IpAddr>,
+    interface: Option<String>,
+    port: Option<u16>,
+    proxies: Vec<Proxy>,
+}
+
+impl Source {
+    pub fn new(ip: Option<IpAddr>, interface: Option<String>, port: Option<u16>) -> Self {
+        Self {
+            ip,
+            interface,
+            port,
+            proxies: Vec::new(),
+        }
+    }
+
+    /// Relays TCP connections through `proxies`, in order.
+    #[must_use]
+    pub fn with_proxies(mut self, proxies: Vec<Proxy>) -> Self {
+        self.proxies = proxies;
+        self
+    }
+
+    pub fn ip(&self) -> Option<IpAddr> {
+        self.ip
+    }
+
+    pub fn port(&self) -> Option<u16> {
+        self.port
+    }
+
+    pub fn proxies(&self) -> &[Proxy] {
+        &self.proxies
+    }
+
+    /// The same source with the port left to the kernel. Connections made
+    /// after the scan, to ports that were just connected to, need this:
+    /// the earlier connection from the fixed port would still be in
+    /// `TIME_WAIT`.
+    #[must_use]
+    pub fn without_port(&self) -> Self {
+        Self {
+            port: None,
+            ..self.clone()
+        }
+    }
+
+    /// Connects to `target` from this source, giving up after `timeout`.
+    /// Going through proxies, the connection to the first one and the
+    /// tunnel to `target` get `timeout` each.
+    pub async fn connect(&self, target: SocketAddr, timeout: Duration) -> io::Result<TcpStream> {
+        let Some(first) = self.proxies.first() else {
+            return self.connect_directly(target, timeout).await;
+        };
+        // Running out of local resources is the scan's doing, not the
+        // proxy's, and is kept as is for the scanner to slow down.
+        let mut stream = self
+            .connect_directly(first.address(), timeout)
+            .await
+            .map_err(|e| {
+                if ProbeOutcome::is_resource_exhaustion(&e) {
+                    e
+                } else {
+                    proxy::chain_error(first, &e)
+                }
+            })?;
+        io::timeout(timeout, proxy::tunnel(&mut stream, &self.proxies, target)).await?;
+        Ok(stream)
+    }
+
+    async fn connect_directly(
+        &self,
+        target: SocketAddr,
+        timeout: Duration,
+    ) -> io::Result<TcpStream> {
+        if self.ip.is_none() && self.interface.is_none() && self.port.is_none() {
+            return io::timeout(timeout, TcpStream::connect(target)).await;
+        }
+
+        let socket = self.socket(target, Type::STREAM, Protocol::TCP)?;
+        socket.set_nonblocking(true)?;
+        // Non-blocking connects are only started here, the socket turns
+        // writable once the handshake is over.
+        match socket.connect(&SockAddr::from(target)) {
+            Err(e)
+                if e.raw_os_error() != Some(libc::EINPROGRESS)
+                    && e.kind() != io::ErrorKind::WouldBlock =>
+            {
+                return Err(e)
+            }
+            _ => {}
+        }
+
+        let stream = Async::new(std::net::TcpStream::from(socket))?;
+        io::timeout(timeout, stream.writable()).await?;
+        if let Some(e) = stream.get_ref().take_error()? {
+            return Err(e);
+        }
+        Ok(TcpStream::from(stream.into_inner()?))
+    }
+
+    /// Binds a UDP socket that can reach `target` from this source.
+    pub async fn bind_udp(&self, target: SocketAddr) -> io::Result<UdpSocket> {
+        if self.ip.is_none() && self.interface.is_none() && self.port.is_none() {
+            return UdpSocket::bind(SocketAddr::new(unspecified(target), 0)).await;
+        }
+
+        let socket = self.socket(target, Type::DGRAM, Protocol::UDP)?;
+        socket.set_nonblocking(true)?;
+        Ok(UdpSocket::from(std::net::UdpSocket::from(socket)))
+    }
+
+    /// Opens a socket of the family of `target`, bound to this source.
+    pub fn socket(&self, target: SocketAddr, ty: Type, protocol: Protocol) -> io::Result<Socket> {
+        let socket = Socket::new(Domain::for_address(target), ty, Some(protocol))?;
+        self.bind_interface(&socket)?;
+        if self.ip.is_some() || self.port.is_some() {
+            if self.port.is_some() {
+                // Probes to other ports, or a retry of a closed one, reuse
+                // the port while the previous socket is still around.
+                socket.set_reuse_address(true)?;
+            }
+            let local = SocketAddr::new(self.local_ip(target)?, self.port.unwrap_or(0));
+            socket.bind(&SockAddr::from(local))?;
+        }
+        Ok(socket)
+    }
+
+    /// Binds `socket` to the interface of this source, if there is one.
+    pub fn bind_interface(&self, socket: &Socket) -> io::Result<()> {
+        let Some(interface) = &self.interface else {
+            return Ok(());
+        };
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        {
+            socket.bind_device(Some(interface.as_bytes()))
+        }
+        #[cfg(not(any(target_os = "linux", target_os = "android")))]
+        {
+            let _ = socket;
+            Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("binding to interface {interface} is only supported on Linux"),
+            ))
+        }
+    }
+
+    /// The local address to send to `target` from, failing when the source
+    /// address is of the other family.
+    fn local_ip(&self, target: SocketAddr) -> io::Result<IpAddr> {
+        match self.ip {
+            Some(ip) if ip.is_ipv4() != target.is_ipv4() => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("source address {ip} can't reach {}", target.ip()),
+            )),
+            Some(ip) => Ok(ip),
+            None => Ok(unspecified(target)),
+        }
+    }
+}
+
+fn unspecified(target: SocketAddr) -> IpAddr {
+    match target {
+        SocketAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        SocketAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Source;
+    use async_std::task::block_on;
+    use std::net::{IpAddr, TcpListener};
+    use std::time::Duration;
+
+    #[test]
+    fn connects_from_the_source_port() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let target = listener.local_addr().unwrap();
+        let port = {
+            let free = TcpListener::bind("127.0.0.1:0").unwrap();
+            free.local_addr().unwrap().port()
+        };
+        let source = Source::new(Some("127.0.0.1".parse().unwrap()), None, Some(port));
+
+        let stream = block_on(source.connect(target, Duration::from_secs(1))).unwrap();
+        let (_, peer) = listener.accept().unwrap();
+
+        assert_eq!(stream.local_addr().unwrap().port(), port);
+        assert_eq!(peer.port(), port);
+    }
+
+    #[test]
+    fn refuses_targets_of_the_other_family() {
+        let source = Source::new(Some("127.0.0.1".parse().unwrap()), None, None);
+        let target = (IpAddr::from([0u16, 0, 0, 0, 0, 0, 0, 1]), 80).into();
+
+        let error = block_on(source.connect(target, Duration::from_secs(1))).unwrap_err();
+
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn closed_ports_are_refused() {
+        let port = {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            listener.local_addr().unwrap().port()
+        };
+        let source = Source::new(Some("127.0.0.1".parse().unwrap()), None, None);
+
+        let error = block_on(source.connect(
+            ("127.0.0.1".parse::<IpAddr>().unwrap(), port).into(),
+            Duration::from_secs(1),
+        ))
+        .unwrap_err();
+
+        assert_eq!(error.kind(), std::io::ErrorKind::ConnectionRefused);
+    }
+}
@@ -0,0 +1,431 @@
+use super::checksum::checksum;
+use super::{PortState, Source};
+use async_std::io;
+use futures::channel::oneshot;
+use log::debug;
+use rand::Rng;
+use socket2::{Domain, Protocol, Socket, Type};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::Duration;
+
+const TCP_PROTOCOL: u8 = 6;
+const SYN: u8 = 0x02;
+const RST: u8 = 0x04;
+const ACK: u8 = 0x10;
+/// How often the receiving threads check whether they should stop.
+const RECEIVE_POLL: Duration = Duration::from_millis(100);
+
+/// Probes waiting for an answer, by the address they were sent to, along
+/// with the sequence number of their SYN.
+type Pending = Mutex<HashMap<SocketAddr, (u32, oneshot::Sender<PortState>)>>;
+
+/// A segment for the sending thread, the address to send it to and where
+/// to hand back how that went.
+type Outgoing = (Vec<u8>, SocketAddr, oneshot::Sender<io::Result<()>>);
+
+/// Half-open TCP scanning over raw sockets.
+///
+/// A SYN is sent to the port and the answer decides its state: SYN/ACK means
+/// open and RST means closed. We never complete the handshake, the kernel
+/// answers the SYN/ACK with a RST on its own since no socket of ours is
+/// bound to the source port. Raw sockets need root or `CAP_NET_RAW`.
+///
+/// One thread per address family reads every TCP segment coming in and
+/// hands the answers to the probes waiting for them, only taking the ones
+/// acknowledging the SYN they were sent. Another thread sends the SYNs,
+/// raw sockets block while doing so.
+#[derive(Debug)]
+pub struct SynScanner {
+    source: Source,
+    source_port: u16,
+    ipv6: bool,
+    outgoing: mpsc::Sender<Outgoing>,
+    pending: Arc<Pending>,
+    sources: Mutex<HashMap<IpAddr, IpAddr>>,
+    stop: Arc<AtomicBool>,
+}
+
+impl SynScanner {
+    /// Opens the raw sockets, fails when we are not allowed to. Probes are
+    /// sent from `source`, from a random port when it has none.
+    pub fn new(source: Source) -> io::Result<Self> {
+        let source_port = source
+            .port()
+            .unwrap_or_else(|| rand::thread_rng().gen_range(40_000..60_000));
+        let pending: Arc<Pending> = Arc::new(Mutex::new(HashMap::new()));
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let v4 = raw_socket(Domain::IPV4, &source)?;
+        // IPv6 may well be disabled, IPv4 targets can still be scanned then.
+        let v6 = raw_socket(Domain::IPV6, &source)
+            .map_err(|e| debug!("No raw IPv6 socket, IPv6 SYN scans are off: {}", e))
+            .ok();
+
+        for socket in [Some(&v4), v6.as_ref()].into_iter().flatten() {
+            let socket = socket.try_clone()?;
+            let pending = Arc::clone(&pending);
+            let stop = Arc::clone(&stop);
+            thread::spawn(move || receive(&socket, source_port, &pending, &stop));
+        }
+        let ipv6 = v6.is_some();
+        let (outgoing, segments) = mpsc::channel();
+        thread::spawn(move || send(&v4, v6.as_ref(), &segments));
+
+        Ok(Self {
+            source,
+            source_port,
+            ipv6,
+            outgoing,
+            pending,
+            sources: Mutex::new(HashMap::new()),
+            stop,
+        })
+    }
+
+    /// Sends a SYN to `target` and waits up to `timeout` for the answer.
+    /// Returns `None` when nothing came back in time.
+    pub async fn probe(
+        &self,
+        target: SocketAddr,
+        timeout: Duration,
+    ) -> io::Result<Option<PortState>> {
+        if target.is_ipv6() && !self.ipv6 {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "no raw socket for IPv6",
+            ));
+        }
+        let source = self.source_for(target.ip())?;
+
+        let sequence: u32 = rand::thread_rng().gen();
+        let (sender, receiver) = oneshot::channel();
+        self.pending().insert(target, (sequence, sender));
+
+        let segment = syn_segment(SocketAddr::new(source, self.source_port), target, sequence);
+        let answer = match self.send(segment, SocketAddr::new(target.ip(), 0)).await {
+            Ok(()) => {
+                io::timeout(timeout, async {
+                    receiver
+                        .await
+                        .map_err(|_| io::Error::other("SYN receiver stopped"))
+                })
+                .await
+            }
+            Err(e) => Err(e),
+        };
+        self.pending().remove(&target);
+
+        match answer {
+            Ok(state) => Ok(Some(state)),
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn pending(&self) -> MutexGuard<'_, HashMap<SocketAddr, (u32, oneshot::Sender<PortState>)>> {
+        self.pending.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Hands `segment` to the sending thread and waits for it to be sent.
+    async fn send(&self, segment: Vec<u8>, target: SocketAddr) -> io::Result<()> {
+        let (sent, result) = oneshot::channel();
+        self.outgoing
+            .send((segment, target, sent))
+            .map_err(|_| io::Error::other("SYN sender stopped"))?;
+        result
+            .await
+            .map_err(|_| io::Error::other("SYN sender stopped"))?
+    }
+
+    /// The local address the kernel would use to reach `target` from our
+    /// source, needed for the TCP checksum.
+    fn source_for(&self, target: IpAddr) -> io::Result<IpAddr> {
+        let mut sources = self.sources.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(source) = sources.get(&target) {
+            return Ok(*source);
+        }
+
+        // Connecting a UDP socket sends nothing, it only picks a route.
+        let target = SocketAddr::new(target, 9);
+        let probe = self
+            .source
+            .without_port()
+            .socket(target, Type::DGRAM, Protocol::UDP)?;
+        probe.connect(&target.into())?;
+        let source = probe
+            .local_addr()?
+            .as_socket()
+            .ok_or_else(|| io::Error::other("no local address"))?
+            .ip();
+        sources.insert(target.ip(), source);
+        Ok(source)
+    }
+}
+
+impl Drop for SynScanner {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Opens a raw TCP socket, bound to the interface of `source`. It is
+/// wrapped in a `UdpSocket` only to get safe `send_to` and `recv_from`,
+/// which work the same on any datagram socket.
+fn raw_socket(domain: Domain, source: &Source) -> io::Result<UdpSocket> {
+    let socket = Socket::new(domain, Type::RAW, Some(Protocol::TCP))?;
+    source.bind_interface(&socket)?;
+    socket.set_read_timeout(Some(RECEIVE_POLL))?;
+    Ok(UdpSocket::from(socket))
+}
+
+/// Sends the segments handed over on `segments` until the scanner is
+/// dropped, over `v4` or `v6` depending on where they go.
+fn send(v4: &UdpSocket, v6: Option<&UdpSocket>, segments: &mpsc::Receiver<Outgoing>) {
+    for (segment, target, sent) in segments {
+        let result = match target {
+            SocketAddr::V4(_) => Some(v4),
+            SocketAddr::V6(_) => v6,
+        }
+        .ok_or_else(|| io::Error::new(io::ErrorKind::Unsupported, "no raw socket for IPv6"))
+        .and_then(|socket| socket.send_to(&segment, target))
+        .map(|_| ());
+        let _ = sent.send(result);
+    }
+}
+
+/// Reads TCP segments off `socket` until told to stop, and answers the
+/// probes waiting for a reply from the segment's source.
+fn receive(socket: &UdpSocket, source_port: u16, pending: &Pending, stop: &AtomicBool) {
+    let mut buf = [0u8; 1500];
+    while !stop.load(Ordering::Relaxed) {
+        let (size, from) = match socket.recv_from(&mut buf) {
+            Ok(received) => received,
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                continue
+            }
+            Err(e) => {
+                debug!("Raw socket receive failed: {}", e);
+                continue;
+            }
+        };
+
+        let Some((from, segment)) = tcp_segment(&buf[..size], from.ip()) else {
+            continue;
+        };
+        let Some(port) = answer_port(segment, source_port) else {
+            continue;
+        };
+        let target = SocketAddr::new(from, port);
+        let mut pending = pending.lock().unwrap_or_else(PoisonError::into_inner);
+        let Some(state) = pending
+            .get(&target)
+            .and_then(|(sequence, _)| parse_answer(segment, *sequence))
+        else {
+            // Not an answer to the SYN we sent, a late one to an earlier
+            // probe or a stray segment.
+            continue;
+        };
+        if let Some((_, sender)) = pending.remove(&target) {
+            let _ = sender.send(state);
+        }
+    }
+}
+
+/// Finds the TCP segment in what a raw socket handed us. IPv4 raw sockets
+/// include the IP header, IPv6 ones don't.
+fn tcp_segment(packet: &[u8], from: IpAddr) -> Option<(IpAddr, &[u8])> {
+    match from {
+        IpAddr::V4(_) => {
+            let header_len = usize::from(packet.first()? & 0x0f) * 4;
+            if packet.first()? >> 4 != 4 || *packet.get(9)? != TCP_PROTOCOL {
+                return None;
+            }
+            let source: [u8; 4] = packet.get(12..16)?.try_into().ok()?;
+            Some((IpAddr::from(source), packet.get(header_len..)?))
+        }
+        IpAddr::V6(_) => Some((from, packet)),
+    }
+}
+
+/// Reads the port a segment came from, ignoring segments that are too
+/// short or not addressed to `source_port`.
+fn answer_port(segment: &[u8], source_port: u16) -> Option<u16> {
+    if segment.len() < 20 {
+        return None;
+    }
+    let destination = u16::from_be_bytes([segment[2], segment[3]]);
+    (destination == source_port).then(|| u16::from_be_bytes([segment[0], segment[1]]))
+}
+
+/// Reads the state a segment tells us about, when it acknowledges the SYN
+/// sent with `sequence`, as both a SYN/ACK and the RST answering a SYN do.
+fn parse_answer(segment: &[u8], sequence: u32) -> Option<PortState> {
+    let ack = u32::from_be_bytes(segment.get(8..12)?.try_into().ok()?);
+    let flags = *segment.get(13)?;
+    if flags & ACK == 0 || ack != sequence.wrapping_add(1) {
+        return None;
+    }
+
+    if flags & SYN != 0 {
+        Some(PortState::Open)
+    } else if flags & RST != 0 {
+        Some(PortState::Closed)
+    } else {
+        None
+    }
+}
+
+/// Builds a SYN segment with an MSS option, the way most stacks open a
+/// connection.
+fn syn_segment(source: SocketAddr, target: SocketAddr, sequence: u32) -> Vec<u8> {
+    let mut segment = Vec::with_capacity(24);
+    segment.extend_from_slice(&source.port().to_be_bytes());
+    segment.extend_from_slice(&target.port().to_be_bytes());
+    segment.extend_from_slice(&sequence.to_be_bytes());
+    segment.extend_from_slice(&0u32.to_be_bytes());
+    // 6 words of header, the 20 fixed bytes and the MSS option.
+    segment.push(6 << 4);
+    segment.push(SYN);
+    segment.extend_from_slice(&1024u16.to_be_bytes());
+    segment.extend_from_slice(&[0, 0, 0, 0]);
+    segment.extend_from_slice(&[2, 4, 0x05, 0xb4]);
+
+    let checksum = checksum(
+        &[
+            pseudo_header(source.ip(), target.ip(), segment.len()),
+            segment.clone(),
+        ]
+        .concat(),
+    );
+    segment[16..18].copy_from_slice(&checksum.to_be_bytes());
+    segment
+}
+
+/// The pseudo header the TCP checksum is computed over.
+fn pseudo_header(source: IpAddr, target: IpAddr, length: usize) -> Vec<u8> {
+    let mut header = Vec::with_capacity(40);
+    match (source, target) {
+        (IpAddr::V4(source), IpAddr::V4(target)) => {
+            header.extend_from_slice(&source.octets());
+            header.extend_from_slice(&target.octets());
+            header.push(0);
+            header.push(TCP_PROTOCOL);
+            header.extend_from_slice(&u16::try_from(length).unwrap_or(u16::MAX).to_be_bytes());
+        }
+        _ => {
+            let octets = |ip: IpAddr| match ip {
+                IpAddr::V4(ip) => ip.to_ipv6_mapped().octets(),
+                IpAddr::V6(ip) => ip.octets(),
+            };
+            header.extend_from_slice(&octets(source));
+            header.extend_from_slice(&octets(target));
+            header.extend_from_slice(&u32::try_from(length).unwrap_or(u32::MAX).to_be_bytes());
+            header.extend_from_slice(&[0, 0, 0, TCP_PROTOCOL]);
+        }
+    }
+    header
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{answer_port, parse_answer, syn_segment, tcp_segment, SynScanner};
+    use crate::scanner::checksum::checksum;
+    use crate::scanner::{PortState, Source};
+    use async_std::task::block_on;
+    use std::net::{IpAddr, SocketAddr};
+    use std::time::Duration;
+
+    #[test]
+    fn builds_syn_segment() {
+        let source: SocketAddr = "10.0.0.1:40000".parse().unwrap();
+        let target: SocketAddr = "10.0.0.2:80".parse().unwrap();
+        let segment = syn_segment(source, target, 1);
+
+        assert_eq!(segment.len(), 24);
+        assert_eq!(&segment[0..4], &[0x9c, 0x40, 0, 80]);
+        assert_eq!(segment[13], 0x02);
+        // A segment with its checksum filled in sums up to zero.
+        let pseudo = super::pseudo_header(source.ip(), target.ip(), segment.len());
+        assert_eq!(checksum(&[pseudo, segment].concat()), 0);
+    }
+
+    #[test]
+    fn parses_answers_to_our_port() {
+        let mut segment = [0u8; 20];
+        segment[0..2].copy_from_slice(&80u16.to_be_bytes());
+        segment[2..4].copy_from_slice(&40_000u16.to_be_bytes());
+        segment[8..12].copy_from_slice(&1_001u32.to_be_bytes());
+
+        assert_eq!(answer_port(&segment, 40_000), Some(80));
+        assert_eq!(answer_port(&segment, 40_001), None);
+        assert_eq!(answer_port(&segment[..19], 40_000), None);
+        segment[13] = 0x12;
+        assert_eq!(parse_answer(&segment, 1_000), Some(PortState::Open));
+        segment[13] = 0x14;
+        assert_eq!(parse_answer(&segment, 1_000), Some(PortState::Closed));
+        segment[13] = 0x10;
+        assert_eq!(parse_answer(&segment, 1_000), None);
+        // A RST that doesn't acknowledge anything.
+        segment[13] = 0x04;
+        assert_eq!(parse_answer(&segment, 1_000), None);
+    }
+
+    #[test]
+    fn ignores_answers_to_other_syns() {
+        let mut segment = [0u8; 20];
+        segment[8..12].copy_from_slice(&1_001u32.to_be_bytes());
+
+        segment[13] = 0x12;
+        assert_eq!(parse_answer(&segment, 1_001), None);
+        assert_eq!(parse_answer(&segment, 7), None);
+        segment[13] = 0x14;
+        assert_eq!(parse_answer(&segment, 1_001), None);
+        // The sequence number wraps around.
+        segment[8..12].copy_from_slice(&0u32.to_be_bytes());
+        assert_eq!(parse_answer(&segment, u32::MAX), Some(PortState::Closed));
+    }
+
+    #[test]
+    fn skips_ipv4_header() {
+        let mut packet = vec![
+            0x45, 0, 0, 0, 0, 0, 0, 0, 64, 6, 0, 0, 10, 0, 0, 2, 10, 0, 0, 1,
+        ];
+        packet.extend_from_slice(&[0, 80]);
+        let from: IpAddr = "10.0.0.2".parse().unwrap();
+
+        let (source, segment) = tcp_segment(&packet, from).unwrap();
+        assert_eq!(source, from);
+        assert_eq!(segment, &[0, 80]);
+    }
+
+    #[test]
+    #[ignore = "raw sockets need root or CAP_NET_RAW"]
+    fn syn_scans_loopback() {
+        let scanner = SynScanner::new(Source::default()).unwrap();
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let open = listener.local_addr().unwrap();
+        let closed = {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            listener.local_addr().unwrap()
+        };
+
+        let timeout = Duration::from_secs(1);
+        assert_eq!(
+            block_on(scanner.probe(open, timeout)).unwrap(),
+            Some(PortState::Open)
+        );
+        assert_eq!(
+            block_on(scanner.probe(closed, timeout)).unwrap(),
+            Some(PortState::Closed)
+        );
+    }
+}
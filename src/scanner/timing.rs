@@ -0,0 +1,145 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Mutex, PoisonError};
+use std::time::Duration;
+
+/// Lower bound used for per host timeouts unless told otherwise.
+pub const DEFAULT_MIN_RTT_TIMEOUT: Duration = Duration::from_millis(100);
+/// Upper bound used for per host timeouts unless told otherwise.
+pub const DEFAULT_MAX_RTT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Number of round trips we want to have seen on a host before trusting
+/// our own estimate over the configured timeout.
+const MIN_SAMPLES: u32 = 3;
+
+/// Smoothed round trip time of a host, computed the way TCP (RFC 6298) and
+/// nmap do it.
+#[derive(Debug, Clone, Copy)]
+struct RttEstimate {
+    srtt: Duration,
+    rttvar: Duration,
+    samples: u32,
+}
+
+impl RttEstimate {
+    fn new(rtt: Duration) -> Self {
+        Self {
+            srtt: rtt,
+            rttvar: rtt / 2,
+            samples: 1,
+        }
+    }
+
+    fn update(&mut self, rtt: Duration) {
+        let delta = rtt.abs_diff(self.srtt);
+        self.rttvar = (self.rttvar * 3 + delta) / 4;
+        self.srtt = (self.srtt * 7 + rtt) / 8;
+        self.samples = self.samples.saturating_add(1);
+    }
+
+    fn timeout(&self) -> Duration {
+        self.srtt + self.rttvar * 4
+    }
+}
+
+/// Keeps track of how fast every host answers and hands out a timeout
+/// tailored to it, so slow hosts get the time they need while fast ones
+/// don't keep us waiting.
+///
+/// Until a host has answered a few times the configured timeout is used
+/// as is. After that the timeout is the smoothed round trip time plus four
+/// times its variance, kept between `min` and `max`.
+#[derive(Debug)]
+pub struct HostTimeouts {
+    fallback: Duration,
+    min: Duration,
+    max: Duration,
+    estimates: Mutex<HashMap<IpAddr, RttEstimate>>,
+}
+
+impl HostTimeouts {
+    pub fn new(fallback: Duration, min: Duration, max: Duration) -> Self {
+        Self {
+            fallback,
+            min,
+            max: max.max(min),
+            estimates: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The time to wait for an answer from `ip`.
+    pub fn timeout(&self, ip: IpAddr) -> Duration {
+        let estimates = self
+            .estimates
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        match estimates.get(&ip) {
+            Some(estimate) if estimate.samples >= MIN_SAMPLES => {
+                estimate.timeout().clamp(self.min, self.max)
+            }
+            _ => self.fallback,
+        }
+    }
+
+    /// Records the round trip time of an answer from `ip`, be it an
+    /// accepted or a refused connection.
+    pub fn record(&self, ip: IpAddr, rtt: Duration) {
+        let mut estimates = self
+            .estimates
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        estimates
+            .entry(ip)
+            .and_modify(|estimate| estimate.update(rtt))
+            .or_insert_with(|| RttEstimate::new(rtt));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HostTimeouts;
+    use std::net::IpAddr;
+    use std::time::Duration;
+
+    fn timeouts() -> HostTimeouts {
+        HostTimeouts::new(
+            Duration::from_millis(1_500),
+            Duration::from_millis(100),
+            Duration::from_millis(2_000),
+        )
+    }
+
+    #[test]
+    fn falls_back_until_enough_samples() {
+        let timeouts = timeouts();
+        let ip: IpAddr = "127.0.0.1".parse().unwrap();
+
+        assert_eq!(timeouts.timeout(ip), Duration::from_millis(1_500));
+        timeouts.record(ip, Duration::from_millis(200));
+        timeouts.record(ip, Duration::from_millis(200));
+        assert_eq!(timeouts.timeout(ip), Duration::from_millis(1_500));
+
+        timeouts.record(ip, Duration::from_millis(200));
+        assert!(timeouts.timeout(ip) < Duration::from_millis(1_500));
+        assert!(timeouts.timeout(ip) > Duration::from_millis(200));
+    }
+
+    #[test]
+    fn timeouts_are_per_host_and_bounded() {
+        let timeouts = timeouts();
+        let lan: IpAddr = "192.168.0.1".parse().unwrap();
+        let vpn: IpAddr = "10.8.0.1".parse().unwrap();
+
+        for _ in 0..10 {
+            timeouts.record(lan, Duration::from_micros(300));
+            timeouts.record(vpn, Duration::from_secs(3));
+        }
+
+        assert_eq!(timeouts.timeout(lan), Duration::from_millis(100));
+        assert_eq!(timeouts.timeout(vpn), Duration::from_millis(2_000));
+        assert_eq!(
+            timeouts.timeout("8.8.8.8".parse().unwrap()),
+            Duration::from_millis(1_500)
+        );
+    }
+}
@@ -0,0 +1,559 @@
+use super::result::TlsInfo;
+use super::Source;
+use async_std::io::{self, ReadExt, WriteExt};
+use async_std::net::TcpStream;
+use futures_rustls::client::TlsStream;
+use futures_rustls::TlsConnector;
+use log::debug;
+use rand::Rng;
+use rustls::client::{ServerCertVerified, ServerCertVerifier};
+use rustls::{Certificate, CipherSuite, ClientConfig, ProtocolVersion, ServerName};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use x509_parser::prelude::{ASN1Time, FromDer, GeneralName, X509Certificate};
+
+/// The newest and oldest versions offered in the hellos sent when a full
+/// handshake fails, TLS 1.2 and TLS 1.0.
+const NEWEST_HELLO_VERSION: u16 = 0x0303;
+const OLDEST_HELLO_VERSION: u16 = 0x0301;
+/// The cipher suites offered in those hellos, the common ones of TLS 1.0 to
+/// 1.2 from the ECDHE GCM ones down to RC4.
+const HELLO_CIPHERS: [u16; 15] = [
+    0xc02f, 0xc030, 0xc02b, 0xc02c, 0x009c, 0x009d, 0xc013, 0xc014, 0xc009, 0xc00a, 0x002f, 0x0035,
+    0x000a, 0x0005, 0x0004,
+];
+/// How much of the answer to a hello is read looking for the certificate.
+const MAX_HELLO_BYTES: usize = 64 * 1024;
+
+/// Shakes hands with TLS services to see what they present.
+///
+/// Certificates are recorded, not judged. Expired, self-signed and
+/// mismatched certificates are exactly what an audit is looking for, so
+/// any certificate is accepted, the signatures of the handshake itself are
+/// still checked.
+///
+/// Full handshakes only go through with TLS 1.2 and 1.3 and their modern
+/// cipher suites. When one fails, hellos offering TLS 1.2 down to 1.0 and
+/// older cipher suites are sent instead, to find out which versions the
+/// service takes and read the certificate it sends in the clear before
+/// TLS 1.3. See `TlsInfo::handshake_error`.
+#[derive(Debug, Clone)]
+pub struct TlsProbe {
+    config: Arc<ClientConfig>,
+    wait: Duration,
+    source: Source,
+}
+
+impl TlsProbe {
+    /// Gives up on handshakes that take longer than `wait`.
+    pub fn new(wait: Duration) -> Self {
+        let config = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_custom_certificate_verifier(Arc::new(AcceptAnyCertificate))
+            .with_no_client_auth();
+        Self {
+            config: Arc::new(config),
+            wait,
+            source: Source::default(),
+        }
+    }
+
+    /// Connects from `source`.
+    #[must_use]
+    pub fn with_source(mut self, source: Source) -> Self {
+        self.source = source;
+        self
+    }
+
+    /// Opens a TLS connection to `socket`, giving up on connections that
+    /// take longer than `connect_timeout`. `hostname` is sent as SNI, there
+    /// is no SNI without one.
+    pub async fn connect(
+        &self,
+        socket: SocketAddr,
+        hostname: Option<&str>,
+        connect_timeout: Duration,
+    ) -> io::Result<TlsStream<TcpStream>> {
+        let server_name = match hostname.map(ServerName::try_from) {
+            Some(Ok(server_name)) => server_name,
+            _ => ServerName::IpAddress(socket.ip()),
+        };
+        let stream = self.source.connect(socket, connect_timeout).await?;
+        let connector = TlsConnector::from(Arc::clone(&self.config));
+        io::timeout(self.wait, connector.connect(server_name, stream)).await
+    }
+
+    /// Shakes hands with `socket` and describes the session along with the
+    /// certificate the service presented. Returns `None` when the service
+    /// doesn't speak TLS.
+    pub async fn probe(
+        &self,
+        socket: SocketAddr,
+        hostname: Option<&str>,
+        connect_timeout: Duration,
+    ) -> Option<TlsInfo> {
+        let stream = match self.connect(socket, hostname, connect_timeout).await {
+            Ok(stream) => stream,
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => {
+                debug!("TLS handshake with {} timed out", socket);
+                return None;
+            }
+            Err(e) => {
+                debug!("TLS handshake with {} failed: {}", socket, e);
+                return self
+                    .probe_hellos(socket, hostname, connect_timeout, &e)
+                    .await;
+            }
+        };
+        let (_, session) = stream.get_ref();
+        let info = describe(
+            session.protocol_version()?,
+            session.negotiated_cipher_suite()?.suite(),
+            session.peer_certificates()?.first()?,
+        );
+        if info.is_none() {
+            debug!("Could not parse the certificate of {}", socket);
+        }
+        info
+    }
+
+    /// Sends hellos offering older versions and cipher suites after the
+    /// full handshake failed with `error`, see `TlsProbe`. Returns `None`
+    /// when the service doesn't answer with a certificate.
+    async fn probe_hellos(
+        &self,
+        socket: SocketAddr,
+        hostname: Option<&str>,
+        connect_timeout: Duration,
+        error: &io::Error,
+    ) -> Option<TlsInfo> {
+        let mut info: Option<TlsInfo> = None;
+        let mut offered = NEWEST_HELLO_VERSION;
+        while offered >= OLDEST_HELLO_VERSION {
+            let Ok(Some(hello)) = self.hello(socket, hostname, connect_timeout, offered).await
+            else {
+                break;
+            };
+            if hello.version > offered || hello.version < OLDEST_HELLO_VERSION {
+                break;
+            }
+            match &mut info {
+                Some(info) => info.versions.push(version_name(hello.version.into())),
+                None => {
+                    let Some(certificate) = &hello.certificate else {
+                        debug!("{} answered our hello without a certificate", socket);
+                        return None;
+                    };
+                    let mut described = describe(
+                        hello.version.into(),
+                        hello.cipher.into(),
+                        &Certificate(certificate.clone()),
+                    )?;
+                    described.handshake_error = Some(error.to_string());
+                    described.versions = vec![described.version.clone()];
+                    info = Some(described);
+                }
+            }
+            offered = hello.version - 1;
+        }
+        info
+    }
+
+    /// Sends a hello offering `version` to `socket` and reads the answer.
+    async fn hello(
+        &self,
+        socket: SocketAddr,
+        hostname: Option<&str>,
+        connect_timeout: Duration,
+        version: u16,
+    ) -> io::Result<Option<ServerHello>> {
+        let mut stream = self.source.connect(socket, connect_timeout).await?;
+        io::timeout(self.wait, async {
+            stream.write_all(&client_hello(hostname, version)).await?;
+            read_server_hello(&mut stream).await
+        })
+        .await
+    }
+}
+
+/// Builds a TLS 1.0 to 1.2 hello offering `version` and `HELLO_CIPHERS`,
+/// with `hostname` as SNI.
+fn client_hello(hostname: Option<&str>, version: u16) -> Vec<u8> {
+    let mut extensions = Vec::new();
+    if let Some(hostname) = hostname.filter(|hostname| hostname.parse::<IpAddr>().is_err()) {
+        // A single name, of the host name kind.
+        let name = [&[0][..], &length_prefixed(2, hostname.as_bytes())].concat();
+        extension(&mut extensions, 0x0000, &length_prefixed(2, &name));
+    }
+    // x25519, secp256r1 and secp384r1.
+    extension(
+        &mut extensions,
+        0x000a,
+        &length_prefixed(2, &[0, 0x1d, 0, 0x17, 0, 0x18]),
+    );
+    extension(&mut extensions, 0x000b, &length_prefixed(1, &[0]));
+    if version >= 0x0303 {
+        // RSA, ECDSA and RSA-PSS with SHA-256 to SHA-512, and with SHA-1.
+        let algorithms = [
+            4, 1, 5, 1, 6, 1, 4, 3, 5, 3, 6, 3, 8, 4, 8, 5, 8, 6, 2, 1, 2, 3,
+        ];
+        extension(&mut extensions, 0x000d, &length_prefixed(2, &algorithms));
+    }
+
+    let mut body = version.to_be_bytes().to_vec();
+    body.extend_from_slice(&rand::thread_rng().gen::<[u8; 32]>());
+    // No session to resume.
+    body.push(0);
+    let ciphers: Vec<u8> = HELLO_CIPHERS
+        .iter()
+        .flat_map(|cipher| cipher.to_be_bytes())
+        .collect();
+    body.extend_from_slice(&length_prefixed(2, &ciphers));
+    // No compression.
+    body.extend_from_slice(&[1, 0]);
+    body.extend_from_slice(&length_prefixed(2, &extensions));
+
+    let handshake = [&[1][..], &length_prefixed(3, &body)].concat();
+    [&[22, 3, 1][..], &length_prefixed(2, &handshake)].concat()
+}
+
+fn extension(extensions: &mut Vec<u8>, kind: u16, data: &[u8]) {
+    extensions.extend_from_slice(&kind.to_be_bytes());
+    extensions.extend_from_slice(&length_prefixed(2, data));
+}
+
+/// `data` after its length written over `size` bytes.
+fn length_prefixed(size: usize, data: &[u8]) -> Vec<u8> {
+    let length = (data.len() as u32).to_be_bytes();
+    [&length[4 - size..], data].concat()
+}
+
+/// What a service answered a hello of ours with.
+#[derive(Debug, PartialEq, Eq)]
+struct ServerHello {
+    version: u16,
+    cipher: u16,
+    certificate: Option<Vec<u8>>,
+}
+
+/// Reads the records a service answered a hello with until its
+/// certificate, the end of its hello or anything else than a handshake
+/// record.
+async fn read_server_hello(stream: &mut TcpStream) -> io::Result<Option<ServerHello>> {
+    let mut messages = Vec::new();
+    loop {
+        let mut header = [0u8; 5];
+        stream.read_exact(&mut header).await?;
+        let mut record = vec![0u8; usize::from(u16::from_be_bytes([header[3], header[4]]))];
+        stream.read_exact(&mut record).await?;
+        // Anything but a handshake record, like an alert, ends the hello.
+        if header[0] != 22 {
+            return Ok(parse_server_hello(&messages).0);
+        }
+        messages.extend_from_slice(&record);
+        let (hello, done) = parse_server_hello(&messages);
+        if done || messages.len() > MAX_HELLO_BYTES {
+            return Ok(hello);
+        }
+    }
+}
+
+/// Goes through the handshake messages a service sent, returns its hello
+/// with the certificate it came with so far, and whether there is nothing
+/// more to wait for.
+fn parse_server_hello(mut messages: &[u8]) -> (Option<ServerHello>, bool) {
+    const SERVER_HELLO: u8 = 2;
+    const CERTIFICATE: u8 = 11;
+    const SERVER_HELLO_DONE: u8 = 14;
+
+    let mut hello: Option<ServerHello> = None;
+    while messages.len() >= 4 {
+        let length = u32::from_be_bytes([0, messages[1], messages[2], messages[3]]) as usize;
+        let Some(body) = messages.get(4..4 + length) else {
+            break;
+        };
+        match messages[0] {
+            SERVER_HELLO => {
+                let session_length = usize::from(*body.get(34).unwrap_or(&0));
+                let (Some(version), Some(cipher)) = (
+                    body.get(..2),
+                    body.get(35 + session_length..37 + session_length),
+                ) else {
+                    return (None, true);
+                };
+                hello = Some(ServerHello {
+                    version: u16::from_be_bytes([version[0], version[1]]),
+                    cipher: u16::from_be_bytes([cipher[0], cipher[1]]),
+                    certificate: None,
+                });
+            }
+            CERTIFICATE => {
+                let certificate = body.get(3..6).and_then(|length| {
+                    let length = u32::from_be_bytes([0, length[0], length[1], length[2]]);
+                    body.get(6..6 + length as usize)
+                });
+                if let (Some(hello), Some(certificate)) = (&mut hello, certificate) {
+                    hello.certificate = Some(certificate.to_vec());
+                }
+                return (hello, true);
+            }
+            SERVER_HELLO_DONE => return (hello, true),
+            _ => {}
+        }
+        messages = &messages[4 + length..];
+    }
+    (hello, false)
+}
+
+/// Accepts any certificate, see `TlsProbe`.
+struct AcceptAnyCertificate;
+
+impl ServerCertVerifier for AcceptAnyCertificate {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+}
+
+fn describe(
+    version: ProtocolVersion,
+    cipher: CipherSuite,
+    certificate: &Certificate,
+) -> Option<TlsInfo> {
+    let (_, certificate) = X509Certificate::from_der(&certificate.0).ok()?;
+    let sans = certificate
+        .subject_alternative_name()
+        .ok()
+        .flatten()
+        .map(|sans| {
+            sans.value
+                .general_names
+                .iter()
+                .filter_map(describe_name)
+                .collect()
+        })
+        .unwrap_or_default();
+
+    Some(TlsInfo {
+        version: version_name(version),
+        cipher: cipher
+            .as_str()
+            .map_or_else(|| format!("{cipher:?}"), ToOwned::to_owned),
+        subject: certificate.subject().to_string(),
+        issuer: certificate.issuer().to_string(),
+        sans,
+        not_before: rfc3339(&certificate.validity().not_before),
+        not_after: rfc3339(&certificate.validity().not_after),
+        handshake_error: None,
+        versions: Vec::new(),
+    })
+}
+
+/// Names `version` the way OpenSSL does, like `TLSv1.2`.
+fn version_name(version: ProtocolVersion) -> String {
+    match version {
+        ProtocolVersion::SSLv3 => "SSLv3".to_owned(),
+        ProtocolVersion::TLSv1_0 => "TLSv1.0".to_owned(),
+        ProtocolVersion::TLSv1_1 => "TLSv1.1".to_owned(),
+        ProtocolVersion::TLSv1_2 => "TLSv1.2".to_owned(),
+        ProtocolVersion::TLSv1_3 => "TLSv1.3".to_owned(),
+        other => format!("{other:?}"),
+    }
+}
+
+/// Writes a subject alternative name the way OpenSSL does, like
+/// `DNS:example.com`.
+fn describe_name(name: &GeneralName) -> Option<String> {
+    match name {
+        GeneralName::DNSName(name) => Some(format!("DNS:{name}")),
+        GeneralName::RFC822Name(email) => Some(format!("email:{email}")),
+        GeneralName::URI(uri) => Some(format!("URI:{uri}")),
+        GeneralName::IPAddress(bytes) => {
+            let ip = match bytes.len() {
+                4 => IpAddr::from(<[u8; 4]>::try_from(*bytes).ok()?),
+                16 => IpAddr::from(<[u8; 16]>::try_from(*bytes).ok()?),
+                _ => return None,
+            };
+            Some(format!("IP:{ip}"))
+        }
+        _ => None,
+    }
+}
+
+fn rfc3339(time: &ASN1Time) -> String {
+    let time = time.to_datetime();
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        time.year(),
+        u8::from(time.month()),
+        time.day(),
+        time.hour(),
+        time.minute(),
+        time.second()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_server_hello, ServerHello, TlsProbe};
+    use async_std::task::block_on;
+    use rustls::{Certificate, PrivateKey, ServerConfig, ServerConnection};
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::sync::{mpsc, Arc};
+    use std::time::Duration;
+
+    /// Completes a single TLS handshake with the test certificate and hands
+    /// back the SNI the client sent.
+    fn serve_tls(listener: TcpListener) -> mpsc::Receiver<Option<String>> {
+        let certificate = Certificate(include_bytes!("../../fixtures/tls/cert.der").to_vec());
+        let key = PrivateKey(include_bytes!("../../fixtures/tls/key.der").to_vec());
+        let config = ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(vec![certificate], key)
+            .unwrap();
+        let (sender, receiver) = mpsc::channel();
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut connection = ServerConnection::new(Arc::new(config)).unwrap();
+            while connection.is_handshaking() {
+                if connection.complete_io(&mut stream).is_err() {
+                    break;
+                }
+            }
+            let _ = sender.send(connection.server_name().map(ToOwned::to_owned));
+        });
+        receiver
+    }
+
+    #[test]
+    fn describes_the_certificate() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let sni = serve_tls(listener);
+
+        let info = block_on(TlsProbe::new(Duration::from_secs(2)).probe(
+            address,
+            Some("www.azula.test"),
+            Duration::from_secs(1),
+        ))
+        .expect("the handshake succeeds");
+
+        assert_eq!(info.version, "TLSv1.3");
+        assert!(info.cipher.starts_with("TLS13_"));
+        assert_eq!(info.subject, "CN=azula.test, O=Azula");
+        assert_eq!(info.issuer, info.subject);
+        assert_eq!(
+            info.sans,
+            ["DNS:azula.test", "DNS:www.azula.test", "IP:127.0.0.1"]
+        );
+        assert_eq!(info.not_before, "2025-01-01T00:00:00Z");
+        assert_eq!(info.not_after, "2125-01-01T00:00:00Z");
+        assert_eq!(sni.recv().unwrap().as_deref(), Some("www.azula.test"));
+    }
+
+    /// The handshake messages of a service answering a hello with
+    /// `version`, `TLS_RSA_WITH_AES_128_CBC_SHA` and the test certificate.
+    fn server_hello(version: u16) -> Vec<u8> {
+        let certificate = include_bytes!("../../fixtures/tls/cert.der");
+        let u24 = |length: usize| (length as u32).to_be_bytes()[1..].to_vec();
+        let mut hello = version.to_be_bytes().to_vec();
+        hello.extend_from_slice(&[0; 32]);
+        hello.extend_from_slice(&[0, 0x00, 0x2f, 0]);
+        [
+            &[2][..],
+            &u24(hello.len()),
+            &hello,
+            &[11],
+            &u24(certificate.len() + 6),
+            &u24(certificate.len() + 3),
+            &u24(certificate.len()),
+            certificate,
+            &[14, 0, 0, 0],
+        ]
+        .concat()
+    }
+
+    #[test]
+    fn parses_server_hellos() {
+        let messages = server_hello(0x0301);
+        let (hello, done) = parse_server_hello(&messages);
+
+        assert!(done);
+        assert_eq!(
+            hello,
+            Some(ServerHello {
+                version: 0x0301,
+                cipher: 0x002f,
+                certificate: Some(include_bytes!("../../fixtures/tls/cert.der").to_vec()),
+            })
+        );
+        let (hello, done) = parse_server_hello(&messages[..50]);
+        assert!(!done);
+        assert_eq!(hello.unwrap().certificate, None);
+        assert_eq!(parse_server_hello(&[]), (None, false));
+    }
+
+    #[test]
+    fn describes_services_stuck_on_older_versions() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        // Takes TLS 1.0 and 1.1 only, the full handshake and two hellos.
+        std::thread::spawn(move || {
+            for _ in 0..3 {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut header = [0u8; 5];
+                stream.read_exact(&mut header).unwrap();
+                let mut hello = vec![0u8; usize::from(u16::from_be_bytes([header[3], header[4]]))];
+                stream.read_exact(&mut hello).unwrap();
+                let offered = u16::from_be_bytes([hello[4], hello[5]]);
+                let messages = server_hello(offered.min(0x0302));
+                let mut record = vec![22, 3, 1];
+                record.extend_from_slice(&(messages.len() as u16).to_be_bytes());
+                record.extend_from_slice(&messages);
+                let _ = stream.write_all(&record);
+                let _ = stream.read(&mut [0u8; 64]);
+            }
+        });
+
+        let info = block_on(TlsProbe::new(Duration::from_secs(2)).probe(
+            address,
+            None,
+            Duration::from_secs(1),
+        ))
+        .expect("the hellos are answered");
+
+        assert_eq!(info.version, "TLSv1.1");
+        assert_eq!(info.cipher, "TLS_RSA_WITH_AES_128_CBC_SHA");
+        assert_eq!(info.subject, "CN=azula.test, O=Azula");
+        assert_eq!(info.versions, ["TLSv1.1", "TLSv1.0"]);
+        assert!(info.handshake_error.is_some());
+    }
+
+    #[test]
+    fn plain_services_have_no_tls() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let _ = stream.write_all(b"SSH-2.0-azula\r\n");
+        });
+
+        let info = block_on(TlsProbe::new(Duration::from_secs(1)).probe(
+            address,
+            None,
+            Duration::from_secs(1),
+        ));
+
+        assert_eq!(info, None);
+    }
+}
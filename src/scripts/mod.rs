@@ -1,6 +1,7 @@
 #![allow(clippy::module_name_repetitions)]
 
 use crate::input::ScriptsRequired;
+use crate::scanner::Protocol;
 use anyhow::{anyhow, Result};
 use log::debug;
 use serde_derive::{Deserialize, Serialize};
@@ -18,7 +19,7 @@ use std::os::unix::process::ExitStatusExt;
 static DEFAULT: &str = r#"tags = ["core_approved", "RustScan", "default"]
 developer = [ "RustScan", "https://github.com/RustScan" ]
 ports_separator = ","
-call_format = "nmap -vvv -p {{port}} -{{ipversion}} {{ip}}"
+call_format = "nmap -vvv -p {{port}} -{{ipversion}} {{ip}} {{scantype}}"
 "#;
 
 #[cfg(not(tarpaulin_include))]
@@ -104,6 +105,9 @@ pub struct Script {
 
     // The format how we want the script to run.
     call_format: Option<String>,
+
+    // Protocol the open ports were found over.
+    protocol: Protocol,
 }
 
 #[derive(Serialize)]
@@ -112,6 +116,8 @@ struct ExecPartsScript {
     ip: String,
     port: String,
     ipversion: String,
+    protocol: String,
+    scantype: String,
 }
 
 #[derive(Serialize)]
@@ -119,6 +125,8 @@ struct ExecParts {
     ip: String,
     port: String,
     ipversion: String,
+    protocol: String,
+    scantype: String,
 }
 
 impl Script {
@@ -139,9 +147,19 @@ impl Script {
             ports_separator,
             tags,
             call_format,
+            protocol: Protocol::Tcp,
         }
     }
 
+    /// Tells the script its ports were found open over `protocol`, TCP
+    /// being assumed otherwise. Call formats get it as `{{protocol}}`, and
+    /// as the nmap scan type flag in `{{scantype}}`, `-sU` for UDP.
+    #[must_use]
+    pub fn with_protocol(mut self, protocol: Protocol) -> Self {
+        self.protocol = protocol;
+        self
+    }
+
     // Some variables get changed before read, and compiler throws warning on warn(unused_assignments)
     #[allow(unused_assignments)]
     pub fn run(self) -> Result<String> {
@@ -167,6 +185,10 @@ impl Script {
         }
         let default_template: Template = Template::new(&final_call_format);
         let mut to_run = String::new();
+        let (protocol, scantype) = match self.protocol {
+            Protocol::Tcp => ("tcp", ""),
+            Protocol::Udp => ("udp", "-sU"),
+        };
 
         if final_call_format.contains("{{script}}") {
             let exec_parts_script: ExecPartsScript = ExecPartsScript {
@@ -177,6 +199,8 @@ impl Script {
                     IpAddr::V4(_) => String::from("4"),
                     IpAddr::V6(_) => String::from("6"),
                 },
+                protocol: protocol.to_owned(),
+                scantype: scantype.to_owned(),
             };
             to_run = default_template.fill_with_struct(&exec_parts_script)?;
         } else {
@@ -187,6 +211,8 @@ impl Script {
                     IpAddr::V4(_) => String::from("4"),
                     IpAddr::V6(_) => String::from("6"),
                 },
+                protocol: protocol.to_owned(),
+                scantype: scantype.to_owned(),
             };
             to_run = default_template.fill_with_struct(&exec_parts)?;
         }
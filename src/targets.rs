@@ -0,0 +1,340 @@
+//! The set of addresses a scan goes through.
+//!
+//! Targets are kept as sorted ranges of addresses rather than as every
+//! address they cover, so a `/8` or an IPv6 prefix costs as much memory as
+//! a single host. Overlapping and adjacent ranges are merged, which takes
+//! care of duplicates.
+
+use serde_derive::{Deserialize, Serialize};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// Every address from `start` to `end`, both included, of the same family.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(try_from = "UncheckedIpRange")]
+pub struct IpRange {
+    start: IpAddr,
+    end: IpAddr,
+}
+
+/// An `IpRange` as read from a file, which could have been edited by hand
+/// or cut short, checked by `IpRange::new` before it is used.
+#[derive(Deserialize)]
+struct UncheckedIpRange {
+    start: IpAddr,
+    end: IpAddr,
+}
+
+impl TryFrom<UncheckedIpRange> for IpRange {
+    type Error = String;
+
+    fn try_from(range: UncheckedIpRange) -> Result<Self, Self::Error> {
+        Self::new(range.start, range.end)
+            .ok_or_else(|| format!("invalid IP range {}-{}", range.start, range.end))
+    }
+}
+
+impl IpRange {
+    /// Returns `None` when `start` and `end` are not of the same family or
+    /// `start` comes after `end`.
+    pub fn new(start: IpAddr, end: IpAddr) -> Option<Self> {
+        (start.is_ipv4() == end.is_ipv4() && start <= end).then_some(Self { start, end })
+    }
+
+    pub fn start(&self) -> IpAddr {
+        self.start
+    }
+
+    pub fn end(&self) -> IpAddr {
+        self.end
+    }
+
+    /// How many addresses the range covers, `u128::MAX` for the whole IPv6
+    /// space which is one more than that.
+    pub fn len(&self) -> u128 {
+        (to_bits(self.end) - to_bits(self.start)).saturating_add(1)
+    }
+
+    /// Ranges always cover at least one address.
+    pub fn is_empty(&self) -> bool {
+        false
+    }
+}
+
+impl From<IpAddr> for IpRange {
+    fn from(ip: IpAddr) -> Self {
+        Self { start: ip, end: ip }
+    }
+}
+
+/// A deduplicated set of addresses, IPv4 ones first and in ascending order
+/// within each family, that can be gone through or indexed without ever
+/// being expanded.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(from = "Vec<IpRange>", into = "Vec<IpRange>")]
+pub struct TargetSet {
+    /// Disjoint and non adjacent ranges, in ascending order.
+    ranges: Vec<IpRange>,
+    /// How many addresses come before each range.
+    offsets: Vec<u128>,
+}
+
+impl TargetSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The merged ranges making up the set, in ascending order.
+    pub fn ranges(&self) -> &[IpRange] {
+        &self.ranges
+    }
+
+    /// How many addresses are in the set, saturating at `u128::MAX`.
+    pub fn len(&self) -> u128 {
+        match (self.offsets.last(), self.ranges.last()) {
+            (Some(offset), Some(range)) => offset.saturating_add(range.len()),
+            _ => 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let after = self.ranges.partition_point(|range| range.start <= ip);
+        after > 0 && ip <= self.ranges[after - 1].end
+    }
+
+    /// The address at `index` in the order `iter` goes through them.
+    pub fn get(&self, index: u128) -> Option<IpAddr> {
+        let after = self.offsets.partition_point(|&offset| offset <= index);
+        let range = self.ranges.get(after.checked_sub(1)?)?;
+        let position = index - self.offsets[after - 1];
+        (position < range.len()).then(|| from_bits(range.start, to_bits(range.start) + position))
+    }
+
+    /// The index of `ip` in the order `iter` goes through the addresses,
+    /// the reverse of `get`, saturating at `u128::MAX` like `len`.
+    pub fn index_of(&self, ip: IpAddr) -> Option<u128> {
+        let after = self.ranges.partition_point(|range| range.start <= ip);
+        let range = self.ranges.get(after.checked_sub(1)?)?;
+        (ip <= range.end)
+            .then(|| self.offsets[after - 1].saturating_add(to_bits(ip) - to_bits(range.start)))
+    }
+
+    /// Goes through every address of the set in ascending order.
+    pub fn iter(&self) -> Iter<'_> {
+        Iter {
+            ranges: self.ranges.iter(),
+            current: None,
+        }
+    }
+}
+
+impl From<IpAddr> for TargetSet {
+    fn from(ip: IpAddr) -> Self {
+        std::iter::once(IpRange::from(ip)).collect()
+    }
+}
+
+impl From<Vec<IpRange>> for TargetSet {
+    fn from(mut ranges: Vec<IpRange>) -> Self {
+        ranges.sort_unstable_by_key(|range| range.start);
+        let mut merged: Vec<IpRange> = Vec::with_capacity(ranges.len());
+        for range in ranges {
+            match merged.last_mut() {
+                // Same family and overlapping or right next to each other.
+                Some(last)
+                    if last.end.is_ipv4() == range.start.is_ipv4()
+                        && to_bits(range.start) <= to_bits(last.end).saturating_add(1) =>
+                {
+                    last.end = last.end.max(range.end);
+                }
+                _ => merged.push(range),
+            }
+        }
+        let mut offsets = Vec::with_capacity(merged.len());
+        let mut offset: u128 = 0;
+        for range in &merged {
+            offsets.push(offset);
+            offset = offset.saturating_add(range.len());
+        }
+        Self {
+            ranges: merged,
+            offsets,
+        }
+    }
+}
+
+impl From<TargetSet> for Vec<IpRange> {
+    fn from(targets: TargetSet) -> Self {
+        targets.ranges
+    }
+}
+
+impl FromIterator<IpRange> for TargetSet {
+    fn from_iter<I: IntoIterator<Item = IpRange>>(iter: I) -> Self {
+        iter.into_iter().collect::<Vec<_>>().into()
+    }
+}
+
+impl FromIterator<IpAddr> for TargetSet {
+    fn from_iter<I: IntoIterator<Item = IpAddr>>(iter: I) -> Self {
+        iter.into_iter().map(IpRange::from).collect()
+    }
+}
+
+impl<'t> IntoIterator for &'t TargetSet {
+    type Item = IpAddr;
+    type IntoIter = Iter<'t>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// Goes through the addresses of a `TargetSet`, see `TargetSet::iter`.
+#[derive(Debug, Clone)]
+pub struct Iter<'t> {
+    ranges: std::slice::Iter<'t, IpRange>,
+    /// The range being gone through and the next address of it.
+    current: Option<(IpRange, u128)>,
+}
+
+impl Iterator for Iter<'_> {
+    type Item = IpAddr;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (range, next) = match self.current {
+            Some(current) => current,
+            None => {
+                let range = *self.ranges.next()?;
+                (range, to_bits(range.start))
+            }
+        };
+        // Checked rather than incremented past the end, which could be the
+        // last address of the family.
+        self.current = (next < to_bits(range.end)).then_some((range, next + 1));
+        Some(from_bits(range.start, next))
+    }
+}
+
+fn to_bits(ip: IpAddr) -> u128 {
+    match ip {
+        IpAddr::V4(ip) => u32::from(ip).into(),
+        IpAddr::V6(ip) => ip.into(),
+    }
+}
+
+/// The address numbered `bits` in the family of `family`.
+fn from_bits(family: IpAddr, bits: u128) -> IpAddr {
+    match family {
+        #[allow(clippy::cast_possible_truncation)]
+        IpAddr::V4(_) => Ipv4Addr::from(bits as u32).into(),
+        IpAddr::V6(_) => Ipv6Addr::from(bits).into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{IpRange, TargetSet};
+    use std::net::IpAddr;
+
+    fn range(start: &str, end: &str) -> IpRange {
+        IpRange::new(start.parse().unwrap(), end.parse().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn merges_overlapping_and_adjacent_ranges() {
+        let targets: TargetSet = [
+            range("10.0.0.10", "10.0.0.20"),
+            range("::1", "::1"),
+            range("10.0.0.0", "10.0.0.9"),
+            range("10.0.0.15", "10.0.0.30"),
+            range("10.0.1.0", "10.0.1.0"),
+        ]
+        .into_iter()
+        .collect();
+
+        assert_eq!(
+            targets.ranges(),
+            [
+                range("10.0.0.0", "10.0.0.30"),
+                range("10.0.1.0", "10.0.1.0"),
+                range("::1", "::1")
+            ]
+        );
+        assert_eq!(targets.len(), 33);
+        assert!(targets.contains("10.0.0.17".parse().unwrap()));
+        assert!(!targets.contains("10.0.0.31".parse().unwrap()));
+        assert!(!targets.contains("::2".parse().unwrap()));
+    }
+
+    #[test]
+    fn indexes_addresses_in_iteration_order() {
+        let targets: TargetSet = [
+            range("192.168.0.254", "192.168.1.1"),
+            range("10.0.0.1", "10.0.0.2"),
+            range("fe80::ffff", "fe80::1:0"),
+        ]
+        .into_iter()
+        .collect();
+
+        let ips: Vec<IpAddr> = targets.iter().collect();
+        assert_eq!(ips.len() as u128, targets.len());
+        assert_eq!(ips[0], "10.0.0.1".parse::<IpAddr>().unwrap());
+        assert_eq!(ips[3], "192.168.0.255".parse::<IpAddr>().unwrap());
+        assert_eq!(ips[4], "192.168.1.0".parse::<IpAddr>().unwrap());
+        assert_eq!(ips[7], "fe80::1:0".parse::<IpAddr>().unwrap());
+        for (index, ip) in ips.iter().enumerate() {
+            assert_eq!(targets.get(index as u128), Some(*ip));
+            assert_eq!(targets.index_of(*ip), Some(index as u128));
+        }
+        assert_eq!(targets.get(ips.len() as u128), None);
+        assert_eq!(targets.index_of("10.0.0.3".parse().unwrap()), None);
+        assert_eq!(targets.index_of("::1".parse().unwrap()), None);
+    }
+
+    #[test]
+    fn refuses_to_read_back_invalid_ranges() {
+        let json = |start: &str, end: &str| format!(r#"{{"start":"{start}","end":"{end}"}}"#);
+
+        let valid: IpRange = serde_json::from_str(&json("10.0.0.1", "10.0.0.9")).unwrap();
+        assert_eq!(valid, range("10.0.0.1", "10.0.0.9"));
+        let backwards = serde_json::from_str::<IpRange>(&json("10.0.0.9", "10.0.0.1"));
+        assert!(backwards
+            .unwrap_err()
+            .to_string()
+            .contains("invalid IP range 10.0.0.9-10.0.0.1"));
+        assert!(serde_json::from_str::<IpRange>(&json("10.0.0.1", "::1")).is_err());
+        assert!(serde_json::from_str::<TargetSet>(&format!(
+            "[{},{}]",
+            json("10.0.0.1", "10.0.0.9"),
+            json("::2", "::1")
+        ))
+        .is_err());
+    }
+
+    #[test]
+    fn handles_whole_address_spaces() {
+        let targets: TargetSet = [
+            range("0.0.0.0", "255.255.255.255"),
+            range("::", "ffff:ffff:ffff:ffff:ffff:ffff:ffff:ffff"),
+        ]
+        .into_iter()
+        .collect();
+
+        assert_eq!(targets.len(), u128::MAX);
+        assert_eq!(
+            targets.get(u128::from(u32::MAX) + 2),
+            Some("::1".parse().unwrap())
+        );
+        assert_eq!(
+            targets.index_of("::1".parse().unwrap()),
+            Some(u128::from(u32::MAX) + 2)
+        );
+        let last = range("255.255.255.254", "255.255.255.255");
+        let ips: Vec<IpAddr> = TargetSet::from(vec![last]).iter().collect();
+        assert_eq!(ips, [last.start(), last.end()]);
+    }
+}
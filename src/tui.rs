@@ -19,6 +19,20 @@ macro_rules! warning {
     };
 }
 
+/// Prints an error the scan is aborted on to stderr, so that it neither
+/// ends up in a report written to stdout nor goes unseen with greppable
+/// output.
+#[macro_export]
+macro_rules! fatal {
+    ($name:expr, $accessible:expr) => {
+        if $accessible {
+            eprintln!("{}", $name);
+        } else {
+            eprintln!("{} {}", ansi_term::Colour::Red.bold().paint("[!]"), $name);
+        }
+    };
+}
+
 #[macro_export]
 macro_rules! detail {
     ($name:expr) => {